@@ -2,6 +2,8 @@
 #![no_std]
 #![no_main]
 
+use embedded_hal::delay::DelayNs;
+use hal::delay::Delay;
 use hal::gpio::Flex;
 use hal::println;
 use milkv_duo_hal as hal;
@@ -16,7 +18,7 @@ const BANNER: &str = r#"
 #[milkv_duo_riscv_rt::entry]
 fn main() -> ! {
     hal::sbi::put_string(BANNER);
-    hal::sbi::put_string(" - 256M\n");
+    hal::sbi::put_string("\n");
 
     hal::uart::Uart0::init();
 
@@ -26,17 +28,21 @@ fn main() -> ! {
 
     let p = hal::init();
 
-    let mut led = Flex::new(p.PIN_25);
+    println!("Board: {}", hal::board::NAME);
+    let mut led = Flex::new(hal::led!(p)).unwrap();
     led.set_as_output();
     led.set_high();
 
+    println!("CPU: {}", hal::clocks::cpu_frequency());
     println!("Hello world!!!!");
 
+    let mut delay = Delay::new();
+
     loop {
         println!("toggle!!!!");
         led.toggle();
 
-        riscv::asm::delay(28 * 10_000_000);
+        delay.delay_ms(500);
     }
 }
 
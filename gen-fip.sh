@@ -2,21 +2,47 @@
 
 set -ex
 
+# ./gen-fip.sh [duo|duo256m|duos], match the board-* feature of the firmware
+BOARD=${1:-duo256m}
+
 FSPL_PATH=./pre-built/fsbl
 
 # opensbi
 MONITOR_RUNADDR=0x0000000080000000
+
 # after uboot
-BLCP_2ND_RUNADDR=0x0000000087e00000
+case "${BOARD}" in
+    duo)
+        FSBL_BUILD="${FSPL_PATH}/build/milkv_duo"
+        BLCP_2ND_RUNADDR=0x0000000083f40000
+        ;;
+    duo256m)
+        FSBL_BUILD="${FSPL_PATH}/build/milkv_duo_256m"
+        BLCP_2ND_RUNADDR=0x0000000087e00000
+        ;;
+    duos)
+        FSBL_BUILD="${FSPL_PATH}/build/milkv_duos"
+        BLCP_2ND_RUNADDR=0x000000009fe00000
+        ;;
+    *)
+        echo "unknown board ${BOARD}, expected duo, duo256m or duos" >&2
+        exit 1
+        ;;
+esac
+
+if [ ! -f "${FSBL_BUILD}/bl2.bin" ]; then
+    echo "no prebuilt FSBL for ${BOARD}, build it into ${FSBL_BUILD}" >&2
+    exit 1
+fi
 
 python3 ./pre-built/fsbl/plat/sg200x/fiptool.py -v genfip \
         './fip.bin' \
         --MONITOR_RUNADDR="${MONITOR_RUNADDR}" \
         --BLCP_2ND_RUNADDR="${BLCP_2ND_RUNADDR}" \
-        --CHIP_CONF="${FSPL_PATH}/build/milkv_duo_256m/chip_conf.bin" \
+        --CHIP_CONF="${FSBL_BUILD}/chip_conf.bin" \
         --NOR_INFO='FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF' \
         --NAND_INFO='00000000'\
-        --BL2="${FSPL_PATH}/build/milkv_duo_256m/bl2.bin" \
+        --BL2="${FSBL_BUILD}/bl2.bin" \
         --BLCP_IMG_RUNADDR=0x05200200 \
         --BLCP_PARAM_LOADADDR=0 \
         --BLCP="${FSPL_PATH}/test/empty.bin" \
@@ -0,0 +1,329 @@
+//! Generates the pad tables from `svd/pads.csv` and `svd/pinmux.csv`
+//!
+//! - `pad_peripherals.rs`, `peripherals_with_pads!`, which appends a singleton
+//!   for every pad with a GPIO to a `peripherals!` list
+//! - `pad_pins.rs`, an `impl_pin!` for every pad with a GPIO, included by
+//!   `gpio.rs`
+//! - `pad_functions.rs`, the function list of every FMUX pad, included by
+//!   `gpio.rs`
+//! - `pin_traits.rs`, a `pin_trait_impl!` for every pad function the hal has a
+//!   driver for, included by `traits.rs`
+//! - `adc_pins.rs`, an `impl_adc_pin!` for every ADC pad and SARADC block,
+//!   included by `adc.rs`
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+struct Pad {
+    name: String,
+    pad: String,
+    fmux: u32,
+    ioblk: Option<(u32, u32)>,
+    gpio: Option<(u32, u32)>,
+}
+
+/// IOBLK_G1, G7, G10, G12 and RTC
+const IOBLK_GROUPS: [u32; 5] = [1, 7, 10, 12, 13];
+
+fn parse_u32(s: &str) -> u32 {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .unwrap_or_else(|_| panic!("pads.csv: bad number `{s}`"))
+}
+
+/// `A7` -> (0, 7), `PWR3` -> (4, 3)
+fn parse_gpio(s: &str) -> (u32, u32) {
+    let (port, bit) = if let Some(bit) = s.strip_prefix("PWR") {
+        (4, bit)
+    } else {
+        let port = match &s[..1] {
+            "A" => 0,
+            "B" => 1,
+            "C" => 2,
+            "D" => 3,
+            _ => panic!("pads.csv: bad GPIO `{s}`"),
+        };
+        (port, &s[1..])
+    };
+    (port, parse_u32(bit))
+}
+
+/// Split the non-comment lines after the header into trimmed fields
+fn records<'a>(csv: &'a str, file: &'static str, header: &'static str) -> impl Iterator<Item = Vec<&'a str>> {
+    let mut lines = csv
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    assert_eq!(lines.next(), Some(header), "{file}: unexpected columns");
+    let columns = header.split(',').count();
+
+    lines.map(move |line| {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        assert_eq!(fields.len(), columns, "{file}: `{line}` needs {columns} fields");
+        fields
+    })
+}
+
+fn parse_pads(csv: &str) -> Vec<Pad> {
+    records(csv, "pads.csv", "name,pad,fmux,ioblk_group,ioblk,gpio")
+        .map(|fields| {
+            let ioblk = match (fields[3], fields[4]) {
+                ("", "") => None,
+                (group, offset) => {
+                    let group = parse_u32(group);
+                    // the groups `gpio.rs` has a register block for
+                    assert!(
+                        IOBLK_GROUPS.contains(&group),
+                        "pads.csv: {} has unknown IOBLK group {group}",
+                        fields[1]
+                    );
+                    Some((group, parse_u32(offset)))
+                }
+            };
+            Pad {
+                name: fields[0].to_string(),
+                pad: fields[1].to_string(),
+                fmux: parse_u32(fields[2]),
+                ioblk,
+                gpio: (!fields[5].is_empty()).then(|| parse_gpio(fields[5])),
+            }
+        })
+        .collect()
+}
+
+/// Encoding of `sealed::Pin::pad_pin_io_num`, must match `impl_pin!`. `None`
+/// for pads without a GPIO, they have no pin
+fn pad_pin_io_num(pad: &Pad) -> Option<u32> {
+    // group 0 marks pads without IOBLK control
+    let (group, offset) = pad.ioblk.unwrap_or((0, 0));
+    let (port, bit) = pad.gpio?;
+    Some((pad.fmux / 4) << 24 | group << 16 | (offset / 4) << 8 | port << 5 | bit)
+}
+
+/// FUNC_SEL 0 ~ 7 of every pad, keyed by pad name
+fn parse_functions(csv: &str) -> HashMap<String, [Option<String>; 8]> {
+    records(csv, "pinmux.csv", "pad,f0,f1,f2,f3,f4,f5,f6,f7")
+        .map(|fields| {
+            let functions = core::array::from_fn(|i| {
+                let signal = fields[i + 1];
+                (!signal.is_empty()).then(|| signal.to_string())
+            });
+            (fields[0].to_string(), functions)
+        })
+        .collect()
+}
+
+/// Name of the GPIO function in the pinmux table, `A7` is `XGPIOA_7`, `PWR3` is `PWR_GPIO_3`
+fn gpio_signal((port, bit): (u32, u32)) -> String {
+    match port {
+        4 => format!("PWR_GPIO_{bit}"),
+        _ => format!("XGPIO{}_{bit}", (b'A' + port as u8) as char),
+    }
+}
+
+/// Pin trait and instance a signal binds to, `None` for signals without a
+/// driver. Traits of single instance peripherals take no instance.
+fn binding(signal: &str) -> Option<(String, Option<String>)> {
+    let single = match signal {
+        "EPHY_RTX" => Some("eth::RtxPin"),
+        "EPHY_TXP" => Some("eth::TxpPin"),
+        "EPHY_TXN" => Some("eth::TxnPin"),
+        "EPHY_RXP" => Some("eth::RxpPin"),
+        "EPHY_RXN" => Some("eth::RxnPin"),
+        "AUD_AINL_MIC" | "AUD_AINR_MIC" => Some("audio::MicPin"),
+        "AUD_AOUTL" | "AUD_AOUTR" => Some("audio::OutPin"),
+        "CAM_MCLK0" => Some("camera::MclkPin"),
+        "USB_VBUS_DET" => Some("usb::VbusDetPin"),
+        s if s.starts_with("MIPIRX") => Some("camera::MipiRxPin"),
+        _ => None,
+    };
+    if let Some(pin) = single {
+        return Some((format!("crate::{pin}"), None));
+    }
+
+    let (instance, line) = if let Some(channel) = signal.strip_prefix("PWM_") {
+        // 4 channels per controller, PWM_5 is channel 1 of PWM1
+        let channel: u32 = channel.parse().ok()?;
+        (format!("PWM{}", channel / 4), format!("Ch{}", channel % 4))
+    } else if let Some(line) = signal.strip_prefix("EMMC_") {
+        ("EMMC".to_string(), line.replace("DAT_", "D"))
+    } else if let Some(line) = signal.strip_prefix("SDIO0_") {
+        ("SDIO0".to_string(), line.replace("D_", "D"))
+    } else if let Some(line) = signal.strip_prefix("PWR_SD1_") {
+        ("SDIO1".to_string(), line.replace("D_", "D"))
+    } else {
+        let (instance, line) = signal.split_once('_')?;
+        (instance.to_string(), line.to_string())
+    };
+
+    let module = match instance.as_str() {
+        "UART0" | "UART1" | "UART2" | "UART3" => "uart",
+        "SPI0" | "SPI1" | "SPI2" | "SPI3" => "spi",
+        "PWM0" | "PWM1" | "PWM2" | "PWM3" => "pwm",
+        "EMMC" | "SDIO0" | "SDIO1" => "sdmmc",
+        _ => return None,
+    };
+    let pin = match (module, line.as_str()) {
+        ("uart", "TX") => "TxPin",
+        ("uart", "RX") => "RxPin",
+        ("uart", "RTS") => "RtsPin",
+        ("uart", "CTS") => "CtsPin",
+        ("spi", "SCK") => "SckPin",
+        ("spi", "SDO") => "MosiPin",
+        ("spi", "SDI") => "MisoPin",
+        ("pwm", ch) => return Some((format!("crate::pwm::{ch}Pin"), Some(instance))),
+        ("sdmmc", "CLK") => "ClkPin",
+        ("sdmmc", "CMD") => "CmdPin",
+        ("sdmmc", "CD") => "CdPin",
+        ("sdmmc", "D0") => "D0Pin",
+        ("sdmmc", "D1") => "D1Pin",
+        ("sdmmc", "D2") => "D2Pin",
+        ("sdmmc", "D3") => "D3Pin",
+        _ => return None,
+    };
+    Some((format!("crate::{module}::{pin}"), Some(instance)))
+}
+
+fn main() {
+    let manifest = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let read = |name: &str| {
+        let path = manifest.join("../svd").join(name);
+        println!("cargo:rerun-if-changed={}", path.display());
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("read svd/{name}: {e}"))
+    };
+
+    let pads = parse_pads(&read("pads.csv"));
+    let gpio_pads: Vec<&Pad> = pads.iter().filter(|pad| pad.gpio.is_some()).collect();
+    let functions = parse_functions(&read("pinmux.csv"));
+
+    let mut peripherals = String::new();
+    writeln!(peripherals, "// generated by build.rs from svd/pads.csv").unwrap();
+    writeln!(peripherals, "macro_rules! peripherals_with_pads {{").unwrap();
+    writeln!(peripherals, "    ($($other:tt)*) => {{").unwrap();
+    writeln!(peripherals, "        crate::peripherals! {{").unwrap();
+    writeln!(peripherals, "            $($other)*").unwrap();
+    for pad in &gpio_pads {
+        writeln!(peripherals, "            {} <= virtual,", pad.name).unwrap();
+    }
+    writeln!(peripherals, "        }}").unwrap();
+    writeln!(peripherals, "    }};").unwrap();
+    writeln!(peripherals, "}}").unwrap();
+
+    let mut pins = String::new();
+    writeln!(pins, "// generated by build.rs from svd/pads.csv").unwrap();
+    writeln!(pins, "// PIN, FMUX_PAD, IOBLK_GROUP, IOBLK_PIN, IO_PORT, IO_PIN").unwrap();
+    for pad in &gpio_pads {
+        let (group, offset) = pad.ioblk.unwrap_or((0, 0));
+        let (port, bit) = pad.gpio.unwrap();
+        writeln!(
+            pins,
+            "impl_pin!({}, {}, {}, {}, {}, {}); // PAD_{}, FMUX {:#05x}, IOBLK {:#05x}",
+            pad.name,
+            pad.fmux / 4,
+            group,
+            offset / 4,
+            port,
+            bit,
+            pad.pad,
+            pad.fmux,
+            offset
+        )
+        .unwrap();
+    }
+
+    let mut pads_by_fmux: Vec<&Pad> = pads.iter().collect();
+    pads_by_fmux.sort_by_key(|pad| pad.fmux);
+
+    let mut table = String::new();
+    writeln!(table, "// generated by build.rs from svd/pads.csv and svd/pinmux.csv").unwrap();
+    writeln!(table, "pub(crate) const PAD_COUNT: usize = {};", pads.len()).unwrap();
+    writeln!(table, "/// Every FMUX pad, sorted by FMUX index").unwrap();
+    writeln!(table, "pub(crate) static PADS: [PadInfo; PAD_COUNT] = [").unwrap();
+    let mut traits = String::new();
+    writeln!(traits, "// generated by build.rs from svd/pinmux.csv").unwrap();
+    for pad in pads_by_fmux {
+        let signals = functions
+            .get(&pad.pad)
+            .unwrap_or_else(|| panic!("pinmux.csv: no functions for {}", pad.pad));
+        let gpio_func = pad.gpio.map(|gpio| {
+            let gpio = gpio_signal(gpio);
+            signals
+                .iter()
+                .position(|signal| signal.as_deref() == Some(gpio.as_str()))
+                .unwrap_or_else(|| panic!("pinmux.csv: {} has no {gpio} function", pad.pad))
+        });
+
+        let names: Vec<String> = signals
+            .iter()
+            .map(|signal| match signal {
+                Some(signal) => format!("Some(\"{signal}\")"),
+                None => "None".to_string(),
+            })
+            .collect();
+        writeln!(table, "    PadInfo {{").unwrap();
+        writeln!(table, "        name: \"{}\",", pad.pad).unwrap();
+        writeln!(table, "        fmux: {},", pad.fmux / 4).unwrap();
+        writeln!(table, "        functions: [{}],", names.join(", ")).unwrap();
+        match gpio_func {
+            Some(func) => writeln!(table, "        gpio: Some(AltFunction::F{func}),").unwrap(),
+            None => writeln!(table, "        gpio: None,").unwrap(),
+        }
+        match pad_pin_io_num(pad) {
+            Some(num) => writeln!(table, "        pad_pin_io_num: Some({num:#010x}),").unwrap(),
+            None => writeln!(table, "        pad_pin_io_num: None,").unwrap(),
+        }
+        writeln!(table, "    }},").unwrap();
+
+        // no singleton to implement the traits on
+        if pad.gpio.is_none() {
+            continue;
+        }
+        // a signal offered twice on one pad keeps the lower function
+        let mut bound = Vec::new();
+        for (func, signal) in signals.iter().enumerate() {
+            let Some((pin, instance)) = signal.as_deref().and_then(binding) else {
+                continue;
+            };
+            if bound.contains(&(pin.clone(), instance.clone())) {
+                continue;
+            }
+            let prefix = instance
+                .as_ref()
+                .map_or(String::new(), |instance| format!("{instance}, "));
+            writeln!(
+                traits,
+                "pin_trait_impl!({pin}, {prefix}{}, F{func}); // {}",
+                pad.name,
+                signal.as_deref().unwrap()
+            )
+            .unwrap();
+            bound.push((pin, instance));
+        }
+    }
+    writeln!(table, "];").unwrap();
+
+    // the analog side of a pad has no FUNC_SEL, ADC pads are found by name
+    let mut adc_pins = String::new();
+    writeln!(adc_pins, "// generated by build.rs from svd/pads.csv").unwrap();
+    for pad in &gpio_pads {
+        let Some(channel) = pad.pad.strip_prefix("ADC") else {
+            continue;
+        };
+        // both blocks sample the same three pads
+        for instance in ["SARADC", "RTC_SARADC"] {
+            writeln!(adc_pins, "impl_adc_pin!({instance}, {}, Adc{channel});", pad.name).unwrap();
+        }
+    }
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(out.join("pad_peripherals.rs"), peripherals).unwrap();
+    fs::write(out.join("pad_pins.rs"), pins).unwrap();
+    fs::write(out.join("pad_functions.rs"), table).unwrap();
+    fs::write(out.join("pin_traits.rs"), traits).unwrap();
+    fs::write(out.join("adc_pins.rs"), adc_pins).unwrap();
+}
@@ -0,0 +1,273 @@
+//! SARADC, 12-bit successive approximation ADC
+//!
+//! There are 2 SARADC blocks, `SARADC` in the active domain and `RTC_SARADC`
+//! in the always-on(RTC) domain. Both have 3 channels, ADC1 ~ ADC3.
+//! ADC pads are in the 1V8 IO domain, so full scale is 1.8V.
+
+use core::marker::PhantomData;
+
+use crate::clocks::Gate;
+use crate::delay;
+use crate::gpio::Pull;
+use crate::mmio::regs;
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, pinmux, Peripheral};
+
+/// Full scale voltage in millivolts
+pub const VREF_MV: u32 = 1800;
+
+/// Maximum raw conversion result
+pub const MAX_VALUE: u16 = 0xfff;
+
+const CTRL_EN: u32 = 1 << 0;
+const STATUS_BUSY: u32 = 1 << 0;
+const RESULT_VALID: u32 = 1 << 15;
+const RESULT_MASK: u32 = 0xfff;
+const INTR_DONE: u32 = 1 << 0;
+
+/// Sample/hold and conversion timing, vendor default
+const DEFAULT_CYC_SET: u32 = 0x000f_1f0f;
+
+/// ADC channel, named after the pads ADC1 ~ ADC3
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Channel {
+    Adc1 = 1,
+    Adc2 = 2,
+    Adc3 = 3,
+}
+
+impl Channel {
+    #[inline]
+    fn index(self) -> usize {
+        self as usize - 1
+    }
+
+    #[inline]
+    fn sel(self) -> u32 {
+        1 << (self as u32 + 4)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Conversion finished without a valid result
+    Invalid,
+    /// Conversion didn't finish in time
+    Timeout,
+    /// The pad is held by another driver
+    Pinmux(pinmux::Conflict),
+}
+
+regs! {
+    pub struct Regs {
+        /// [0] enable(start), [7:5] channel select
+        ctrl: 0x04,
+        /// [0] busy
+        status: 0x08,
+        cyc_set: 0x0c,
+        /// [11:0] result, [15] valid
+        result[3]: 0x14 / 0x04,
+        intr_en: 0x20,
+        intr_clr: 0x24,
+        intr_sta: 0x28,
+    }
+}
+
+pub struct Adc<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> Adc<'d, T> {
+    pub fn new(_peri: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(_peri);
+        T::enable_and_reset();
+
+        let regs = T::regs();
+        regs.ctrl().write(0);
+        regs.cyc_set().write(DEFAULT_CYC_SET);
+        regs.intr_en().write(0);
+        regs.intr_clr().write(INTR_DONE);
+
+        Self { phantom: PhantomData }
+    }
+
+    /// Release a pad from digital functions, so it can be sampled.
+    ///
+    /// The pad is switched to GPIO input with pulls disabled.
+    pub fn configure_pin(&mut self, pin: impl Peripheral<P = impl AdcPin<T>> + 'd) -> Result<Channel, Error> {
+        into_ref!(pin);
+        pin.try_claim(pin.gpio_function(), T::NAME).map_err(Error::Pinmux)?;
+        pin.set_pull(Pull::None);
+        Ok(pin.channel())
+    }
+
+    /// Single conversion of one channel, in raw counts
+    pub fn blocking_read(&mut self, ch: Channel) -> Result<u16, Error> {
+        let regs = T::regs();
+        self.start(ch.sel());
+        self.wait_idle()?;
+        let result = regs.result(ch.index()).read();
+        regs.ctrl().write(0);
+        if result & RESULT_VALID == 0 {
+            return Err(Error::Invalid);
+        }
+        Ok((result & RESULT_MASK) as u16)
+    }
+
+    /// Single conversion of one channel, in millivolts
+    pub fn blocking_read_mv(&mut self, ch: Channel) -> Result<u32, Error> {
+        self.blocking_read(ch).map(to_mv)
+    }
+
+    /// One conversion round over `channels`, in raw counts indexed by
+    /// `Channel as usize - 1`.
+    ///
+    /// The SARADC has no free-running mode, every call starts a new round.
+    /// Channels not in `channels`, or without a valid result, are `None`.
+    pub fn blocking_read_all(&mut self, channels: &[Channel]) -> Result<[Option<u16>; 3], Error> {
+        let regs = T::regs();
+        let mask = channels.iter().fold(0, |acc, ch| acc | ch.sel());
+        self.start(mask);
+        self.wait_idle()?;
+        let mut values = [None; 3];
+        for ch in [Channel::Adc1, Channel::Adc2, Channel::Adc3] {
+            let result = regs.result(ch.index()).read();
+            if mask & ch.sel() != 0 && result & RESULT_VALID != 0 {
+                values[ch.index()] = Some((result & RESULT_MASK) as u16);
+            }
+        }
+        regs.ctrl().write(0);
+        Ok(values)
+    }
+
+    /// Same as [`Adc::blocking_read_all`], in millivolts
+    pub fn blocking_read_all_mv(&mut self, channels: &[Channel]) -> Result<[Option<u32>; 3], Error> {
+        self.blocking_read_all(channels)
+            .map(|values| values.map(|v| v.map(to_mv)))
+    }
+
+    pub fn enable_interrupt(&mut self) {
+        T::regs().intr_en().write(INTR_DONE);
+    }
+
+    pub fn disable_interrupt(&mut self) {
+        T::regs().intr_en().write(0);
+    }
+
+    pub fn clear_interrupt(&mut self) {
+        T::regs().intr_clr().write(INTR_DONE);
+    }
+
+    #[inline]
+    pub fn is_busy(&self) -> bool {
+        T::regs().status().is_set(STATUS_BUSY)
+    }
+
+    fn start(&mut self, mask: u32) {
+        let regs = T::regs();
+        regs.ctrl().write(0);
+        regs.intr_clr().write(INTR_DONE);
+        regs.ctrl().write(mask | CTRL_EN);
+    }
+
+    fn wait_idle(&self) -> Result<(), Error> {
+        // a 3 channel round is a few microseconds
+        delay::wait(|| !self.is_busy(), 1000, Error::Timeout)
+    }
+}
+
+impl<'d, T: Instance> Drop for Adc<'d, T> {
+    fn drop(&mut self) {
+        pinmux::release_owner(T::NAME);
+        T::disable();
+    }
+}
+
+/// Convert raw counts to millivolts
+#[inline]
+pub fn to_mv(raw: u16) -> u32 {
+    raw as u32 * VREF_MV / MAX_VALUE as u32
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Instance {
+        /// Owner of the pads in [`pinmux`]
+        const NAME: &'static str;
+
+        fn regs() -> Regs;
+
+        fn enable_and_reset();
+
+        fn disable();
+    }
+
+    pub trait AdcPin<T> {
+        fn channel(&self) -> Channel;
+    }
+}
+
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static + Send {}
+
+/// A pad wired to an ADC channel of `T`
+pub trait AdcPin<T: Instance>: crate::gpio::Pin + sealed::AdcPin<T> {}
+
+macro_rules! impl_adc {
+    ($inst:ident, $base:expr, $gate:ident, $rst:ident) => {
+        impl sealed::Instance for crate::peripherals::$inst {
+            const NAME: &'static str = stringify!($inst);
+
+            fn regs() -> Regs {
+                Regs::at($base)
+            }
+
+            fn enable_and_reset() {
+                rcc::enable_and_reset(&[Gate::$gate], Reset::$rst);
+            }
+
+            fn disable() {
+                rcc::disable(&[Gate::$gate], Reset::$rst);
+            }
+        }
+
+        impl Instance for peripherals::$inst {}
+    };
+    // RTC domain, no gate or reset in CLKGEN/RSTGEN
+    ($inst:ident, $base:expr) => {
+        impl sealed::Instance for crate::peripherals::$inst {
+            const NAME: &'static str = stringify!($inst);
+
+            fn regs() -> Regs {
+                Regs::at($base)
+            }
+
+            fn enable_and_reset() {}
+
+            fn disable() {
+                Self::regs().ctrl().write(0);
+            }
+        }
+
+        impl Instance for peripherals::$inst {}
+    };
+}
+
+macro_rules! impl_adc_pin {
+    ($inst:ident, $pin:ident, $ch:ident) => {
+        impl sealed::AdcPin<peripherals::$inst> for peripherals::$pin {
+            #[inline(always)]
+            fn channel(&self) -> Channel {
+                Channel::$ch
+            }
+        }
+
+        impl AdcPin<peripherals::$inst> for peripherals::$pin {}
+    };
+}
+
+impl_adc!(SARADC, 0x030f_0000, ApbSaradc, Saradc);
+impl_adc!(RTC_SARADC, 0x0502_f000);
+
+// ADC1 ~ ADC3 for both blocks, generated from svd/pads.csv
+include!(concat!(env!("OUT_DIR"), "/adc_pins.rs"));
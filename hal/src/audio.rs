@@ -0,0 +1,459 @@
+//! Audio, I2S streams to and from the internal codec
+//!
+//! The SG2002 has a stereo ADC and a stereo DAC on chip. The ADC feeds I2S0
+//! and the DAC is fed by I2S3, both I2S controllers run as masters in
+//! standard I2S format with 16-bit samples. The Duo brings out the left ADC
+//! input on `MIC_IN` and the left DAC output on `AUDIO_OUT`.
+//!
+//! Samples stream through [`crate::dma`] ring buffers, one 32-bit word per
+//! stereo [`Frame`]. The caller provides the ring, a multiple of [`PERIODS`]
+//! words, [`AudioIn::read`] and [`AudioOut::write`] hand over whole periods.
+//!
+//! Bit clocks derive from the 24.576MHz audio clock FSBL sets up from A0PLL,
+//! so only the 48kHz family of [`SampleRate`]s is available.
+//!
+//! This module doesn't drive the codec. Its register map isn't in the TRM, so
+//! the ADC and DAC are only taken out of reset and keep their reset
+//! configuration: no power sequencing, no microphone PGA gain and no DAC
+//! level. The only level control is [`AudioOut::set_gain`], which scales the
+//! samples in software before they are queued.
+
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+
+use crate::clocks::Gate;
+use crate::dma::{self, ReadableRing, TransferOptions, WritableRing};
+use crate::mmio::regs;
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, pinmux, Peripheral};
+
+/// Audio clock in front of the I2S MCLK dividers
+const AUDIO_CLOCK: u32 = 24_576_000;
+/// MCLK is 256 fs
+const MCLK_FS: u32 = 256;
+/// BCLK is 64 fs, 2 slots of 32 bits
+const BCLK_FS: u32 = 64;
+const SLOT_BITS: u32 = 32;
+const DATA_BITS: u32 = 16;
+
+/// Periods of a ring buffer, each at most [`dma::MAX_BLOCK_ITEMS`] frames
+pub const PERIODS: usize = 4;
+
+/// Largest [`AudioOut::set_gain`], samples pass unchanged
+pub const MAX_GAIN: u8 = 0x7f;
+
+const I2S0_BASE: usize = 0x0410_0000;
+const I2S3_BASE: usize = 0x0413_0000;
+
+// BLK_MODE_SETTING
+const BLK_MODE_TX: u32 = 1 << 0;
+const BLK_MODE_MASTER: u32 = 1 << 1;
+const BLK_MODE_DMA: u32 = 1 << 7;
+
+// FRAME_SETTING
+const FRAME_LEN_OFFSET: u32 = 0;
+const FRAME_FS_ACT_LEN_OFFSET: u32 = 16;
+
+// SLOT_SETTING1
+const SLOT_NUM_OFFSET: u32 = 0;
+const SLOT_SIZE_OFFSET: u32 = 8;
+const SLOT_DATA_SIZE_OFFSET: u32 = 16;
+/// Data starts one BCLK after the frame sync edge, standard I2S
+const SLOT_FB_OFFSET_OFFSET: u32 = 24;
+
+// DATA_FORMAT
+const DATA_FORMAT_WORD_16: u32 = 1 << 1;
+
+// FIFO_THRESHOLD
+const FIFO_RX_THRESHOLD_OFFSET: u32 = 0;
+const FIFO_TX_THRESHOLD_OFFSET: u32 = 16;
+const FIFO_THRESHOLD: u32 = 8;
+
+// FIFO_RESET
+const FIFO_RESET_RX: u32 = 1 << 0;
+const FIFO_RESET_TX: u32 = 1 << 16;
+
+// I2S_RESET
+const I2S_RESET_RX: u32 = 1 << 0;
+const I2S_RESET_TX: u32 = 1 << 1;
+
+// I2S_CLK_CTRL0
+const CLK_BCLK_OUT_EN: u32 = 1 << 6;
+const CLK_MCLK_OUT_EN: u32 = 1 << 7;
+const CLK_AUD_EN: u32 = 1 << 8;
+
+// I2S_CLK_CTRL1
+const CLK_MCLK_DIV_OFFSET: u32 = 0;
+const CLK_BCLK_DIV_OFFSET: u32 = 16;
+
+regs! {
+    /// I2S controller
+    pub struct I2sRegs {
+        /// [0] TX, [1] master, [7] DMA handshake
+        blk_mode_setting: 0x000,
+        /// [8:0] frame length - 1, [23:16] frame sync active length - 1
+        frame_setting: 0x004,
+        /// [3:0] slots - 1, [13:8] slot size - 1, [20:16] data size - 1, [28:24] first bit offset
+        slot_setting1: 0x008,
+        /// [15:0] slot enable
+        slot_setting2: 0x00c,
+        data_format: 0x010,
+        blk_cfg: 0x014,
+        i2s_enable: 0x018,
+        i2s_reset: 0x01c,
+        i2s_int_en: 0x020,
+        i2s_int: 0x024,
+        /// [4:0] RX, [20:16] TX request threshold
+        fifo_threshold: 0x028,
+        /// [0] RX, [16] TX
+        fifo_reset: 0x030,
+        rx_status: 0x040,
+        tx_status: 0x048,
+        /// [6] BCLK out, [7] MCLK out, [8] audio clock enable
+        clk_ctrl0: 0x060,
+        /// [15:0] MCLK divider, [31:16] BCLK divider
+        clk_ctrl1: 0x064,
+        rx_rd_port: 0x080,
+        tx_wr_port: 0x0c0,
+    }
+}
+
+/// Stereo sample, left then right
+pub type Frame = [i16; 2];
+
+#[inline]
+fn pack(frame: Frame) -> u32 {
+    frame[0] as u16 as u32 | ((frame[1] as u16 as u32) << 16)
+}
+
+#[inline]
+fn unpack(word: u32) -> Frame {
+    [word as u16 as i16, (word >> 16) as u16 as i16]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The ring buffer wasn't serviced in time, the stream stopped
+    Overrun,
+    Dma(dma::Error),
+    /// The MIC_IN or AUDIO_OUT pad is held by another driver
+    Pinmux(pinmux::Conflict),
+}
+
+impl From<dma::Error> for Error {
+    fn from(e: dma::Error) -> Self {
+        match e {
+            dma::Error::Overrun => Error::Overrun,
+            e => Error::Dma(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SampleRate {
+    Hz8000,
+    Hz16000,
+    Hz24000,
+    Hz32000,
+    #[default]
+    Hz48000,
+}
+
+impl SampleRate {
+    #[inline]
+    pub fn hz(self) -> u32 {
+        match self {
+            SampleRate::Hz8000 => 8_000,
+            SampleRate::Hz16000 => 16_000,
+            SampleRate::Hz24000 => 24_000,
+            SampleRate::Hz32000 => 32_000,
+            SampleRate::Hz48000 => 48_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Config {
+    pub sample_rate: SampleRate,
+}
+
+#[inline]
+fn i2s_regs(base: usize) -> I2sRegs {
+    I2sRegs::at(base)
+}
+
+/// Master mode, standard I2S, 2 slots of 16-bit data, DMA paced
+fn configure_i2s(regs: &I2sRegs, rate: SampleRate, tx: bool) {
+    let (mode, fifo_reset, i2s_reset) = if tx {
+        (BLK_MODE_TX, FIFO_RESET_TX, I2S_RESET_TX)
+    } else {
+        (0, FIFO_RESET_RX, I2S_RESET_RX)
+    };
+    regs.i2s_enable().write(0);
+    regs.i2s_reset().write(i2s_reset);
+    regs.i2s_reset().write(0);
+
+    regs.blk_mode_setting().write(mode | BLK_MODE_MASTER | BLK_MODE_DMA);
+    regs.frame_setting()
+        .write(((BCLK_FS - 1) << FRAME_LEN_OFFSET) | ((BCLK_FS / 2 - 1) << FRAME_FS_ACT_LEN_OFFSET));
+    regs.slot_setting1().write(
+        (1 << SLOT_NUM_OFFSET)
+            | ((SLOT_BITS - 1) << SLOT_SIZE_OFFSET)
+            | ((DATA_BITS - 1) << SLOT_DATA_SIZE_OFFSET)
+            | (1 << SLOT_FB_OFFSET_OFFSET),
+    );
+    regs.slot_setting2().write(0b11);
+    regs.data_format().write(DATA_FORMAT_WORD_16);
+    regs.fifo_threshold()
+        .write((FIFO_THRESHOLD << FIFO_RX_THRESHOLD_OFFSET) | (FIFO_THRESHOLD << FIFO_TX_THRESHOLD_OFFSET));
+
+    let mclk_div = AUDIO_CLOCK / (MCLK_FS * rate.hz());
+    let bclk_div = MCLK_FS / BCLK_FS;
+    regs.clk_ctrl1()
+        .write((mclk_div << CLK_MCLK_DIV_OFFSET) | (bclk_div << CLK_BCLK_DIV_OFFSET));
+    regs.clk_ctrl0().write(CLK_AUD_EN | CLK_MCLK_OUT_EN | CLK_BCLK_OUT_EN);
+
+    regs.fifo_reset().write(fifo_reset);
+    regs.fifo_reset().write(0);
+}
+
+/// Microphone capture through the internal ADC and I2S0
+pub struct AudioIn<'d, C: dma::Channel> {
+    /// Stopped in `drop` before the I2S controller, so its last burst completes
+    ring: ManuallyDrop<ReadableRing<'d, C, u32>>,
+    sample_rate: SampleRate,
+    phantom: PhantomData<&'d mut peripherals::AUDIO_ADC>,
+}
+
+impl<'d, C: dma::Channel> AudioIn<'d, C> {
+    /// Start capturing into `buf`, [`PERIODS`] periods of up to [`dma::MAX_BLOCK_ITEMS`] frames
+    pub fn new(
+        _peri: impl Peripheral<P = peripherals::AUDIO_ADC> + 'd,
+        mic: impl Peripheral<P = impl MicPin> + 'd,
+        channel: impl Peripheral<P = C> + 'd,
+        buf: &'d mut [u32],
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, mic);
+        // the analog input of the codec
+        mic.try_claim(mic.alt_function(), "AUDIO_ADC").map_err(Error::Pinmux)?;
+
+        rcc::enable_and_reset(&[Gate::ApbI2s0, Gate::SdmaAud0], Reset::I2s0);
+        rcc::reset(Reset::AudAdc);
+        let i2s = i2s_regs(I2S0_BASE);
+        configure_i2s(&i2s, config.sample_rate, false);
+
+        let port = i2s.rx_rd_port().addr() as *mut u32;
+        // SAFETY: RX_RD_PORT is paced by the I2S0 RX request, the ring stops before `buf` is released
+        let ring = unsafe {
+            ReadableRing::new(
+                channel,
+                dma::Request::I2s0Rx,
+                port,
+                buf,
+                PERIODS,
+                TransferOptions::default(),
+            )
+        };
+        let ring = match ring {
+            Ok(ring) => ring,
+            Err(e) => {
+                Self::shutdown();
+                return Err(e.into());
+            }
+        };
+        i2s.i2s_enable().write(1);
+
+        Ok(Self {
+            ring: ManuallyDrop::new(ring),
+            sample_rate: config.sample_rate,
+            phantom: PhantomData,
+        })
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// Frames that [`AudioIn::read`] can return right now
+    pub fn available(&self) -> Result<usize, Error> {
+        Ok(self.ring.len()?)
+    }
+
+    /// Move captured frames into `frames` without waiting, returns how many
+    pub fn read(&mut self, frames: &mut [Frame]) -> Result<usize, Error> {
+        let mut words = [0u32; 64];
+        let mut done = 0;
+        while done < frames.len() {
+            let want = (frames.len() - done).min(words.len());
+            let len = self.ring.read(&mut words[..want])?;
+            for (frame, &word) in frames[done..done + len].iter_mut().zip(&words[..len]) {
+                *frame = unpack(word);
+            }
+            done += len;
+            if len < want {
+                break;
+            }
+        }
+        Ok(done)
+    }
+
+    /// Fill `frames`, waiting for the capture to catch up
+    pub fn blocking_read(&mut self, frames: &mut [Frame]) -> Result<(), Error> {
+        let mut done = 0;
+        while done < frames.len() {
+            done += self.read(&mut frames[done..])?;
+        }
+        Ok(())
+    }
+
+    fn shutdown() {
+        pinmux::release_owner("AUDIO_ADC");
+        i2s_regs(I2S0_BASE).i2s_enable().write(0);
+        rcc::assert_reset(Reset::AudAdc);
+        rcc::disable(&[Gate::ApbI2s0, Gate::SdmaAud0], Reset::I2s0);
+    }
+}
+
+impl<C: dma::Channel> Drop for AudioIn<'_, C> {
+    fn drop(&mut self) {
+        // SAFETY: `ring` isn't used again
+        unsafe { ManuallyDrop::drop(&mut self.ring) };
+        Self::shutdown();
+    }
+}
+
+/// Playback through I2S3 and the internal DAC
+pub struct AudioOut<'d, C: dma::Channel> {
+    /// Stopped in `drop` before the I2S controller, so its last burst completes
+    ring: ManuallyDrop<WritableRing<'d, C, u32>>,
+    sample_rate: SampleRate,
+    gain: u8,
+    phantom: PhantomData<&'d mut peripherals::AUDIO_DAC>,
+}
+
+impl<'d, C: dma::Channel> AudioOut<'d, C> {
+    /// Start playing from `buf`, [`PERIODS`] periods of up to [`dma::MAX_BLOCK_ITEMS`] frames
+    ///
+    /// `buf` is cleared, so playback starts with one ring of silence.
+    pub fn new(
+        _peri: impl Peripheral<P = peripherals::AUDIO_DAC> + 'd,
+        out: impl Peripheral<P = impl OutPin> + 'd,
+        channel: impl Peripheral<P = C> + 'd,
+        buf: &'d mut [u32],
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, out);
+        // the analog output of the codec
+        out.try_claim(out.alt_function(), "AUDIO_DAC").map_err(Error::Pinmux)?;
+        buf.fill(0);
+
+        rcc::enable_and_reset(&[Gate::ApbI2s3, Gate::SdmaAud3], Reset::I2s3);
+        rcc::reset(Reset::AudDacApb);
+        rcc::reset(Reset::AudDac);
+        let i2s = i2s_regs(I2S3_BASE);
+        configure_i2s(&i2s, config.sample_rate, true);
+
+        let port = i2s.tx_wr_port().addr() as *mut u32;
+        // SAFETY: TX_WR_PORT is paced by the I2S3 TX request, the ring stops before `buf` is released
+        let ring = unsafe {
+            WritableRing::new(
+                channel,
+                dma::Request::I2s3Tx,
+                buf,
+                port,
+                PERIODS,
+                TransferOptions::default(),
+            )
+        };
+        let ring = match ring {
+            Ok(ring) => ring,
+            Err(e) => {
+                Self::shutdown();
+                return Err(e.into());
+            }
+        };
+        i2s.i2s_enable().write(1);
+
+        Ok(Self {
+            ring: ManuallyDrop::new(ring),
+            sample_rate: config.sample_rate,
+            gain: MAX_GAIN,
+            phantom: PhantomData,
+        })
+    }
+
+    #[inline]
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// Frames that [`AudioOut::write`] can take right now
+    pub fn free(&self) -> Result<usize, Error> {
+        Ok(self.ring.free()?)
+    }
+
+    /// Queue frames without waiting, returns how many were taken
+    pub fn write(&mut self, frames: &[Frame]) -> Result<usize, Error> {
+        let mut words = [0u32; 64];
+        let mut done = 0;
+        while done < frames.len() {
+            let want = (frames.len() - done).min(words.len());
+            for (word, &frame) in words.iter_mut().zip(&frames[done..done + want]) {
+                *word = pack(frame.map(|sample| self.scale(sample)));
+            }
+            let len = self.ring.write(&words[..want])?;
+            done += len;
+            if len < want {
+                break;
+            }
+        }
+        Ok(done)
+    }
+
+    /// Queue all of `frames`, waiting for room in the ring
+    pub fn blocking_write(&mut self, frames: &[Frame]) -> Result<(), Error> {
+        let mut done = 0;
+        while done < frames.len() {
+            done += self.write(&frames[done..])?;
+        }
+        Ok(())
+    }
+
+    /// Software gain, 0(silent) ~ [`MAX_GAIN`](unchanged), for frames written
+    /// from now on. The DAC output level itself isn't changed.
+    pub fn set_gain(&mut self, gain: u8) {
+        self.gain = gain.min(MAX_GAIN);
+    }
+
+    #[inline]
+    pub fn gain(&self) -> u8 {
+        self.gain
+    }
+
+    #[inline]
+    fn scale(&self, sample: i16) -> i16 {
+        (sample as i32 * self.gain as i32 / MAX_GAIN as i32) as i16
+    }
+
+    fn shutdown() {
+        pinmux::release_owner("AUDIO_DAC");
+        i2s_regs(I2S3_BASE).i2s_enable().write(0);
+        rcc::assert_reset(Reset::AudDac);
+        rcc::disable(&[Gate::ApbI2s3, Gate::SdmaAud3], Reset::I2s3);
+    }
+}
+
+impl<C: dma::Channel> Drop for AudioOut<'_, C> {
+    fn drop(&mut self) {
+        // SAFETY: `ring` isn't used again
+        unsafe { ManuallyDrop::drop(&mut self.ring) };
+        Self::shutdown();
+    }
+}
+
+// AUD_AINL_MIC, AUD_AINR_MIC
+pin_trait!(MicPin);
+// AUD_AOUTL, AUD_AOUTR
+pin_trait!(OutPin);
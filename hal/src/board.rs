@@ -0,0 +1,127 @@
+//! Board profiles
+//!
+//! One of the `board-duo`, `board-duo256m` or `board-duos` features picks the
+//! board the firmware is built for:
+//!
+//! | feature         | SoC     | package | DDR          | LED              |
+//! |-----------------|---------|---------|--------------|------------------|
+//! | `board-duo`     | CV1800B | QFN     | 64MiB DDR2   | `PIN_AUDIO_OUT`  |
+//! | `board-duo256m` | SG2002  | QFN     | 256MiB DDR3  | `PIN_25`         |
+//! | `board-duos`    | SG2000  | BGA     | 512MiB DDR3  | `PIN_1`          |
+//!
+//! The pad singletons are the same on every board. `PIN_<n>` is header pin
+//! GP`n` of the Duo and Duo 256M. The Duo S header is labelled with GPIO
+//! names, A28 is `XGPIOA[28]`, see the `gpio` column of `svd/pads.csv`.
+//!
+//! [`crate::init`] checks with [`verify`] that the chip matches the board and
+//! panics if it doesn't, [`crate::try_init`] returns the [`Mismatch`].
+//!
+//! The start of DRAM is `milkv_duo_riscv_rt::DRAM_BASE`. The runtime places
+//! the stack per board on its own, [`DDR_SIZE`] only describes the package.
+
+use crate::peripherals;
+use crate::signature::{self, ChipInfo};
+
+#[cfg(any(
+    all(feature = "board-duo", feature = "board-duo256m"),
+    all(feature = "board-duo", feature = "board-duos"),
+    all(feature = "board-duo256m", feature = "board-duos"),
+))]
+compile_error!("select only one of the `board-duo`, `board-duo256m` and `board-duos` features");
+
+/// Where the FSBL loads the firmware, after OpenSBI
+pub const FIRMWARE_BASE: usize = 0x8020_0000;
+
+#[cfg(feature = "board-duo")]
+mod profile {
+    use super::*;
+
+    pub const NAME: &str = "Milk-V Duo";
+    pub const DDR_SIZE: usize = 64 * 1024 * 1024;
+    /// `signature::ChipInfo::chip_id` of a CV1800B, QFN with 512Mbit DDR2
+    pub const CHIP_ID: u32 = 0x1810c;
+    /// XGPIOC[24]
+    pub type Led = peripherals::PIN_AUDIO_OUT;
+
+    #[macro_export]
+    macro_rules! led {
+        ($p:expr) => {
+            $p.PIN_AUDIO_OUT
+        };
+    }
+}
+
+#[cfg(feature = "board-duo256m")]
+mod profile {
+    use super::*;
+
+    pub const NAME: &str = "Milk-V Duo 256M";
+    pub const DDR_SIZE: usize = 256 * 1024 * 1024;
+    /// `signature::ChipInfo::chip_id` of a SG2002, QFN with 2Gbit DDR3
+    pub const CHIP_ID: u32 = 0x1812c;
+    /// PWR_GPIO[2]
+    pub type Led = peripherals::PIN_25;
+
+    #[macro_export]
+    macro_rules! led {
+        ($p:expr) => {
+            $p.PIN_25
+        };
+    }
+}
+
+#[cfg(feature = "board-duos")]
+mod profile {
+    use super::*;
+
+    pub const NAME: &str = "Milk-V Duo S";
+    pub const DDR_SIZE: usize = 512 * 1024 * 1024;
+    /// `signature::ChipInfo::chip_id` of a SG2000, BGA with 4Gbit DDR3
+    pub const CHIP_ID: u32 = 0x1813f;
+    /// XGPIOA[29]
+    pub type Led = peripherals::PIN_1;
+
+    #[macro_export]
+    macro_rules! led {
+        ($p:expr) => {
+            $p.PIN_1
+        };
+    }
+}
+
+/// Pad of the on-board LED, take it from the peripherals with [`led!`](crate::led)
+pub use profile::Led;
+/// Chip ID the board carries, see [`signature::read_chip_info`]
+pub use profile::CHIP_ID;
+/// Size of the DDR in the package, not used for the memory layout
+pub use profile::DDR_SIZE;
+/// Board name
+pub use profile::NAME;
+
+/// The detected chip doesn't match the board the firmware was built for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The package type in conf_info is not one the hal knows, `chip_id` is 0
+    Unidentified(ChipInfo),
+    /// Another chip than [`CHIP_ID`]
+    Chip(ChipInfo),
+}
+
+impl Mismatch {
+    /// What [`signature::read_chip_info`] returned
+    pub fn detected(&self) -> &ChipInfo {
+        match self {
+            Mismatch::Unidentified(info) | Mismatch::Chip(info) => info,
+        }
+    }
+}
+
+/// Read the chip info and check it against the board profile
+pub fn verify() -> Result<ChipInfo, Mismatch> {
+    let info = signature::read_chip_info();
+    match info.chip_id {
+        CHIP_ID => Ok(info),
+        0 => Err(Mismatch::Unidentified(info)),
+        _ => Err(Mismatch::Chip(info)),
+    }
+}
@@ -0,0 +1,127 @@
+//! C906 L1 cache maintenance, T-Head `dcache.*`/`icache.*` extension instructions
+//!
+//! DMA on the SG2002 is not coherent with the C906 L1 data cache. Buffers
+//! going to a device are cleaned first, buffers coming from one are invalidated.
+//! Both work on whole cache lines, keep DMA buffers line aligned.
+//!
+//! Code written to memory, loaded or patched, only runs after the data cache
+//! is cleaned and the instruction cache invalidated over it, see [`sync_icache`].
+//!
+//! OpenSBI enables both caches and the extension instructions (MXSTATUS.THEADISAEE)
+//! before jumping to us, the enable bits in MHCR are not reachable from S-mode.
+
+use core::arch::asm;
+
+/// L1 cache line size, data and instruction
+pub const LINE_SIZE: usize = 64;
+
+#[inline(always)]
+fn lines(addr: usize, len: usize) -> impl Iterator<Item = usize> {
+    let start = addr & !(LINE_SIZE - 1);
+    let end = if len == 0 { start } else { end(addr, len) };
+    (start..end).step_by(LINE_SIZE)
+}
+
+#[inline(always)]
+fn end(addr: usize, len: usize) -> usize {
+    addr.checked_add(len).expect("cache range wraps the address space")
+}
+
+/// Write dirty lines covering `addr..addr + len` back to memory
+pub fn clean(addr: usize, len: usize) {
+    for line in lines(addr, len) {
+        // th.dcache.cva a0
+        unsafe { asm!(".long 0x0255000b", in("a0") line) };
+    }
+    sync();
+}
+
+/// Drop lines covering `addr..addr + len`, so memory written by a device is seen.
+///
+/// Lines fully inside the range are dropped without write back. A line only
+/// partly inside it also holds data next to the range, so it is written back
+/// then dropped: if that line was dirtied while the device wrote to memory,
+/// the write back overwrites what the device put in the range. Either keep
+/// receive buffers line aligned, or [`flush`] them before starting the
+/// transfer and leave their edge lines alone until it completes.
+pub fn invalidate(addr: usize, len: usize) {
+    let end = end(addr, len);
+    for line in lines(addr, len) {
+        if line < addr || line + LINE_SIZE > end {
+            // th.dcache.civa a0
+            unsafe { asm!(".long 0x0275000b", in("a0") line) };
+        } else {
+            // th.dcache.iva a0
+            unsafe { asm!(".long 0x0265000b", in("a0") line) };
+        }
+    }
+    sync();
+}
+
+/// Write back then drop lines covering `addr..addr + len`
+pub fn flush(addr: usize, len: usize) {
+    for line in lines(addr, len) {
+        // th.dcache.civa a0
+        unsafe { asm!(".long 0x0275000b", in("a0") line) };
+    }
+    sync();
+}
+
+/// Write every dirty line of the data cache back to memory
+pub fn clean_all() {
+    // th.dcache.call
+    unsafe { asm!(".long 0x0010000b") };
+    sync();
+}
+
+/// Write back then drop the whole data cache
+pub fn flush_all() {
+    // th.dcache.ciall
+    unsafe { asm!(".long 0x0030000b") };
+    sync();
+}
+
+/// Drop the whole data cache without writing it back
+///
+/// # Safety
+///
+/// Every write still in the cache is lost, including the stack and statics.
+pub unsafe fn invalidate_all() {
+    // th.dcache.iall
+    asm!(".long 0x0020000b");
+    sync();
+}
+
+/// Drop instruction cache lines covering `addr..addr + len`
+pub fn invalidate_icache(addr: usize, len: usize) {
+    for line in lines(addr, len) {
+        // th.icache.iva a0
+        unsafe { asm!(".long 0x0305000b", in("a0") line) };
+    }
+    sync_i();
+}
+
+/// Drop the whole instruction cache
+pub fn invalidate_icache_all() {
+    // th.icache.iall
+    unsafe { asm!(".long 0x0100000b") };
+    sync_i();
+}
+
+/// Make code written to `addr..addr + len` visible to instruction fetch
+pub fn sync_icache(addr: usize, len: usize) {
+    clean(addr, len);
+    invalidate_icache(addr, len);
+}
+
+#[inline(always)]
+fn sync() {
+    // th.sync.s, wait for cache operations to complete
+    unsafe { asm!(".long 0x0190000b") };
+}
+
+#[inline(always)]
+fn sync_i() {
+    // th.sync.i, also flush the pipeline so no stale instruction is left
+    unsafe { asm!(".long 0x01a0000b") };
+}
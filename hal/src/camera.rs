@@ -0,0 +1,335 @@
+//! Camera, sensor control and raw frame layout
+//!
+//! This module does not capture frames. The MIPI RX PHY, the sensor MAC and
+//! the ISP CSI bridge with its write DMA, which receive frames into DRAM,
+//! have no register map in the TRM, so the hal doesn't program them. What it
+//! covers is the sensor side and the memory layout of a frame:
+//!
+//! - [`Csi`] holds the MIPI RX pads MIPIRX0 ~ MIPIRX2 the sensor's lanes come
+//!   in on, so no other driver takes them.
+//! - [`Camera`] owns SENSOR_RSTN as a GPIO and SENSOR_CLK as CAM_MCLK0 from
+//!   CAM0PLL, and ungates the VIP clocks and CSI MAC0 for a receive path set
+//!   up elsewhere.
+//! - Sensor registers are reached over I2C by the sensor driver, [`gc2083`]
+//!   for the GC2083 used on Milk-V boards. The hal has no I2C driver, bring
+//!   any `embedded_hal::i2c::I2c`.
+//! - [`Frame`] reads a raw dump in the bridge's memory layout: Bayer RAW with
+//!   one 16-bit word per pixel, or YUV 4:2:2 as YUYV, lines padded to
+//!   [`BUFFER_ALIGN`].
+//!
+//! ```ignore
+//! // `i2c` is the sensor bus, `table` the vendor's 1080p30 register table
+//! let csi = Csi::new(p.PIN_MIPI0_DN0, p.PIN_MIPI0_DP0, p.PIN_MIPI0_DN1, p.PIN_MIPI0_DP1, p.PIN_MIPI0_CKN, p.PIN_MIPI0_CKP)?;
+//! let mut camera = Camera::new(p.CAMERA, csi, p.PIN_SENSOR_RSTN, p.PIN_SENSOR_CLK, gc2083::MODE_1080P30)?;
+//! camera.reset_sensor();
+//! let mut sensor = Gc2083::new(i2c)?;
+//! sensor.init(table)?;
+//! sensor.set_streaming(true)?;
+//! ```
+
+use core::marker::PhantomData;
+
+use embedded_hal::delay::DelayNs;
+use fugit::HertzU32 as Hertz;
+
+use crate::clocks::{self, Gate};
+use crate::delay::Delay;
+use crate::gpio::{AnyPin, Flex};
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, pinmux, Peripheral, PeripheralRef};
+
+pub mod gc2083;
+
+/// Lines of a frame are padded to a cache line
+pub const BUFFER_ALIGN: usize = 64;
+
+/// VIP bus and source clocks, shared by every VIP block, then CSI MAC0
+const GATES: [Gate; 5] = [
+    Gate::AxiVip,
+    Gate::SrcVipSys0,
+    Gate::SrcVipSys1,
+    Gate::SrcVipSys2,
+    Gate::CsiMac0Vip,
+];
+
+/// Pixel data as the sensor sends it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Raw8,
+    Raw10,
+    Raw12,
+    /// YUV 4:2:2 8-bit, stored as Y0 U Y1 V
+    Yuv422,
+}
+
+impl PixelFormat {
+    /// CSI-2 data type
+    #[inline]
+    pub fn data_type(self) -> u8 {
+        match self {
+            PixelFormat::Raw8 => 0x2a,
+            PixelFormat::Raw10 => 0x2b,
+            PixelFormat::Raw12 => 0x2c,
+            PixelFormat::Yuv422 => 0x1e,
+        }
+    }
+
+    /// Bytes per pixel in memory
+    #[inline]
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Raw8 => 1,
+            _ => 2,
+        }
+    }
+
+    #[inline]
+    pub fn is_raw(self) -> bool {
+        self != PixelFormat::Yuv422
+    }
+}
+
+/// Color filter order of the top left 2x2 pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BayerPattern {
+    Rggb,
+    Grbg,
+    Gbrg,
+    Bggr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Blue,
+}
+
+impl BayerPattern {
+    /// Filter color of pixel (`x`, `y`)
+    pub fn color(self, x: usize, y: usize) -> Color {
+        let pattern = match self {
+            BayerPattern::Rggb => [Color::Red, Color::Green, Color::Green, Color::Blue],
+            BayerPattern::Grbg => [Color::Green, Color::Red, Color::Blue, Color::Green],
+            BayerPattern::Gbrg => [Color::Green, Color::Blue, Color::Red, Color::Green],
+            BayerPattern::Bggr => [Color::Blue, Color::Green, Color::Green, Color::Red],
+        };
+        pattern[(y & 1) * 2 + (x & 1)]
+    }
+}
+
+/// Sensor output mode, see [`gc2083::MODE_1080P30`]
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub width: u16,
+    pub height: u16,
+    pub format: PixelFormat,
+    /// Only used by [`Frame::color`] for RAW formats
+    pub bayer: BayerPattern,
+    /// Sensor master clock on SENSOR_CLK
+    pub mclk: Hertz,
+}
+
+impl Config {
+    /// Bytes per line in memory
+    #[inline]
+    pub fn stride(&self) -> usize {
+        (self.width as usize * self.format.bytes_per_pixel()).next_multiple_of(BUFFER_ALIGN)
+    }
+
+    /// Bytes of one frame in memory
+    #[inline]
+    pub fn frame_len(&self) -> usize {
+        self.stride() * self.height as usize
+    }
+}
+
+/// The MIPI RX pads of the CSI connector
+pub struct Csi<'d> {
+    phantom: PhantomData<&'d mut AnyPin>,
+}
+
+impl<'d> Csi<'d> {
+    pub fn new(
+        dn0: impl Peripheral<P = impl MipiRxPin> + 'd,
+        dp0: impl Peripheral<P = impl MipiRxPin> + 'd,
+        dn1: impl Peripheral<P = impl MipiRxPin> + 'd,
+        dp1: impl Peripheral<P = impl MipiRxPin> + 'd,
+        ckn: impl Peripheral<P = impl MipiRxPin> + 'd,
+        ckp: impl Peripheral<P = impl MipiRxPin> + 'd,
+    ) -> Result<Self, pinmux::Conflict> {
+        into_ref!(dn0, dp0, dn1, dp1, ckn, ckp);
+        // the MIPI RX PHY
+        pinmux::claim_all("CAMERA", || {
+            dn0.try_claim(dn0.alt_function(), "CAMERA")?;
+            dp0.try_claim(dp0.alt_function(), "CAMERA")?;
+            dn1.try_claim(dn1.alt_function(), "CAMERA")?;
+            dp1.try_claim(dp1.alt_function(), "CAMERA")?;
+            ckn.try_claim(ckn.alt_function(), "CAMERA")?;
+            ckp.try_claim(ckp.alt_function(), "CAMERA")
+        })?;
+        Ok(Self { phantom: PhantomData })
+    }
+}
+
+/// Gives up every pad of the camera, including the ones [`Camera`] added
+impl Drop for Csi<'_> {
+    fn drop(&mut self) {
+        pinmux::release_owner("CAMERA");
+    }
+}
+
+/// One raw frame, `height` lines of [`Config::stride`] bytes
+pub struct Frame<'a> {
+    data: &'a [u8],
+    config: Config,
+}
+
+impl<'a> Frame<'a> {
+    /// View `data` as a frame of `config`, `None` if it is shorter than
+    /// [`Config::frame_len`]
+    pub fn new(data: &'a [u8], config: Config) -> Option<Self> {
+        let data = data.get(..config.frame_len())?;
+        Some(Self { data, config })
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.config.width as usize
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.config.height as usize
+    }
+
+    #[inline]
+    pub fn format(&self) -> PixelFormat {
+        self.config.format
+    }
+
+    /// Bytes per line, lines are padded to [`BUFFER_ALIGN`]
+    #[inline]
+    pub fn stride(&self) -> usize {
+        self.config.stride()
+    }
+
+    /// The whole frame, `height` lines of `stride` bytes
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Pixels of line `y`, without padding. `None` below the last line
+    pub fn line(&self, y: usize) -> Option<&'a [u8]> {
+        if y >= self.height() {
+            return None;
+        }
+        let start = y * self.stride();
+        self.data
+            .get(start..start + self.width() * self.config.format.bytes_per_pixel())
+    }
+
+    /// RAW value of pixel (`x`, `y`), `None` for YUV frames or outside the frame
+    pub fn raw(&self, x: usize, y: usize) -> Option<u16> {
+        if x >= self.width() {
+            return None;
+        }
+        let line = self.line(y)?;
+        match self.config.format {
+            PixelFormat::Raw8 => Some(line[x] as u16),
+            PixelFormat::Raw10 | PixelFormat::Raw12 => Some(u16::from_le_bytes([line[2 * x], line[2 * x + 1]])),
+            PixelFormat::Yuv422 => None,
+        }
+    }
+
+    /// Filter color of pixel (`x`, `y`), `None` for YUV frames
+    pub fn color(&self, x: usize, y: usize) -> Option<Color> {
+        self.config.format.is_raw().then(|| self.config.bayer.color(x, y))
+    }
+
+    /// Y, U, V of pixel (`x`, `y`), U and V are shared by pixel pairs. `None`
+    /// for RAW frames or outside the frame
+    pub fn yuv(&self, x: usize, y: usize) -> Option<(u8, u8, u8)> {
+        if self.config.format != PixelFormat::Yuv422 || x >= self.width() {
+            return None;
+        }
+        let line = self.line(y)?;
+        let pair = line.get((x & !1) * 2..(x & !1) * 2 + 4)?;
+        Some((pair[(x & 1) * 2], pair[1], pair[3]))
+    }
+}
+
+pub struct Camera<'d> {
+    _csi: Csi<'d>,
+    reset: Flex<'d>,
+    _mclk: PeripheralRef<'d, AnyPin>,
+    config: Config,
+    phantom: PhantomData<&'d mut peripherals::CAMERA>,
+}
+
+impl<'d> Camera<'d> {
+    /// Start the sensor clock for `config`
+    ///
+    /// The sensor is left in reset, release it with [`Camera::reset_sensor`].
+    /// Fails if another driver holds SENSOR_RSTN or SENSOR_CLK, `csi` is
+    /// dropped then.
+    pub fn new(
+        _peri: impl Peripheral<P = peripherals::CAMERA> + 'd,
+        csi: Csi<'d>,
+        rstn: impl Peripheral<P = peripherals::PIN_SENSOR_RSTN> + 'd,
+        mclk: impl Peripheral<P = impl MclkPin> + 'd,
+        config: Config,
+    ) -> Result<Self, pinmux::Conflict> {
+        into_ref!(_peri, mclk);
+        let mut reset = Flex::new_for(rstn, "CAMERA")?;
+        // CAM_MCLK0
+        mclk.try_claim(mclk.alt_function(), "CAMERA")?;
+        reset.set_low();
+        reset.set_as_output();
+
+        rcc::enable_and_reset(&GATES, Reset::VipCam0);
+        clocks::set_cam0_mclk(config.mclk);
+
+        Ok(Self {
+            _csi: csi,
+            reset,
+            _mclk: mclk.map_into(),
+            config,
+            phantom: PhantomData,
+        })
+    }
+
+    #[inline]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Pulse SENSOR_RSTN, the sensor takes I2C commands on return
+    pub fn reset_sensor(&mut self) {
+        let mut delay = Delay::new();
+        self.reset.set_low();
+        delay.delay_ms(1);
+        self.reset.set_high();
+        delay.delay_ms(10);
+    }
+
+    /// Hold the sensor in reset, its lowest power state
+    pub fn hold_sensor_in_reset(&mut self) {
+        self.reset.set_low();
+    }
+}
+
+impl Drop for Camera<'_> {
+    fn drop(&mut self) {
+        clocks::disable_cam0_mclk();
+        // the VIP bus clocks stay on for the other VIP blocks
+        rcc::disable(&[Gate::CsiMac0Vip], Reset::VipCam0);
+        self.reset.set_low();
+    }
+}
+
+// MIPIRX0N ~ MIPIRX4P, lanes are mapped in the PHY
+pin_trait!(MipiRxPin);
+// CAM_MCLK0, from CAM0PLL
+pin_trait!(MclkPin);
@@ -0,0 +1,120 @@
+//! GalaxyCore GC2083, 1920x1080 RAW10 sensor
+//!
+//! Registers have 16-bit addresses and 8-bit values. The mode table, PLL and
+//! analog settings for a resolution and frame rate, comes from the sensor
+//! vendor and isn't bundled, [`Gc2083::init`] writes the one given to it.
+//! [`MODE_1080P30`] describes the output of the common 2-lane 1080p30 table.
+
+use embedded_hal::i2c::I2c;
+use fugit::HertzU32 as Hertz;
+
+use super::{BayerPattern, Config, PixelFormat};
+
+/// 7-bit I2C address
+pub const ADDRESS: u8 = 0x37;
+pub const CHIP_ID: u16 = 0x2083;
+
+const REG_CHIP_ID_H: u16 = 0x03f0;
+const REG_CHIP_ID_L: u16 = 0x03f1;
+/// Writing 0xf0 resets all registers
+const REG_RESET: u16 = 0x03fe;
+const RESET: u8 = 0xf0;
+const REG_STREAM: u16 = 0x023e;
+const STREAM_ON: u8 = 0x99;
+const STREAM_OFF: u8 = 0x00;
+/// [5:0] high, [7:0] low, in lines
+const REG_EXPOSURE_H: u16 = 0x0d03;
+const REG_EXPOSURE_L: u16 = 0x0d04;
+const EXPOSURE_MASK: u16 = 0x3fff;
+
+/// Output of the vendor 1080p30 table, 27MHz MCLK, 2 lanes at 594Mbit/s
+pub const MODE_1080P30: Config = Config {
+    width: 1920,
+    height: 1080,
+    format: PixelFormat::Raw10,
+    bayer: BayerPattern::Rggb,
+    mclk: Hertz::MHz(27),
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    I2c(E),
+    /// Something else answered, with the ID read
+    WrongChip(u16),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::I2c(e)
+    }
+}
+
+pub struct Gc2083<I: I2c> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> Gc2083<I> {
+    /// Talk to the sensor at [`ADDRESS`] and check its chip ID
+    pub fn new(i2c: I) -> Result<Self, Error<I::Error>> {
+        Self::with_address(i2c, ADDRESS)
+    }
+
+    pub fn with_address(i2c: I, address: u8) -> Result<Self, Error<I::Error>> {
+        let mut sensor = Self { i2c, address };
+        let id = sensor.chip_id()?;
+        if id != CHIP_ID {
+            return Err(Error::WrongChip(id));
+        }
+        Ok(sensor)
+    }
+
+    /// Give the I2C bus back
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    pub fn chip_id(&mut self) -> Result<u16, I::Error> {
+        let high = self.read(REG_CHIP_ID_H)?;
+        let low = self.read(REG_CHIP_ID_L)?;
+        Ok(u16::from_be_bytes([high, low]))
+    }
+
+    pub fn read(&mut self, reg: u16) -> Result<u8, I::Error> {
+        let mut value = [0];
+        self.i2c.write_read(self.address, &reg.to_be_bytes(), &mut value)?;
+        Ok(value[0])
+    }
+
+    pub fn write(&mut self, reg: u16, value: u8) -> Result<(), I::Error> {
+        let [high, low] = reg.to_be_bytes();
+        self.i2c.write(self.address, &[high, low, value])
+    }
+
+    /// Reset the registers and load a mode table of (register, value), streaming stays off
+    pub fn init(&mut self, table: &[(u16, u8)]) -> Result<(), Error<I::Error>> {
+        self.write(REG_RESET, RESET)?;
+        for &(reg, value) in table {
+            self.write(reg, value)?;
+        }
+        self.set_streaming(false)?;
+        Ok(())
+    }
+
+    pub fn set_streaming(&mut self, on: bool) -> Result<(), I::Error> {
+        self.write(REG_STREAM, if on { STREAM_ON } else { STREAM_OFF })
+    }
+
+    /// Exposure time in lines, up to the frame length of the mode
+    pub fn set_exposure(&mut self, lines: u16) -> Result<(), I::Error> {
+        let [high, low] = (lines & EXPOSURE_MASK).to_be_bytes();
+        self.write(REG_EXPOSURE_H, high)?;
+        self.write(REG_EXPOSURE_L, low)
+    }
+
+    pub fn exposure(&mut self) -> Result<u16, I::Error> {
+        let high = self.read(REG_EXPOSURE_H)?;
+        let low = self.read(REG_EXPOSURE_L)?;
+        Ok(u16::from_be_bytes([high, low]) & EXPOSURE_MASK)
+    }
+}
@@ -0,0 +1,481 @@
+//! CLKGEN, PLLs, dividers and clock gates
+//!
+//! FSBL leaves the clock tree in a working state, [`clocks`] reads it back so
+//! drivers can work without any setup. [`Clocks::freeze`] optionally changes
+//! peripheral dividers first, then records the result for every later driver.
+//!
+//! ```text
+//! XTAL 25MHz -+- MPLL, TPLL, FPLL (PLL group 6)
+//!             +- MIPIMPLL, A0PLL, DISPPLL, CAM0PLL, CAM1PLL (PLL group 2)
+//!             +- UART, timers, watchdog
+//! FPLL 1.5GHz --- dividers: PWM, SPI, I2C, SD0, SD1, EMMC, ETH
+//! TPLL/MPLL/FPLL --- C906 big core
+//! ```
+//!
+//! [`set_cpu_frequency`] reprograms TPLL for the C906 big core. Peripheral
+//! clocks don't derive from it, so only cycle-counting code like
+//! [`crate::delay::Delay`] has to follow, by reading [`clocks`] again.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use fugit::HertzU32 as Hertz;
+
+use crate::mmio::{regs, Reg};
+
+/// Crystal oscillator, the root of the clock tree
+pub const XTAL: u32 = 25_000_000;
+
+const DIV_RESET_DEASSERT: u32 = 1 << 0;
+const DIV_FACTOR_FROM_REG: u32 = 1 << 3;
+const DIV_FACTOR_OFFSET: u32 = 16;
+const DIV_FACTOR_WIDTH: u32 = 5;
+const DIV_SRC_OFFSET: u32 = 8;
+const DIV_SRC_WIDTH: u32 = 2;
+
+const MAX_DIV: u32 = (1 << DIV_FACTOR_WIDTH) - 1;
+
+const PLL_PRE_DIV_OFFSET: u32 = 0;
+const PLL_POST_DIV_OFFSET: u32 = 8;
+const PLL_DIV_OFFSET: u32 = 17;
+const PLL_DIV_WIDTH: u32 = 7;
+
+/// PLL output range
+const PLL_MIN: u32 = 600_000_000;
+const PLL_MAX: u32 = 1_200_000_000;
+
+/// Rated maximum of the C906 big core
+pub const CPU_MAX: u32 = 1_000_000_000;
+
+/// PLL_G6_STATUS, [2:0] update pending and [10:8] locked, for MPLL, TPLL, FPLL
+const TPLL_UPDATING: u32 = 1 << 1;
+const TPLL_LOCKED: u32 = 1 << 9;
+const PLL_LOCK_TIMEOUT: u32 = 100_000;
+
+/// CLK_BYP_0 bit, C906 big core runs from XTAL when set
+const BYP_C906_0: u32 = 1 << 6;
+/// CLK_SEL_0 bit, C906 big core from DIV_CLK_C906_0_1(FPLL) when set
+const SEL_C906_0: u32 = 1 << 23;
+
+regs! {
+    pub struct Regs {
+        clk_en[5]: 0x000 / 0x04,
+        clk_sel_0: 0x020,
+        clk_byp_0: 0x030,
+        clk_byp_1: 0x034,
+        div_clk_emmc: 0x064,
+        div_clk_sd0: 0x070,
+        div_clk_sd1: 0x07c,
+        div_clk_eth0_500m: 0x08c,
+        div_clk_spi: 0x0d4,
+        div_clk_i2c: 0x0d8,
+        div_clk_pwm_src_0: 0x120,
+        div_clk_c906_0_0: 0x130,
+        div_clk_c906_0_1: 0x134,
+        pll_g2_ctrl: 0x800,
+        pll_g2_status: 0x804,
+        mipimpll_csr: 0x808,
+        apll0_csr: 0x80c,
+        disppll_csr: 0x810,
+        cam0pll_csr: 0x814,
+        cam1pll_csr: 0x818,
+        /// CAM_MCLK0: [1] enable, [9:8] source, [21:16] divider
+        clk_cam0_src_div: 0x8c0,
+        pll_g6_ctrl: 0x900,
+        pll_g6_status: 0x904,
+        mpll_csr: 0x908,
+        tpll_csr: 0x90c,
+        fpll_csr: 0x910,
+    }
+}
+
+#[inline]
+pub(crate) fn regs() -> Regs {
+    Regs::at(0x0300_2000)
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Pll {
+    Mpll,
+    Tpll,
+    Fpll,
+    MipiMpll,
+    Apll0,
+    DispPll,
+    Cam0Pll,
+    Cam1Pll,
+}
+
+impl Pll {
+    pub(crate) fn csr(self) -> Reg {
+        let regs = regs();
+        match self {
+            Pll::Mpll => regs.mpll_csr(),
+            Pll::Tpll => regs.tpll_csr(),
+            Pll::Fpll => regs.fpll_csr(),
+            Pll::MipiMpll => regs.mipimpll_csr(),
+            Pll::Apll0 => regs.apll0_csr(),
+            Pll::DispPll => regs.disppll_csr(),
+            Pll::Cam0Pll => regs.cam0pll_csr(),
+            Pll::Cam1Pll => regs.cam1pll_csr(),
+        }
+    }
+
+    /// Output frequency, computed from the CSR as `XTAL * div_sel / pre_div_sel / post_div_sel`
+    ///
+    /// Spread spectrum synthesizers in front of group 2 PLLs are assumed to be bypassed.
+    pub fn frequency(self) -> Hertz {
+        let csr = self.csr();
+        let pre_div = csr.read_field(PLL_PRE_DIV_OFFSET, PLL_DIV_WIDTH).max(1);
+        let post_div = csr.read_field(PLL_POST_DIV_OFFSET, PLL_DIV_WIDTH).max(1);
+        let div = csr.read_field(PLL_DIV_OFFSET, PLL_DIV_WIDTH);
+        Hertz::from_raw((XTAL as u64 * div as u64 / pre_div as u64 / post_div as u64) as u32)
+    }
+}
+
+/// Clock gates in CLK_EN_0 ~ CLK_EN_4, as `register index << 5 | bit`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Gate {
+    Axi4Emmc = 15,
+    Emmc = 16,
+    Emmc100k = 17,
+    Axi4Sd0 = 18,
+    Sd0 = 19,
+    Sd0_100k = 20,
+    Axi4Sd1 = 21,
+    Sd1 = 22,
+    Sd1_100k = 23,
+    Efuse = 25,
+    ApbEfuse = 26,
+    Eth0_500m = 27,
+    Axi4Eth0 = 28,
+    SdmaAxi = 29,
+    Gpio = 30,
+    ApbGpio = 31,
+
+    GpioDb = 32 + 1,
+    SdmaAud0 = 32 + 3,
+    SdmaAud1 = 32 + 4,
+    SdmaAud2 = 32 + 5,
+    SdmaAud3 = 32 + 6,
+    ApbI2c = 32 + 7,
+    ApbWdt = 32 + 8,
+    ApbPwm = 32 + 9,
+    ApbSpi0 = 32 + 10,
+    ApbSpi1 = 32 + 11,
+    ApbSpi2 = 32 + 12,
+    ApbSpi3 = 32 + 13,
+    Uart0 = 32 + 14,
+    ApbUart0 = 32 + 15,
+    Uart1 = 32 + 16,
+    ApbUart1 = 32 + 17,
+    Uart2 = 32 + 18,
+    ApbUart2 = 32 + 19,
+    Uart3 = 32 + 20,
+    ApbUart3 = 32 + 21,
+    Uart4 = 32 + 22,
+    ApbUart4 = 32 + 23,
+    ApbI2s0 = 32 + 24,
+    ApbI2s1 = 32 + 25,
+    ApbI2s2 = 32 + 26,
+    ApbI2s3 = 32 + 27,
+    Axi4Usb = 32 + 28,
+    ApbUsb = 32 + 29,
+    Usb125m = 32 + 30,
+    Usb33k = 32 + 31,
+
+    Usb12m = 64,
+    AxiVip = 64 + 4,
+    SrcVipSys0 = 64 + 5,
+    SrcVipSys1 = 64 + 6,
+    CsiMac0Vip = 64 + 18,
+    Spi = 64 + 27,
+    I2c = 64 + 28,
+    Pwm = 64 + 29,
+
+    ApbI2c0 = 96 + 1,
+    ApbI2c1 = 96 + 2,
+    ApbI2c2 = 96 + 3,
+    ApbI2c3 = 96 + 4,
+    ApbI2c4 = 96 + 5,
+    ApbSaradc = 96 + 6,
+    ApbAudsrc = 96 + 7,
+    SrcVipSys2 = 96 + 29,
+
+    Timer0 = 128 + 9,
+    Timer1 = 128 + 10,
+    Timer2 = 128 + 11,
+    Timer3 = 128 + 12,
+    Timer4 = 128 + 13,
+    Timer5 = 128 + 14,
+    Timer6 = 128 + 15,
+    Timer7 = 128 + 16,
+}
+
+impl Gate {
+    #[inline]
+    fn reg(self) -> Reg {
+        regs().clk_en(self as usize >> 5)
+    }
+
+    #[inline]
+    fn mask(self) -> u32 {
+        1 << (self as u32 & 0x1f)
+    }
+}
+
+/// Ungate a clock
+pub fn enable(gate: Gate) {
+    critical_section::with(|_| gate.reg().set_bits(gate.mask()));
+}
+
+/// Gate a clock
+pub fn disable(gate: Gate) {
+    critical_section::with(|_| gate.reg().clear_bits(gate.mask()));
+}
+
+pub fn is_enabled(gate: Gate) -> bool {
+    gate.reg().is_set(gate.mask())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Frequency can't be generated, is above [`CPU_MAX`], or needs a divider
+    /// factor above 31
+    InvalidFrequency,
+    /// TPLL didn't lock at the new setting. The old setting is restored, the
+    /// core is left on XTAL only if that doesn't lock either, [`clocks`]
+    /// reports where it ended up.
+    PllLockTimeout,
+}
+
+/// Requested peripheral clocks, `None` keeps what FSBL configured
+///
+/// Each divider takes its input from FPLL, the closest frequency not above
+/// the request is used. Requests below FPLL / 31 can't be met.
+#[derive(Default)]
+pub struct Config {
+    pub pwm: Option<Hertz>,
+    pub spi: Option<Hertz>,
+    pub i2c: Option<Hertz>,
+    pub sd0: Option<Hertz>,
+    pub sd1: Option<Hertz>,
+    pub emmc: Option<Hertz>,
+}
+
+/// Frozen clock frequencies
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Clocks {
+    pub xtal: Hertz,
+    pub mpll: Hertz,
+    pub tpll: Hertz,
+    pub fpll: Hertz,
+    /// C906 big core
+    pub cpu: Hertz,
+    pub uart: Hertz,
+    pub pwm: Hertz,
+    pub spi: Hertz,
+    pub i2c: Hertz,
+    pub sd0: Hertz,
+    pub sd1: Hertz,
+    pub emmc: Hertz,
+    pub eth: Hertz,
+    /// Timers and watchdog, XTAL, see [`crate::timer::TIMER_CLOCK`]
+    pub timer: Hertz,
+}
+
+static CLOCKS: Mutex<Cell<Option<Clocks>>> = Mutex::new(Cell::new(None));
+
+impl Clocks {
+    /// Apply `config` and record the resulting frequencies for drivers.
+    ///
+    /// Dividers are changed in order, on error the ones before the failing
+    /// request keep their new setting and nothing is recorded.
+    pub fn freeze(config: Config) -> Result<Clocks, Error> {
+        let regs = regs();
+        let fpll = Pll::Fpll.frequency();
+        for (div, target) in [
+            (regs.div_clk_pwm_src_0(), config.pwm),
+            (regs.div_clk_spi(), config.spi),
+            (regs.div_clk_i2c(), config.i2c),
+            (regs.div_clk_sd0(), config.sd0),
+            (regs.div_clk_sd1(), config.sd1),
+            (regs.div_clk_emmc(), config.emmc),
+        ] {
+            if let Some(target) = target {
+                set_divider(div, fpll.raw().div_ceil(target.raw().max(1)))?;
+            }
+        }
+
+        let clocks = Clocks::read();
+        critical_section::with(|cs| CLOCKS.borrow(cs).set(Some(clocks)));
+        Ok(clocks)
+    }
+
+    /// Read the current clock tree from hardware
+    pub fn read() -> Clocks {
+        let regs = regs();
+        let fpll = Pll::Fpll.frequency();
+        let divided = |div: Reg| Hertz::from_raw(fpll.raw() / divider(div));
+
+        Clocks {
+            xtal: Hertz::from_raw(XTAL),
+            mpll: Pll::Mpll.frequency(),
+            tpll: Pll::Tpll.frequency(),
+            fpll,
+            cpu: cpu_frequency(),
+            uart: Hertz::from_raw(XTAL),
+            pwm: divided(regs.div_clk_pwm_src_0()),
+            spi: divided(regs.div_clk_spi()),
+            i2c: divided(regs.div_clk_i2c()),
+            sd0: divided(regs.div_clk_sd0()),
+            sd1: divided(regs.div_clk_sd1()),
+            emmc: divided(regs.div_clk_emmc()),
+            eth: divided(regs.div_clk_eth0_500m()),
+            timer: Hertz::from_raw(crate::timer::TIMER_CLOCK),
+        }
+    }
+}
+
+/// Frozen clocks, or the current clock tree if [`Clocks::freeze`] was never called
+pub fn clocks() -> Clocks {
+    critical_section::with(|cs| CLOCKS.borrow(cs).get()).unwrap_or_else(Clocks::read)
+}
+
+fn divider(div: Reg) -> u32 {
+    div.read_field(DIV_FACTOR_OFFSET, DIV_FACTOR_WIDTH).max(1)
+}
+
+/// Divide by `factor`, 1 ~ 31
+fn set_divider(div: Reg, factor: u32) -> Result<(), Error> {
+    if !(1..=MAX_DIV).contains(&factor) {
+        return Err(Error::InvalidFrequency);
+    }
+    div.write_field(DIV_FACTOR_OFFSET, DIV_FACTOR_WIDTH, factor);
+    div.set_bits(DIV_FACTOR_FROM_REG | DIV_RESET_DEASSERT);
+    Ok(())
+}
+
+// CLK_CAM0_SRC_DIV
+const CAM_CLK_EN: u32 = 1 << 1;
+const CAM_SRC_OFFSET: u32 = 8;
+const CAM_SRC_WIDTH: u32 = 2;
+/// Source 0, CAM0PLL
+const CAM_SRC_CAM0PLL: u32 = 0;
+const CAM_DIV_OFFSET: u32 = 16;
+const CAM_DIV_WIDTH: u32 = 6;
+
+/// Run the sensor master clock CAM_MCLK0 from CAM0PLL, the closest frequency
+/// not above `freq`. Returns the frequency set.
+pub fn set_cam0_mclk(freq: Hertz) -> Hertz {
+    let reg = regs().clk_cam0_src_div();
+    let pll = Pll::Cam0Pll.frequency().raw();
+    let div = pll.div_ceil(freq.raw().max(1)).clamp(1, (1 << CAM_DIV_WIDTH) - 1);
+    critical_section::with(|_| {
+        reg.clear_bits(CAM_CLK_EN);
+        reg.write_field(CAM_SRC_OFFSET, CAM_SRC_WIDTH, CAM_SRC_CAM0PLL);
+        reg.write_field(CAM_DIV_OFFSET, CAM_DIV_WIDTH, div);
+        reg.set_bits(CAM_CLK_EN);
+    });
+    Hertz::from_raw(pll / div)
+}
+
+/// Stop CAM_MCLK0
+pub fn disable_cam0_mclk() {
+    critical_section::with(|_| regs().clk_cam0_src_div().clear_bits(CAM_CLK_EN));
+}
+
+/// Current C906 big core frequency, read from hardware
+pub fn cpu_frequency() -> Hertz {
+    let regs = regs();
+    if regs.clk_byp_0().is_set(BYP_C906_0) {
+        return Hertz::from_raw(XTAL);
+    }
+    if regs.clk_sel_0().is_set(SEL_C906_0) {
+        let div = regs.div_clk_c906_0_1();
+        return Hertz::from_raw(Pll::Fpll.frequency().raw() / divider(div));
+    }
+    let div = regs.div_clk_c906_0_0();
+    let src = match div.read_field(DIV_SRC_OFFSET, DIV_SRC_WIDTH) {
+        0 => Pll::Tpll,
+        1 => Pll::Apll0,
+        2 => Pll::MipiMpll,
+        _ => Pll::Mpll,
+    };
+    Hertz::from_raw(src.frequency().raw() / divider(div))
+}
+
+/// Run the C906 big core at `freq`, or the closest frequency below it.
+///
+/// The core runs from XTAL while TPLL relocks, then from TPLL through
+/// DIV_CLK_C906_0_0. Frozen [`Clocks`] are updated, also on error, the new
+/// frequency is returned.
+pub fn set_cpu_frequency(freq: Hertz) -> Result<Hertz, Error> {
+    let (div_sel, factor) = cpu_pll_setting(freq.raw()).ok_or(Error::InvalidFrequency)?;
+
+    critical_section::with(|cs| {
+        let regs = regs();
+        let csr = Pll::Tpll.csr();
+        let previous = csr.read();
+        regs.clk_byp_0().set_bits(BYP_C906_0);
+
+        csr.write_field(PLL_PRE_DIV_OFFSET, PLL_DIV_WIDTH, 1);
+        csr.write_field(PLL_POST_DIV_OFFSET, PLL_DIV_WIDTH, 1);
+        csr.write_field(PLL_DIV_OFFSET, PLL_DIV_WIDTH, div_sel);
+        let result = wait_tpll_lock().and_then(|()| {
+            let div = regs.div_clk_c906_0_0();
+            set_divider(div, factor)?;
+            // source 0, TPLL
+            div.write_field(DIV_SRC_OFFSET, DIV_SRC_WIDTH, 0);
+            regs.clk_sel_0().clear_bits(SEL_C906_0);
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => regs.clk_byp_0().clear_bits(BYP_C906_0),
+            Err(_) => {
+                // back to the old TPLL setting, stay on XTAL if that doesn't lock either
+                csr.write(previous);
+                if wait_tpll_lock().is_ok() {
+                    regs.clk_byp_0().clear_bits(BYP_C906_0);
+                }
+            }
+        }
+
+        // frozen clocks follow whatever the core ended up running from
+        let cpu = cpu_frequency();
+        let frozen = CLOCKS.borrow(cs);
+        if let Some(mut clocks) = frozen.get() {
+            clocks.cpu = cpu;
+            clocks.tpll = Pll::Tpll.frequency();
+            frozen.set(Some(clocks));
+        }
+        result.map(|()| cpu)
+    })
+}
+
+/// TPLL `div_sel` and C906 divider factor for `target`, PLL output kept in range
+fn cpu_pll_setting(target: u32) -> Option<(u32, u32)> {
+    if target == 0 || target > CPU_MAX {
+        return None;
+    }
+    (1..=MAX_DIV).find_map(|factor| {
+        // round down, never above the request
+        let div_sel = (target as u64 * factor as u64 / XTAL as u64) as u32;
+        let output = XTAL as u64 * div_sel as u64;
+        let in_range = (PLL_MIN as u64..=PLL_MAX as u64).contains(&output);
+        (in_range && div_sel < (1 << PLL_DIV_WIDTH)).then_some((div_sel, factor))
+    })
+}
+
+fn wait_tpll_lock() -> Result<(), Error> {
+    let status = regs().pll_g6_status();
+    for _ in 0..PLL_LOCK_TIMEOUT {
+        if !status.is_set(TPLL_UPDATING) && status.is_set(TPLL_LOCKED) {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Error::PllLockTimeout)
+}
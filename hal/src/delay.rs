@@ -0,0 +1,57 @@
+//! Busy-wait delay on the C906 cycle counter
+//!
+//! The core frequency is read from [`clocks`] on every call, so delays stay
+//! right after [`crate::clocks::set_cpu_frequency`].
+
+use core::arch::asm;
+
+use embedded_hal::delay::DelayNs;
+
+use crate::clocks::clocks;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Delay;
+
+impl Delay {
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+#[inline(always)]
+fn cycles() -> u64 {
+    let cycles: u64;
+    unsafe { asm!("rdcycle {}", out(reg) cycles) };
+    cycles
+}
+
+/// Poll `done` about every microsecond, `Err(timeout)` if it still doesn't
+/// hold after `timeout_us`
+///
+/// `done` is checked once more after the last delay, so a condition that came
+/// true meanwhile isn't reported as a timeout.
+pub(crate) fn wait<E>(mut done: impl FnMut() -> bool, timeout_us: u32, timeout: E) -> Result<(), E> {
+    let mut delay = Delay::new();
+    for _ in 0..timeout_us {
+        if done() {
+            return Ok(());
+        }
+        delay.delay_us(1);
+    }
+    if done() {
+        Ok(())
+    } else {
+        Err(timeout)
+    }
+}
+
+impl DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        let cpu = clocks().cpu.raw() as u64;
+        let target = (ns as u64 * cpu).div_ceil(1_000_000_000);
+        let start = cycles();
+        while cycles().wrapping_sub(start) < target {
+            core::hint::spin_loop();
+        }
+    }
+}
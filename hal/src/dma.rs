@@ -0,0 +1,903 @@
+//! DMA, DesignWare AXI DMA controller (SYSDMA)
+//!
+//! SYSDMA has 8 channels, each a [`Channel`] singleton `DMA_CH0` ~ `DMA_CH7`
+//! handed to a driver for the duration of a [`Transfer`]. Peripheral requests
+//! reach the channels through a mux in TOP, [`Request`] lists them.
+//!
+//! Every transfer runs from a linked list of descriptors, so buffers longer
+//! than one block, [`MAX_BLOCK_ITEMS`], and scatter-gather lists work the same.
+//! Memory is not coherent with the C906 data cache, transfers clean their
+//! sources before starting and invalidate their destinations when done.
+//!
+//! [`ReadableRing`] and [`WritableRing`] keep a channel running over a
+//! circular list of descriptors, one per period of the buffer, for streams
+//! such as audio.
+//!
+//! Channels are polled, their interrupts are not routed to the PLIC. The
+//! controller is clocked and enabled when the first channel starts.
+
+use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomData;
+
+use critical_section::Mutex;
+
+use crate::cache;
+use crate::clocks::Gate;
+use crate::delay::wait;
+use crate::mmio::regs;
+use crate::peripheral::PeripheralRef;
+use crate::rcc::{self, Reset};
+use crate::{into_ref, Peripheral};
+
+/// Number of channels
+pub const CHANNEL_COUNT: usize = 8;
+/// Items in one block, the `snps,block-size` of the controller
+pub const MAX_BLOCK_ITEMS: usize = 1024;
+/// Descriptors of each channel, a transfer is at most this many blocks
+pub const MAX_DESCRIPTORS: usize = 16;
+/// Items in one contiguous transfer
+pub const MAX_TRANSFER_ITEMS: usize = MAX_DESCRIPTORS * MAX_BLOCK_ITEMS;
+
+const DMAC_BASE: usize = 0x0433_0000;
+const CHANNEL_OFFSET: usize = 0x100;
+const CHANNEL_STRIDE: usize = 0x100;
+
+/// SDMA_DMA_CH_REMAP0/1 in TOP, one byte per channel
+const REMAP_BASE: usize = 0x0300_0154;
+const REMAP_UPDATE: u32 = 1 << 31;
+const REMAP_MASK: u32 = 0x3f;
+
+const CFG_DMAC_EN: u32 = 1 << 0;
+
+// CH_CTL, low word
+const CTL_SRC_NOINC: u32 = 1 << 4;
+const CTL_DST_NOINC: u32 = 1 << 6;
+const CTL_SRC_WIDTH_OFFSET: u32 = 8;
+const CTL_DST_WIDTH_OFFSET: u32 = 11;
+const CTL_SRC_MSIZE_OFFSET: u32 = 14;
+const CTL_DST_MSIZE_OFFSET: u32 = 18;
+// CH_CTL, high word
+const CTL_H_LLI_LAST: u32 = 1 << 30;
+const CTL_H_LLI_VALID: u32 = 1 << 31;
+
+// CH_CFG, low word
+const CFG_SRC_MULTBLK_LLI: u32 = 0b11;
+const CFG_DST_MULTBLK_LLI: u32 = 0b11 << 2;
+// CH_CFG, high word
+const CFG_H_TT_FC_OFFSET: u32 = 0;
+const CFG_H_SRC_PER_OFFSET: u32 = 7;
+const CFG_H_DST_PER_OFFSET: u32 = 12;
+const CFG_H_PRIORITY_OFFSET: u32 = 17;
+
+// CH_INTSTATUS
+const INT_DMA_TFR_DONE: u32 = 1 << 1;
+/// Decode, slave, descriptor and register errors
+const INT_ERRORS: u32 = 0x003f_7fe0;
+const INT_DISABLED: u32 = 1 << 30;
+
+/// A disabled channel finishes its burst, a stalled handshake never does
+const STOP_TIMEOUT_US: u32 = 1_000;
+/// The first descriptor loads as soon as the channel is enabled
+const LOAD_TIMEOUT_US: u32 = 1_000;
+
+regs! {
+    /// Common registers
+    pub struct Regs {
+        id: 0x000,
+        cfg: 0x010,
+        /// [7:0] enable, [15:8] write enable of each channel
+        chen: 0x018,
+        /// [7:0] abort, [15:8] write enable of each channel
+        chen_hi: 0x01c,
+        intstatus: 0x030,
+        reset: 0x058,
+    }
+}
+
+regs! {
+    /// Registers of one channel, 64-bit ones split into low and high words
+    pub struct ChRegs {
+        sar_lo: 0x000,
+        sar_hi: 0x004,
+        dar_lo: 0x008,
+        dar_hi: 0x00c,
+        block_ts: 0x010,
+        ctl_lo: 0x018,
+        ctl_hi: 0x01c,
+        cfg_lo: 0x020,
+        cfg_hi: 0x024,
+        llp_lo: 0x028,
+        llp_hi: 0x02c,
+        status: 0x030,
+        intstatus_ena: 0x080,
+        intstatus: 0x088,
+        intsignal_ena: 0x090,
+        intclear: 0x098,
+    }
+}
+
+#[inline]
+pub(crate) fn regs() -> Regs {
+    Regs::at(DMAC_BASE)
+}
+
+#[inline]
+fn ch_regs(number: usize) -> ChRegs {
+    ChRegs::at(DMAC_BASE + CHANNEL_OFFSET + number * CHANNEL_STRIDE)
+}
+
+static ENABLED: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Enable the controller, once, before the first channel starts
+fn init() {
+    critical_section::with(|cs| {
+        let enabled = ENABLED.borrow(cs);
+        if !enabled.get() {
+            rcc::enable_and_reset(&[Gate::SdmaAxi], Reset::Sdma);
+            regs().cfg().set_bits(CFG_DMAC_EN);
+            enabled.set(true);
+        }
+    });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Empty buffer, or source and destination lengths differ
+    InvalidLength,
+    /// Transfer needs more than [`MAX_DESCRIPTORS`] blocks
+    TooLong,
+    /// Bus or descriptor error, with the channel interrupt status
+    Bus(u32),
+    /// A ring buffer was lapped and its channel stopped
+    Overrun,
+    /// The channel didn't load its first descriptor, or didn't stop
+    Timeout,
+}
+
+/// Peripheral handshake requests, as numbered by the TOP DMA mux
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Request {
+    I2s0Rx = 0,
+    I2s0Tx = 1,
+    I2s1Rx = 2,
+    I2s1Tx = 3,
+    I2s2Rx = 4,
+    I2s2Tx = 5,
+    I2s3Rx = 6,
+    I2s3Tx = 7,
+    Uart0Rx = 8,
+    Uart0Tx = 9,
+    Uart1Rx = 10,
+    Uart1Tx = 11,
+    Uart2Rx = 12,
+    Uart2Tx = 13,
+    Uart3Rx = 14,
+    Uart3Tx = 15,
+    Spi0Rx = 16,
+    Spi0Tx = 17,
+    Spi1Rx = 18,
+    Spi1Tx = 19,
+    Spi2Rx = 20,
+    Spi2Tx = 21,
+    Spi3Rx = 22,
+    Spi3Tx = 23,
+    I2c0Rx = 24,
+    I2c0Tx = 25,
+    I2c1Rx = 26,
+    I2c1Tx = 27,
+    I2c2Rx = 28,
+    I2c2Tx = 29,
+    I2c3Rx = 30,
+    I2c3Tx = 31,
+    I2c4Rx = 32,
+    I2c4Tx = 33,
+    Tdm0Rx = 34,
+    Tdm0Tx = 35,
+    Tdm1Rx = 36,
+    AudSrc = 37,
+    SpiNand = 38,
+    SpiNor = 39,
+    Uart4Rx = 40,
+    Uart4Tx = 41,
+    SpiNor1 = 42,
+}
+
+/// Items moved per handshake request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Burst {
+    Single = 0,
+    Incr4 = 1,
+    Incr8 = 2,
+    Incr16 = 3,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TransferOptions {
+    /// Channel priority, 0 ~ 7, higher wins
+    pub priority: u8,
+    pub burst: Burst,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            burst: Burst::Single,
+        }
+    }
+}
+
+/// One piece of a scatter-gather transfer, `len` items from `src` to `dst`
+#[derive(Debug, Clone, Copy)]
+pub struct Segment<W: Word> {
+    pub src: *const W,
+    pub dst: *mut W,
+    pub len: usize,
+}
+
+/// Item of a transfer, also the width of every bus access
+pub trait Word: sealed::Word + Copy + 'static {}
+
+macro_rules! impl_word {
+    ($ty:ty, $width:expr) => {
+        impl sealed::Word for $ty {
+            const WIDTH: u32 = $width;
+        }
+
+        impl Word for $ty {}
+    };
+}
+
+impl_word!(u8, 0);
+impl_word!(u16, 1);
+impl_word!(u32, 2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dir {
+    MemoryToMemory = 0,
+    MemoryToPeripheral = 1,
+    PeripheralToMemory = 2,
+}
+
+/// Linked list item, read by the controller from memory
+#[derive(Debug, Clone, Copy)]
+#[repr(C, align(64))]
+struct Lli {
+    sar: u64,
+    dar: u64,
+    block_ts: u64,
+    llp: u64,
+    ctl_lo: u32,
+    ctl_hi: u32,
+    sstat: u32,
+    dstat: u32,
+    status: u64,
+    reserved: u64,
+}
+
+impl Lli {
+    const fn new() -> Self {
+        Self {
+            sar: 0,
+            dar: 0,
+            block_ts: 0,
+            llp: 0,
+            ctl_lo: 0,
+            ctl_hi: 0,
+            sstat: 0,
+            dstat: 0,
+            status: 0,
+            reserved: 0,
+        }
+    }
+}
+
+/// Descriptors of one channel, only touched by the owner of the channel
+struct Descriptors(UnsafeCell<[Lli; MAX_DESCRIPTORS]>);
+
+// SAFETY: each list belongs to a channel singleton, see `Transfer::start`
+unsafe impl Sync for Descriptors {}
+
+static DESCRIPTORS: [Descriptors; CHANNEL_COUNT] =
+    [const { Descriptors(UnsafeCell::new([Lli::new(); MAX_DESCRIPTORS])) }; CHANNEL_COUNT];
+
+/// A running transfer, stopped when dropped
+pub struct Transfer<'a, C: Channel> {
+    _channel: PeripheralRef<'a, C>,
+    dir: Dir,
+    /// Descriptors in use
+    blocks: usize,
+    /// Item size in bytes
+    size: usize,
+    done: bool,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a, C: Channel> Transfer<'a, C> {
+    /// Read `buf.len()` items from the register at `peri_addr`, paced by `request`
+    ///
+    /// # Safety
+    ///
+    /// `peri_addr` must be a peripheral data register that `request` paces.
+    /// The transfer must not be leaked while `buf` is in use elsewhere.
+    pub unsafe fn new_read<W: Word>(
+        channel: impl Peripheral<P = C> + 'a,
+        request: Request,
+        peri_addr: *mut W,
+        buf: &'a mut [W],
+        options: TransferOptions,
+    ) -> Result<Self, Error> {
+        let segment = Segment {
+            src: peri_addr,
+            dst: buf.as_mut_ptr(),
+            len: buf.len(),
+        };
+        Self::start(channel, Dir::PeripheralToMemory, Some(request), &[segment], options)
+    }
+
+    /// Write `buf` to the register at `peri_addr`, paced by `request`
+    ///
+    /// # Safety
+    ///
+    /// See [`Transfer::new_read`].
+    pub unsafe fn new_write<W: Word>(
+        channel: impl Peripheral<P = C> + 'a,
+        request: Request,
+        buf: &'a [W],
+        peri_addr: *mut W,
+        options: TransferOptions,
+    ) -> Result<Self, Error> {
+        let segment = Segment {
+            src: buf.as_ptr(),
+            dst: peri_addr,
+            len: buf.len(),
+        };
+        Self::start(channel, Dir::MemoryToPeripheral, Some(request), &[segment], options)
+    }
+
+    /// Copy `src` into `dst`, which must have the same length
+    ///
+    /// # Safety
+    ///
+    /// The transfer must not be leaked while `dst` is in use elsewhere.
+    pub unsafe fn new_transfer<W: Word>(
+        channel: impl Peripheral<P = C> + 'a,
+        src: &'a [W],
+        dst: &'a mut [W],
+        options: TransferOptions,
+    ) -> Result<Self, Error> {
+        if src.len() != dst.len() {
+            return Err(Error::InvalidLength);
+        }
+        let segment = Segment {
+            src: src.as_ptr(),
+            dst: dst.as_mut_ptr(),
+            len: src.len(),
+        };
+        Self::start(channel, Dir::MemoryToMemory, None, &[segment], options)
+    }
+
+    /// Copy each of `segments` in turn, as one linked list
+    ///
+    /// # Safety
+    ///
+    /// Every segment must be valid memory for the whole transfer, and the
+    /// transfer must not be leaked while the destinations are in use elsewhere.
+    pub unsafe fn new_linked<W: Word>(
+        channel: impl Peripheral<P = C> + 'a,
+        segments: &[Segment<W>],
+        options: TransferOptions,
+    ) -> Result<Self, Error> {
+        Self::start(channel, Dir::MemoryToMemory, None, segments, options)
+    }
+
+    unsafe fn start<W: Word>(
+        channel: impl Peripheral<P = C> + 'a,
+        dir: Dir,
+        request: Option<Request>,
+        segments: &[Segment<W>],
+        options: TransferOptions,
+    ) -> Result<Self, Error> {
+        into_ref!(channel);
+        let number = C::number();
+        let size = core::mem::size_of::<W>();
+
+        let mut blocks = 0;
+        for segment in segments {
+            if segment.len == 0 {
+                return Err(Error::InvalidLength);
+            }
+            blocks += segment.len.div_ceil(MAX_BLOCK_ITEMS);
+        }
+        if blocks == 0 {
+            return Err(Error::InvalidLength);
+        }
+        if blocks > MAX_DESCRIPTORS {
+            return Err(Error::TooLong);
+        }
+
+        let ctl_lo = ctl_lo::<W>(dir, options.burst);
+
+        // SAFETY: the channel is borrowed, nothing else uses its descriptors
+        let list = &mut *DESCRIPTORS[number].0.get();
+        let mut index = 0;
+        for segment in segments {
+            let mut done = 0;
+            while done < segment.len {
+                let len = (segment.len - done).min(MAX_BLOCK_ITEMS);
+                let (src, dst) = match dir {
+                    Dir::MemoryToMemory => (segment.src.add(done), segment.dst.add(done)),
+                    Dir::MemoryToPeripheral => (segment.src.add(done), segment.dst),
+                    Dir::PeripheralToMemory => (segment.src, segment.dst.add(done)),
+                };
+                if dir != Dir::PeripheralToMemory {
+                    cache::clean(src as usize, len * size);
+                }
+                if dir != Dir::MemoryToPeripheral {
+                    // no dirty line may be evicted over the incoming data
+                    cache::flush(dst as usize, len * size);
+                }
+
+                let next = list.as_ptr().add(index + 1) as u64;
+                let lli = &mut list[index];
+                *lli = Lli::new();
+                lli.sar = src as u64;
+                lli.dar = dst as u64;
+                lli.block_ts = (len - 1) as u64;
+                lli.ctl_lo = ctl_lo;
+                lli.ctl_hi = CTL_H_LLI_VALID;
+                if index + 1 < blocks {
+                    lli.llp = next;
+                } else {
+                    lli.ctl_hi |= CTL_H_LLI_LAST;
+                }
+
+                done += len;
+                index += 1;
+            }
+        }
+        cache::clean(list.as_ptr() as usize, blocks * core::mem::size_of::<Lli>());
+
+        start_channel(number, dir, request, list.as_ptr() as u64, options);
+
+        Ok(Self {
+            _channel: channel,
+            dir,
+            blocks,
+            size,
+            done: false,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Whether the channel is still moving data
+    pub fn is_running(&mut self) -> bool {
+        regs().chen().is_set(1 << C::number())
+    }
+
+    /// Ask the channel to stop, it finishes the current burst first
+    pub fn request_stop(&mut self) {
+        let mask = 1 << C::number();
+        regs().chen().write(mask << 8);
+    }
+
+    /// Wait until the transfer completes
+    pub fn blocking_wait(&mut self) -> Result<(), Error> {
+        let ch = ch_regs(C::number());
+        while ch.intstatus().read() & (INT_DMA_TFR_DONE | INT_ERRORS | INT_DISABLED) == 0 && self.is_running() {
+            core::hint::spin_loop();
+        }
+        let status = ch.intstatus().read();
+        self.finish();
+
+        if status & INT_ERRORS != 0 {
+            return Err(Error::Bus(status));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        if self.done {
+            return;
+        }
+        let _ = stop_channel(C::number());
+
+        if self.dir != Dir::MemoryToPeripheral {
+            // SAFETY: the channel is stopped and still borrowed
+            let list = unsafe { &*DESCRIPTORS[C::number()].0.get() };
+            for lli in &list[..self.blocks] {
+                cache::invalidate(lli.dar as usize, (lli.block_ts as usize + 1) * self.size);
+            }
+        }
+        self.done = true;
+    }
+}
+
+impl<C: Channel> Drop for Transfer<'_, C> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Circular transfer, the state shared by [`ReadableRing`] and [`WritableRing`]
+///
+/// Each period of the buffer is one descriptor and the last one links back
+/// to the first. The controller writes a finished descriptor back with
+/// LLI_VALID cleared, so it stops on a period the driver hasn't handed back
+/// yet instead of overwriting it, which is reported as [`Error::Overrun`].
+struct Ring<'a, C: Channel, W: Word> {
+    _channel: PeripheralRef<'a, C>,
+    dir: Dir,
+    buf: *mut W,
+    periods: usize,
+    period_len: usize,
+    /// Next period handed to the driver
+    next: usize,
+    /// Items of `next` already read or written
+    offset: usize,
+    phantom: PhantomData<&'a mut [W]>,
+}
+
+impl<'a, C: Channel, W: Word> Ring<'a, C, W> {
+    unsafe fn start(
+        channel: impl Peripheral<P = C> + 'a,
+        dir: Dir,
+        request: Request,
+        peri_addr: *mut W,
+        buf: &'a mut [W],
+        periods: usize,
+        options: TransferOptions,
+    ) -> Result<Self, Error> {
+        into_ref!(channel);
+        let len = buf.len();
+        let bytes = core::mem::size_of_val(buf);
+        let buf = buf.as_mut_ptr();
+        if periods < 2 || len == 0 || !len.is_multiple_of(periods) {
+            return Err(Error::InvalidLength);
+        }
+        let period_len = len / periods;
+        if periods > MAX_DESCRIPTORS || period_len > MAX_BLOCK_ITEMS {
+            return Err(Error::TooLong);
+        }
+
+        match dir {
+            Dir::PeripheralToMemory => cache::flush(buf as usize, bytes),
+            _ => cache::clean(buf as usize, bytes),
+        }
+
+        let number = C::number();
+        let ctl_lo = ctl_lo::<W>(dir, options.burst);
+        // SAFETY: the channel is borrowed, nothing else uses its descriptors
+        let list = &mut *DESCRIPTORS[number].0.get();
+        let base = list.as_ptr() as u64;
+        for (index, lli) in list[..periods].iter_mut().enumerate() {
+            let period = buf.add(index * period_len);
+            let (src, dst) = match dir {
+                Dir::PeripheralToMemory => (peri_addr, period),
+                _ => (period, peri_addr),
+            };
+            *lli = Lli::new();
+            lli.sar = src as u64;
+            lli.dar = dst as u64;
+            lli.block_ts = (period_len - 1) as u64;
+            lli.llp = base + (((index + 1) % periods) * core::mem::size_of::<Lli>()) as u64;
+            lli.ctl_lo = ctl_lo;
+            lli.ctl_hi = CTL_H_LLI_VALID;
+        }
+        cache::clean(base as usize, periods * core::mem::size_of::<Lli>());
+
+        start_channel(number, dir, Some(request), base, options);
+        let ring = Self {
+            _channel: channel,
+            dir,
+            buf,
+            periods,
+            period_len,
+            next: 0,
+            offset: 0,
+            phantom: PhantomData,
+        };
+        // CH_LLP moves on once the first descriptor is loaded, see `active`
+        wait(
+            || ring.llp() != base || !ring.is_running(),
+            LOAD_TIMEOUT_US,
+            Error::Timeout,
+        )?;
+        Ok(ring)
+    }
+
+    #[inline]
+    fn is_running(&self) -> bool {
+        regs().chen().is_set(1 << C::number())
+    }
+
+    /// Descriptor the controller loads next
+    fn llp(&self) -> u64 {
+        let ch = ch_regs(C::number());
+        ch.llp_lo().read() as u64 | ((ch.llp_hi().read() as u64) << 32)
+    }
+
+    /// Period the controller is working on
+    fn active(&self) -> usize {
+        let base = DESCRIPTORS[C::number()].0.get() as u64;
+        let loaded = (self.llp() - base) as usize / core::mem::size_of::<Lli>();
+        (loaded + self.periods - 1) % self.periods
+    }
+
+    /// Finished periods not handed back yet
+    fn ready(&self) -> Result<usize, Error> {
+        if !self.is_running() {
+            return Err(Error::Overrun);
+        }
+        Ok((self.active() + self.periods - self.next) % self.periods)
+    }
+
+    /// Unused part of period `next`
+    fn period(&mut self) -> &mut [W] {
+        // SAFETY: the controller is done with `next` until it is handed back
+        unsafe {
+            let start = self.buf.add(self.next * self.period_len + self.offset);
+            core::slice::from_raw_parts_mut(start, self.period_len - self.offset)
+        }
+    }
+
+    /// Account for `len` items of period `next`, handing it back once complete
+    fn advance(&mut self, len: usize) {
+        self.offset += len;
+        if self.offset < self.period_len {
+            return;
+        }
+        if self.dir == Dir::MemoryToPeripheral {
+            // SAFETY: the period is inside the borrowed buffer
+            let period = unsafe { self.buf.add(self.next * self.period_len) };
+            cache::clean(period as usize, self.period_len * core::mem::size_of::<W>());
+        }
+        // SAFETY: the controller has written this descriptor back and moved on
+        unsafe {
+            let lli = &mut (*DESCRIPTORS[C::number()].0.get())[self.next];
+            let addr = lli as *mut Lli as usize;
+            cache::invalidate(addr, core::mem::size_of::<Lli>());
+            core::ptr::write_volatile(&mut lli.ctl_hi, CTL_H_LLI_VALID);
+            cache::clean(addr, core::mem::size_of::<Lli>());
+        }
+        self.next = (self.next + 1) % self.periods;
+        self.offset = 0;
+    }
+}
+
+impl<C: Channel, W: Word> Drop for Ring<'_, C, W> {
+    fn drop(&mut self) {
+        let _ = stop_channel(C::number());
+        if self.dir == Dir::PeripheralToMemory {
+            let len = self.periods * self.period_len * core::mem::size_of::<W>();
+            cache::invalidate(self.buf as usize, len);
+        }
+    }
+}
+
+/// Peripheral to memory stream, see [`Ring`] for how periods are recycled
+pub struct ReadableRing<'a, C: Channel, W: Word> {
+    ring: Ring<'a, C, W>,
+}
+
+impl<'a, C: Channel, W: Word> ReadableRing<'a, C, W> {
+    /// Keep reading the register at `peri_addr` into `buf`, split into `periods`
+    ///
+    /// # Safety
+    ///
+    /// `peri_addr` must be a peripheral data register that `request` paces.
+    /// The ring must not be leaked while `buf` is in use elsewhere.
+    pub unsafe fn new(
+        channel: impl Peripheral<P = C> + 'a,
+        request: Request,
+        peri_addr: *mut W,
+        buf: &'a mut [W],
+        periods: usize,
+        options: TransferOptions,
+    ) -> Result<Self, Error> {
+        let ring = Ring::start(
+            channel,
+            Dir::PeripheralToMemory,
+            request,
+            peri_addr,
+            buf,
+            periods,
+            options,
+        )?;
+        Ok(Self { ring })
+    }
+
+    /// Items that [`ReadableRing::read`] can return right now
+    pub fn len(&self) -> Result<usize, Error> {
+        Ok(self.ring.ready()? * self.ring.period_len - self.ring.offset)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Move received items into `out` without waiting, returns how many
+    ///
+    /// Items only become readable once their whole period is received.
+    pub fn read(&mut self, out: &mut [W]) -> Result<usize, Error> {
+        let mut ready = self.ring.ready()?;
+        let mut done = 0;
+        while done < out.len() && ready > 0 {
+            if self.ring.offset == 0 {
+                let period = self.ring.period();
+                cache::invalidate(period.as_ptr() as usize, core::mem::size_of_val(period));
+            }
+            let period = self.ring.period();
+            let len = period.len().min(out.len() - done);
+            out[done..done + len].copy_from_slice(&period[..len]);
+            done += len;
+            if len == period.len() {
+                ready -= 1;
+            }
+            self.ring.advance(len);
+        }
+        Ok(done)
+    }
+}
+
+/// Memory to peripheral stream, see [`Ring`] for how periods are recycled
+pub struct WritableRing<'a, C: Channel, W: Word> {
+    ring: Ring<'a, C, W>,
+}
+
+impl<'a, C: Channel, W: Word> WritableRing<'a, C, W> {
+    /// Keep writing `buf`, split into `periods`, to the register at `peri_addr`
+    ///
+    /// The current content of `buf` goes out first, fill it or zero it.
+    ///
+    /// # Safety
+    ///
+    /// See [`ReadableRing::new`].
+    pub unsafe fn new(
+        channel: impl Peripheral<P = C> + 'a,
+        request: Request,
+        buf: &'a mut [W],
+        peri_addr: *mut W,
+        periods: usize,
+        options: TransferOptions,
+    ) -> Result<Self, Error> {
+        let ring = Ring::start(
+            channel,
+            Dir::MemoryToPeripheral,
+            request,
+            peri_addr,
+            buf,
+            periods,
+            options,
+        )?;
+        Ok(Self { ring })
+    }
+
+    /// Items that [`WritableRing::write`] can take right now
+    pub fn free(&self) -> Result<usize, Error> {
+        Ok(self.ring.ready()? * self.ring.period_len - self.ring.offset)
+    }
+
+    /// Queue items of `data` without waiting, returns how many were taken
+    ///
+    /// A period is handed to the controller once it is completely written.
+    pub fn write(&mut self, data: &[W]) -> Result<usize, Error> {
+        let mut ready = self.ring.ready()?;
+        let mut done = 0;
+        while done < data.len() && ready > 0 {
+            let period = self.ring.period();
+            let len = period.len().min(data.len() - done);
+            period[..len].copy_from_slice(&data[done..done + len]);
+            done += len;
+            if len == period.len() {
+                ready -= 1;
+            }
+            self.ring.advance(len);
+        }
+        Ok(done)
+    }
+}
+
+/// CH_CTL low word shared by every descriptor of a transfer
+fn ctl_lo<W: Word>(dir: Dir, burst: Burst) -> u32 {
+    let (src_noinc, dst_noinc) = match dir {
+        Dir::MemoryToMemory => (0, 0),
+        Dir::MemoryToPeripheral => (0, CTL_DST_NOINC),
+        Dir::PeripheralToMemory => (CTL_SRC_NOINC, 0),
+    };
+    src_noinc
+        | dst_noinc
+        | (W::WIDTH << CTL_SRC_WIDTH_OFFSET)
+        | (W::WIDTH << CTL_DST_WIDTH_OFFSET)
+        | ((burst as u32) << CTL_SRC_MSIZE_OFFSET)
+        | ((burst as u32) << CTL_DST_MSIZE_OFFSET)
+}
+
+/// Point channel `number` at the descriptor list at `llp` and enable it
+fn start_channel(number: usize, dir: Dir, request: Option<Request>, llp: u64, options: TransferOptions) {
+    init();
+    let ch = ch_regs(number);
+    if let Some(request) = request {
+        set_request(number, request);
+    }
+    let per = number as u32;
+    ch.cfg_lo().write(CFG_SRC_MULTBLK_LLI | CFG_DST_MULTBLK_LLI);
+    ch.cfg_hi().write(
+        ((dir as u32) << CFG_H_TT_FC_OFFSET)
+            | (per << CFG_H_SRC_PER_OFFSET)
+            | (per << CFG_H_DST_PER_OFFSET)
+            | (((options.priority & 0b111) as u32) << CFG_H_PRIORITY_OFFSET),
+    );
+    ch.llp_lo().write(llp as u32);
+    ch.llp_hi().write((llp >> 32) as u32);
+
+    ch.intclear().write(u32::MAX);
+    ch.intstatus_ena().write(u32::MAX);
+    ch.intsignal_ena().write(0);
+
+    let mask = 1 << number;
+    regs().chen().write(mask | (mask << 8));
+}
+
+/// Stop channel `number`, it finishes the current burst first
+///
+/// A channel whose peripheral stopped requesting never finishes the burst,
+/// it is aborted instead, which may lose the data in flight.
+fn stop_channel(number: usize) -> Result<(), Error> {
+    let regs = regs();
+    let mask = 1 << number;
+    let stopped = || !regs.chen().is_set(mask);
+    regs.chen().write(mask << 8);
+    let result = wait(stopped, STOP_TIMEOUT_US, Error::Timeout).or_else(|_| {
+        regs.chen_hi().write(mask | (mask << 8));
+        wait(stopped, STOP_TIMEOUT_US, Error::Timeout)
+    });
+    ch_regs(number).intclear().write(u32::MAX);
+    result
+}
+
+/// Route `request` to the handshake interface of channel `number`
+fn set_request(number: usize, request: Request) {
+    let reg = crate::mmio::Reg::at(REMAP_BASE + (number / 4) * 4);
+    let shift = (number % 4) * 8;
+    // 4 channels share the register
+    critical_section::with(|_| {
+        reg.modify(|r| (r & !(REMAP_MASK << shift)) | ((request as u32) << shift) | REMAP_UPDATE)
+    });
+}
+
+pub(crate) mod sealed {
+    pub trait Channel {
+        fn number() -> usize;
+    }
+
+    pub trait Word {
+        /// Transfer width, log2 of the size in bytes
+        const WIDTH: u32;
+    }
+}
+
+pub trait Channel: Peripheral<P = Self> + sealed::Channel + 'static + Send {}
+
+macro_rules! impl_channel {
+    ($inst:ident, $number:expr) => {
+        impl sealed::Channel for crate::peripherals::$inst {
+            #[inline(always)]
+            fn number() -> usize {
+                $number
+            }
+        }
+
+        impl Channel for crate::peripherals::$inst {}
+    };
+}
+
+impl_channel!(DMA_CH0, 0);
+impl_channel!(DMA_CH1, 1);
+impl_channel!(DMA_CH2, 2);
+impl_channel!(DMA_CH3, 3);
+impl_channel!(DMA_CH4, 4);
+impl_channel!(DMA_CH5, 5);
+impl_channel!(DMA_CH6, 6);
+impl_channel!(DMA_CH7, 7);
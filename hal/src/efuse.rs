@@ -0,0 +1,274 @@
+//! eFuse, one-time programmable chip configuration
+//!
+//! The 64 fuse words are copied to the shadow registers at power on, reading
+//! them never touches the fuse array. Words the boot ROM and the hal know about:
+//!
+//! | word    | offset | content                                         |
+//! |---------|--------|-------------------------------------------------|
+//! | 0 ~ 4   | 0x100  | FTSN0 ~ FTSN4, factory test serial              |
+//! | 2       | 0x108  | FTSN2 also holds the DDR vendor and the package |
+//! | 8       | 0x120  | SCS_CONFIG, secure boot and loader encryption   |
+//! | 42 ~ 49 | 0x1a8  | KPUB_HASH, SHA-256 of the boot public key       |
+//!
+//! See [`crate::signature`] for the chip identification built on top of it.
+//!
+//! # Programming
+//!
+//! Blown fuses can't be cleared, so [`Efuse::program`] takes an [`Unlock`]
+//! token, refuses to clear bits or to touch the factory words, and reads the
+//! word back after refreshing the shadow. [`Efuse::dry_run`] runs the same
+//! checks and reports the bits it would blow without powering the array.
+//! Each bit is blown in both rows of its redundant pair.
+
+use core::marker::PhantomData;
+
+use crate::clocks::{self, Gate};
+use crate::delay::wait;
+use crate::{into_ref, pac, peripherals, Peripheral};
+
+/// Number of fuse words
+pub const WORDS: usize = 64;
+
+/// FTSN0 ~ FTSN4, written at factory test
+pub const FTSN: [usize; 5] = [0, 1, 2, 3, 4];
+/// Secure boot configuration
+pub const SCS_CONFIG: usize = 8;
+/// First of the 8 words of the boot public key hash
+pub const KPUB_HASH: usize = 42;
+
+/// MODE commands
+const MODE_POWER_ON: u32 = 0x10;
+const MODE_PROGRAM: u32 = 0x14;
+const MODE_POWER_OFF: u32 = 0x18;
+const MODE_REFRESH_SHADOW: u32 = 0x30;
+
+/// Longest a command may keep STATUS.BUSY set
+const BUSY_TIMEOUT_US: u32 = 10_000;
+
+/// SCS_CONFIG[1:0], boot ROM verifies the FSBL signature
+const SCS_TEE_SCS_ENABLE: u32 = 0b11;
+/// SCS_CONFIG[3:2], the FSBL is encrypted
+const SCS_BOOT_LOADER_ENCRYPTION: u32 = 0b11 << 2;
+
+/// Secure boot state from SCS_CONFIG
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct SecureBoot {
+    /// The boot ROM only runs a loader signed with the key in KPUB_HASH
+    pub enabled: bool,
+    /// The loader is decrypted with the fused loader key
+    pub loader_encrypted: bool,
+}
+
+/// Read a shadow word, without owning the peripheral
+///
+/// Shadow registers are read-only, so this can't race with [`Efuse`].
+#[inline]
+pub(crate) fn shadow(word: usize) -> u32 {
+    let efuse = unsafe { &*pac::EFUSE::PTR };
+    efuse.shadow(word).read().bits()
+}
+
+/// 64-bit chip serial, FTSN3:FTSN4
+pub(crate) fn serial() -> u64 {
+    ((shadow(FTSN[3]) as u64) << 32) | shadow(FTSN[4]) as u64
+}
+
+pub(crate) fn secure_boot() -> SecureBoot {
+    let scs = shadow(SCS_CONFIG);
+    SecureBoot {
+        enabled: scs & SCS_TEE_SCS_ENABLE != 0,
+        loader_encrypted: scs & SCS_BOOT_LOADER_ENCRYPTION != 0,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Word index is not below [`WORDS`]
+    WordOutOfRange(usize),
+    /// FTSN0 ~ FTSN4 are written at factory test and identify the chip
+    Reserved(usize),
+    /// The new value clears bits that are already blown
+    CannotClear { word: usize, bits: u32 },
+    /// The word read back after programming isn't the requested value
+    Verify { word: usize, expected: u32, read: u32 },
+    /// The controller stayed busy
+    Timeout,
+}
+
+/// Permission to blow fuses, required by [`Efuse::program`]
+#[derive(Debug)]
+pub struct Unlock {
+    _private: (),
+}
+
+impl Unlock {
+    /// # Safety
+    ///
+    /// Programming is permanent. A wrong SCS_CONFIG or KPUB_HASH leaves a
+    /// chip that no longer boots, check the values with [`Efuse::dry_run`]
+    /// first.
+    pub unsafe fn new() -> Self {
+        Self { _private: () }
+    }
+}
+
+/// What programming a word does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Plan {
+    pub word: usize,
+    /// Shadow before programming
+    pub current: u32,
+    /// Requested value
+    pub value: u32,
+    /// Bits that get blown, `value & !current`
+    pub blow: u32,
+}
+
+impl Plan {
+    /// Nothing to blow, the word already holds the value
+    pub fn is_noop(&self) -> bool {
+        self.blow == 0
+    }
+}
+
+pub struct Efuse<'d> {
+    phantom: PhantomData<&'d mut peripherals::EFUSE>,
+}
+
+impl<'d> Efuse<'d> {
+    pub fn new(_peri: impl Peripheral<P = peripherals::EFUSE> + 'd) -> Self {
+        into_ref!(_peri);
+
+        clocks::enable(Gate::ApbEfuse);
+        clocks::enable(Gate::Efuse);
+
+        Self { phantom: PhantomData }
+    }
+
+    /// Shadow of fuse word `word`, 0 ~ 63
+    #[inline]
+    pub fn read(&self, word: usize) -> Result<u32, Error> {
+        if word >= WORDS {
+            return Err(Error::WordOutOfRange(word));
+        }
+        Ok(shadow(word))
+    }
+
+    /// Every shadow word
+    pub fn read_all(&self) -> [u32; WORDS] {
+        let efuse = unsafe { &*pac::EFUSE::PTR };
+        let mut words = [0; WORDS];
+        for (word, reg) in words.iter_mut().zip(efuse.shadow_iter()) {
+            *word = reg.read().bits();
+        }
+        words
+    }
+
+    /// 64-bit chip serial, FTSN3:FTSN4
+    pub fn serial(&self) -> u64 {
+        serial()
+    }
+
+    /// 128-bit unique ID, FTSN0:FTSN1:FTSN3:FTSN4
+    ///
+    /// FTSN2 is left out, it carries the DDR vendor and package code that
+    /// every chip of a kind shares.
+    pub fn unique_id(&self) -> u128 {
+        let high = ((shadow(FTSN[0]) as u64) << 32) | shadow(FTSN[1]) as u64;
+        ((high as u128) << 64) | serial() as u128
+    }
+
+    pub fn secure_boot(&self) -> SecureBoot {
+        secure_boot()
+    }
+
+    /// SHA-256 of the public key the boot ROM verifies the loader with, all
+    /// zero when no key is fused
+    pub fn kpub_hash(&self) -> [u32; 8] {
+        core::array::from_fn(|i| shadow(KPUB_HASH + i))
+    }
+
+    /// Check programming `value` into `word` and report the bits it blows,
+    /// the fuse array isn't powered
+    pub fn dry_run(&self, word: usize, value: u32) -> Result<Plan, Error> {
+        if word >= WORDS {
+            return Err(Error::WordOutOfRange(word));
+        }
+        if FTSN.contains(&word) {
+            return Err(Error::Reserved(word));
+        }
+        let current = shadow(word);
+        let cleared = current & !value;
+        if cleared != 0 {
+            return Err(Error::CannotClear { word, bits: cleared });
+        }
+        Ok(Plan {
+            word,
+            current,
+            value,
+            blow: value & !current,
+        })
+    }
+
+    /// Program `value` into fuse word `word` and verify it
+    ///
+    /// Bits already blown in `value` are skipped, see [`Efuse::dry_run`] for
+    /// the checks. The array is powered off again on every return path.
+    pub fn program(&mut self, word: usize, value: u32, _unlock: &Unlock) -> Result<Plan, Error> {
+        let plan = self.dry_run(word, value)?;
+        if plan.is_noop() {
+            return Ok(plan);
+        }
+
+        let efuse = unsafe { &*pac::EFUSE::PTR };
+        wait_ready()?;
+        command(MODE_POWER_ON);
+        // the shadow is only reloaded on request, while the array is powered
+        let programmed = blow(word, plan.blow).and_then(|()| {
+            wait_ready()?;
+            command(MODE_REFRESH_SHADOW);
+            wait_ready()
+        });
+        command(MODE_POWER_OFF);
+        let powered_off = wait_ready();
+        programmed?;
+        powered_off?;
+
+        let read = efuse.shadow(word).read().bits();
+        if read != value {
+            return Err(Error::Verify {
+                word,
+                expected: value,
+                read,
+            });
+        }
+        Ok(plan)
+    }
+}
+
+fn command(mode: u32) {
+    let efuse = unsafe { &*pac::EFUSE::PTR };
+    efuse.mode().write(|w| unsafe { w.bits(mode) });
+}
+
+fn wait_ready() -> Result<(), Error> {
+    let efuse = unsafe { &*pac::EFUSE::PTR };
+    wait(|| !efuse.status().read().busy().bit(), BUSY_TIMEOUT_US, Error::Timeout)
+}
+
+/// Blow every set bit of `bits` in both rows, the array must be powered
+fn blow(word: usize, bits: u32) -> Result<(), Error> {
+    let efuse = unsafe { &*pac::EFUSE::PTR };
+    for bit in (0..32).filter(|bit| bits & (1 << bit) != 0) {
+        for row in [false, true] {
+            wait_ready()?;
+            efuse.adr().write(|w| unsafe {
+                w.row().bit(row);
+                w.word().bits(word as u8);
+                w.bit().bits(bit)
+            });
+            command(MODE_PROGRAM);
+        }
+    }
+    wait_ready()
+}
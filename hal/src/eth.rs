@@ -0,0 +1,759 @@
+//! Ethernet, DesignWare GMAC with the internal 10/100 EPHY
+//!
+//! The MAC talks MII to the on-chip PHY, which sits at [`Config::phy_addr`] on
+//! the MAC's MDIO bus. Link state is polled, [`Ethernet::poll_link`] follows
+//! auto-negotiation and sets up the MAC for the resolved speed and duplex.
+//!
+//! Frames move through chained DMA descriptors in a user provided
+//! [`PacketQueue`], one 1536 byte buffer per frame. Descriptors and buffers are
+//! cache line aligned and maintained with [`crate::cache`], DMA is not coherent.
+//! Descriptors are in the enhanced (alternate) layout of GMAC 3.70.
+//!
+//! With the `smoltcp` feature, [`Ethernet`] implements `smoltcp::phy::Device`.
+//!
+//! The driver claims the PHY's pads: the ETH_TXP/TXM/RXP/RXM line pads and
+//! GPIO_RTX, its bias resistor. The Duo's ARM/RISC-V boot switch is wired to
+//! GPIO_RTX, that is [`peripherals::PIN_ARM_RV_SWITCH`].
+//!
+//! ```ignore
+//! static mut QUEUE: PacketQueue<4, 8> = PacketQueue::new();
+//!
+//! let queue = unsafe { &mut *core::ptr::addr_of_mut!(QUEUE) };
+//! let mut eth = Ethernet::new(
+//!     p.ETH, p.PIN_ETH_TXP, p.PIN_ETH_TXM, p.PIN_ETH_RXP, p.PIN_ETH_RXM, p.PIN_ARM_RV_SWITCH,
+//!     queue, Config::default(),
+//! )?;
+//! while eth.poll_link().is_none() {}
+//! ```
+
+use core::marker::PhantomData;
+use core::sync::atomic::{fence, Ordering};
+
+use embedded_hal::delay::DelayNs;
+
+use crate::cache;
+use crate::clocks::Gate;
+use crate::delay::{wait, Delay};
+use crate::mmio::regs;
+use crate::pinmux::{self, Conflict};
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, Peripheral};
+
+/// Largest frame without FCS, 1500 bytes payload plus header
+pub const MTU: usize = 1514;
+/// Bytes per packet buffer, frame plus FCS rounded up to cache lines
+pub const BUFFER_SIZE: usize = 1536;
+const FCS_LEN: usize = 4;
+
+// MAC_CONFIG
+const MAC_CONFIG_RE: u32 = 1 << 2;
+const MAC_CONFIG_TE: u32 = 1 << 3;
+const MAC_CONFIG_DM: u32 = 1 << 11;
+const MAC_CONFIG_FES: u32 = 1 << 14;
+/// Port select, MII
+const MAC_CONFIG_PS: u32 = 1 << 15;
+
+/// MAC_FRAME_FILTER, pass all multicast
+const MAC_FRAME_FILTER_PM: u32 = 1 << 4;
+
+// GMII_ADDR
+const GMII_ADDR_GB: u32 = 1 << 0;
+const GMII_ADDR_GW: u32 = 1 << 1;
+/// MDC = CSR clock / 124, for 250 ~ 300MHz
+const GMII_ADDR_CR_124: u32 = 0b0101 << 2;
+const GMII_ADDR_GR_OFFSET: u32 = 6;
+const GMII_ADDR_PA_OFFSET: u32 = 11;
+
+/// MAC_ADDR_HIGH, address enable
+const MAC_ADDR_HIGH_AE: u32 = 1 << 31;
+
+// DMA_BUS_MODE
+const BUS_MODE_SWR: u32 = 1 << 0;
+const BUS_MODE_PBL_8: u32 = 8 << 8;
+const BUS_MODE_FB: u32 = 1 << 16;
+const BUS_MODE_AAL: u32 = 1 << 25;
+
+// DMA_OP_MODE
+const OP_MODE_SR: u32 = 1 << 1;
+const OP_MODE_ST: u32 = 1 << 13;
+const OP_MODE_FTF: u32 = 1 << 20;
+const OP_MODE_TSF: u32 = 1 << 21;
+const OP_MODE_RSF: u32 = 1 << 25;
+
+// TDES0
+const TDES0_OWN: u32 = 1 << 31;
+const TDES0_LS: u32 = 1 << 29;
+const TDES0_FS: u32 = 1 << 28;
+const TDES0_TCH: u32 = 1 << 20;
+
+// RDES0
+const RDES0_OWN: u32 = 1 << 31;
+const RDES0_FL_OFFSET: u32 = 16;
+const RDES0_FL_MASK: u32 = 0x3fff;
+const RDES0_ES: u32 = 1 << 15;
+const RDES0_FS: u32 = 1 << 9;
+const RDES0_LS: u32 = 1 << 8;
+
+/// RDES1, second address chained
+const RDES1_RCH: u32 = 1 << 14;
+
+// PHY registers, IEEE 802.3 clause 22
+const PHY_BMCR: u8 = 0x00;
+const PHY_BMSR: u8 = 0x01;
+const PHY_ID1: u8 = 0x02;
+const PHY_ANAR: u8 = 0x04;
+const PHY_ANLPAR: u8 = 0x05;
+
+const BMCR_RESET: u16 = 1 << 15;
+const BMCR_AN_ENABLE: u16 = 1 << 12;
+const BMCR_AN_RESTART: u16 = 1 << 9;
+const BMSR_LINK: u16 = 1 << 2;
+const BMSR_AN_COMPLETE: u16 = 1 << 5;
+/// 10/100, half and full duplex, IEEE 802.3 selector
+const ANAR_ALL: u16 = 0x01e1;
+const AN_100_FULL: u16 = 1 << 8;
+const AN_100_HALF: u16 = 1 << 7;
+const AN_10_FULL: u16 = 1 << 6;
+
+// EPHY_CTRL
+const EPHY_CTRL_SHUTDOWN: u32 = 1 << 0;
+const EPHY_CTRL_ANA_RST_N: u32 = 1 << 1;
+const EPHY_CTRL_DIG_RST_N: u32 = 1 << 2;
+/// Reserved bits the vendor init keeps set
+const EPHY_CTRL_DEFAULT: u32 = 0x0900;
+
+/// EPHY_APB_RW_SEL, MII registers written over APB instead of the MAC's MDIO
+const EPHY_APB_RW_SEL_APB: u32 = 1 << 0;
+
+/// MII page holding the analog power controls
+const EPHY_PAGE_ANA: u32 = 5;
+/// Page 5 register 0x10, [13:8] power down, [7:0] enable, vendor values
+const EPHY_ANA_PD: u32 = 0x0c00;
+const EPHY_ANA_EN: u32 = 0x007e;
+
+/// Vendor OUI and model the PHY ID reads back as, they reset to 0
+const EPHY_ID1: u32 = 0x0043;
+const EPHY_ID2: u32 = 0x5649;
+
+const MDIO_TIMEOUT_US: u32 = 10_000;
+const RESET_TIMEOUT_US: u32 = 500_000;
+
+regs! {
+    pub struct Regs {
+        mac_config: 0x0000,
+        mac_frame_filter: 0x0004,
+        /// [0] busy, [1] write, [5:2] clock range, [10:6] register, [15:11] PHY
+        gmii_addr: 0x0010,
+        gmii_data: 0x0014,
+        flow_ctrl: 0x0018,
+        /// [15:0] address bytes 5, 4, [31] enable
+        mac_addr_high: 0x0040,
+        /// Address bytes 3 ~ 0
+        mac_addr_low: 0x0044,
+        dma_bus_mode: 0x1000,
+        dma_tx_poll_demand: 0x1004,
+        dma_rx_poll_demand: 0x1008,
+        dma_rx_desc_list: 0x100c,
+        dma_tx_desc_list: 0x1010,
+        dma_status: 0x1014,
+        dma_op_mode: 0x1018,
+        dma_int_enable: 0x101c,
+        dma_missed_frames: 0x1020,
+    }
+}
+
+#[inline]
+fn regs() -> Regs {
+    Regs::at(0x0407_0000)
+}
+
+regs! {
+    /// Internal PHY, its MII registers sit at 4 byte stride
+    struct EphyRegs {
+        phy_id1: 0x008,
+        phy_id2: 0x00c,
+        /// Page 5: [13:8] analog power down, [7:0] analog enable
+        ana: 0x040,
+        /// MII register 0x1f, page select
+        page: 0x07c,
+        /// [0] shutdown, [1] analog reset_n, [2] digital reset_n
+        ctrl: 0x800,
+        /// [0] APB owns the MII registers
+        apb_rw_sel: 0x804,
+    }
+}
+
+#[inline]
+fn ephy() -> EphyRegs {
+    EphyRegs::at(0x0300_9000)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// MDIO access or DMA reset didn't finish in time
+    Timeout,
+    /// Nothing answers at the PHY address
+    NoPhy,
+    /// Frame longer than [`MTU`]
+    TooLong,
+    /// All transmit descriptors are in use
+    Busy,
+    /// The EPHY_RTX pad is held by another driver
+    Pinmux(Conflict),
+}
+
+pub struct Config {
+    /// Station address, frames to other unicast addresses are dropped
+    pub mac_addr: [u8; 6],
+    /// MDIO address of the PHY
+    pub phy_addr: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            // locally administered
+            mac_addr: [0x02, 0x00, 0x00, 0x12, 0x34, 0x56],
+            phy_addr: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Speed {
+    Mbps10,
+    Mbps100,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Duplex {
+    Half,
+    Full,
+}
+
+/// Negotiated link parameters
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Link {
+    pub speed: Speed,
+    pub duplex: Duplex,
+}
+
+/// DMA descriptor, DES0 ~ DES3, alone in a cache line
+#[repr(C, align(64))]
+pub struct Descriptor([u32; 4]);
+
+impl Descriptor {
+    const NEW: Self = Self([0; 4]);
+
+    /// Read a word as the DMA left it
+    #[inline]
+    fn read(&self, word: usize) -> u32 {
+        cache::invalidate(self as *const _ as usize, size_of::<Self>());
+        unsafe { core::ptr::read_volatile(&self.0[word]) }
+    }
+
+    #[inline]
+    fn write(&mut self, word: usize, value: u32) {
+        unsafe { core::ptr::write_volatile(&mut self.0[word], value) };
+    }
+
+    /// Make the CPU's writes visible to the DMA
+    #[inline]
+    fn publish(&self) {
+        cache::clean(self as *const _ as usize, size_of::<Self>());
+    }
+}
+
+#[repr(C, align(64))]
+pub struct Packet([u8; BUFFER_SIZE]);
+
+impl Packet {
+    const NEW: Self = Self([0; BUFFER_SIZE]);
+
+    #[inline]
+    fn addr(&self) -> usize {
+        self.0.as_ptr() as usize
+    }
+}
+
+/// Descriptors and buffers for `TX` transmit and `RX` receive frames
+///
+/// Must be in DRAM below 4GiB, which all of the Duo's DRAM is.
+pub struct PacketQueue<const TX: usize, const RX: usize> {
+    tx_desc: [Descriptor; TX],
+    rx_desc: [Descriptor; RX],
+    tx_buf: [Packet; TX],
+    rx_buf: [Packet; RX],
+}
+
+impl<const TX: usize, const RX: usize> PacketQueue<TX, RX> {
+    pub const fn new() -> Self {
+        Self {
+            tx_desc: [Descriptor::NEW; TX],
+            rx_desc: [Descriptor::NEW; RX],
+            tx_buf: [Packet::NEW; TX],
+            rx_buf: [Packet::NEW; RX],
+        }
+    }
+}
+
+impl<const TX: usize, const RX: usize> Default for PacketQueue<TX, RX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct TxRing<'d> {
+    desc: &'d mut [Descriptor],
+    buf: &'d mut [Packet],
+    index: usize,
+}
+
+impl TxRing<'_> {
+    fn init(&mut self) {
+        let len = self.desc.len();
+        for i in 0..len {
+            let next = &self.desc[(i + 1) % len] as *const Descriptor as u32;
+            let buf = self.buf[i].addr() as u32;
+            let desc = &mut self.desc[i];
+            desc.write(0, TDES0_TCH);
+            desc.write(1, 0);
+            desc.write(2, buf);
+            desc.write(3, next);
+            desc.publish();
+        }
+        self.index = 0;
+        regs().dma_tx_desc_list().write(self.desc.as_ptr() as u32);
+    }
+
+    #[inline]
+    fn available(&self) -> bool {
+        self.desc[self.index].read(0) & TDES0_OWN == 0
+    }
+
+    /// Fill the next buffer with `f` and hand it to the DMA, the caller checks [`Self::available`]
+    fn send<R>(&mut self, len: usize, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        let buf = &mut self.buf[self.index].0[..len];
+        let result = f(buf);
+        cache::clean(buf.as_ptr() as usize, len);
+
+        let desc = &mut self.desc[self.index];
+        desc.write(1, len as u32);
+        desc.write(0, TDES0_OWN | TDES0_FS | TDES0_LS | TDES0_TCH);
+        desc.publish();
+        fence(Ordering::SeqCst);
+        // any write resumes a suspended transmit DMA
+        regs().dma_tx_poll_demand().write(1);
+
+        self.index = (self.index + 1) % self.desc.len();
+        result
+    }
+}
+
+struct RxRing<'d> {
+    desc: &'d mut [Descriptor],
+    buf: &'d mut [Packet],
+    index: usize,
+}
+
+impl RxRing<'_> {
+    fn init(&mut self) {
+        let len = self.desc.len();
+        for i in 0..len {
+            // zeroed statics may still be dirty, don't let them be evicted over received frames
+            cache::flush(self.buf[i].addr(), BUFFER_SIZE);
+
+            let next = &self.desc[(i + 1) % len] as *const Descriptor as u32;
+            let buf = self.buf[i].addr() as u32;
+            let desc = &mut self.desc[i];
+            desc.write(1, RDES1_RCH | BUFFER_SIZE as u32);
+            desc.write(2, buf);
+            desc.write(3, next);
+            desc.write(0, RDES0_OWN);
+            desc.publish();
+        }
+        self.index = 0;
+        regs().dma_rx_desc_list().write(self.desc.as_ptr() as u32);
+    }
+
+    /// Length of the next good frame, dropping bad ones on the way
+    fn frame(&mut self) -> Option<usize> {
+        loop {
+            let des0 = self.desc[self.index].read(0);
+            if des0 & RDES0_OWN != 0 {
+                return None;
+            }
+            let whole = RDES0_FS | RDES0_LS;
+            if des0 & RDES0_ES != 0 || des0 & whole != whole {
+                self.release();
+                continue;
+            }
+
+            let len = (((des0 >> RDES0_FL_OFFSET) & RDES0_FL_MASK) as usize).saturating_sub(FCS_LEN);
+            cache::invalidate(self.buf[self.index].addr(), len);
+            return Some(len);
+        }
+    }
+
+    /// Receive the frame [`Self::frame`] found, then give its buffer back
+    fn receive<R>(&mut self, len: usize, f: impl FnOnce(&[u8]) -> R) -> R {
+        let result = f(&self.buf[self.index].0[..len]);
+        self.release();
+        result
+    }
+
+    fn release(&mut self) {
+        let desc = &mut self.desc[self.index];
+        desc.write(0, RDES0_OWN);
+        desc.publish();
+        fence(Ordering::SeqCst);
+        // resume the receive DMA if it ran out of descriptors
+        regs().dma_rx_poll_demand().write(1);
+
+        self.index = (self.index + 1) % self.desc.len();
+    }
+}
+
+pub struct Ethernet<'d> {
+    tx: TxRing<'d>,
+    rx: RxRing<'d>,
+    phy_addr: u8,
+    link: Option<Link>,
+    phantom: PhantomData<&'d mut peripherals::ETH>,
+}
+
+impl<'d> Ethernet<'d> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<const TX: usize, const RX: usize>(
+        _peri: impl Peripheral<P = peripherals::ETH> + 'd,
+        txp: impl Peripheral<P = impl TxpPin> + 'd,
+        txn: impl Peripheral<P = impl TxnPin> + 'd,
+        rxp: impl Peripheral<P = impl RxpPin> + 'd,
+        rxn: impl Peripheral<P = impl RxnPin> + 'd,
+        rtx: impl Peripheral<P = impl RtxPin> + 'd,
+        queue: &'d mut PacketQueue<TX, RX>,
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, txp, txn, rxp, rxn, rtx);
+
+        pinmux::claim_all("ETH", || {
+            txp.try_claim(txp.alt_function(), "ETH")?;
+            txn.try_claim(txn.alt_function(), "ETH")?;
+            rxp.try_claim(rxp.alt_function(), "ETH")?;
+            rxn.try_claim(rxn.alt_function(), "ETH")?;
+            rtx.try_claim(rtx.alt_function(), "ETH")
+        })
+        .map_err(Error::Pinmux)?;
+
+        // the PHY supplies the MII clocks, it has to run before the MAC can reset
+        rcc::reset(Reset::EthPhyApb);
+        rcc::reset(Reset::EthPhy);
+        ephy_init();
+        rcc::enable_and_reset(&[Gate::Eth0_500m, Gate::Axi4Eth0], Reset::Eth0);
+
+        let mut eth = Self {
+            tx: TxRing {
+                desc: &mut queue.tx_desc,
+                buf: &mut queue.tx_buf,
+                index: 0,
+            },
+            rx: RxRing {
+                desc: &mut queue.rx_desc,
+                buf: &mut queue.rx_buf,
+                index: 0,
+            },
+            phy_addr: config.phy_addr,
+            link: None,
+            phantom: PhantomData,
+        };
+
+        let regs = regs();
+        regs.dma_bus_mode().write(BUS_MODE_SWR);
+        wait(
+            || !regs.dma_bus_mode().is_set(BUS_MODE_SWR),
+            RESET_TIMEOUT_US,
+            Error::Timeout,
+        )?;
+
+        eth.phy_init()?;
+
+        let [a0, a1, a2, a3, a4, a5] = config.mac_addr.map(u32::from);
+        regs.mac_addr_high().write(MAC_ADDR_HIGH_AE | (a5 << 8) | a4);
+        regs.mac_addr_low().write((a3 << 24) | (a2 << 16) | (a1 << 8) | a0);
+        regs.mac_frame_filter().write(MAC_FRAME_FILTER_PM);
+        regs.flow_ctrl().write(0);
+
+        regs.dma_bus_mode().write(BUS_MODE_PBL_8 | BUS_MODE_FB | BUS_MODE_AAL);
+        regs.dma_int_enable().write(0);
+        eth.tx.init();
+        eth.rx.init();
+
+        regs.dma_op_mode().write(OP_MODE_FTF);
+        wait(
+            || !regs.dma_op_mode().is_set(OP_MODE_FTF),
+            RESET_TIMEOUT_US,
+            Error::Timeout,
+        )?;
+        regs.dma_op_mode()
+            .write(OP_MODE_TSF | OP_MODE_RSF | OP_MODE_SR | OP_MODE_ST);
+        regs.mac_config()
+            .write(MAC_CONFIG_PS | MAC_CONFIG_FES | MAC_CONFIG_DM | MAC_CONFIG_TE | MAC_CONFIG_RE);
+
+        Ok(eth)
+    }
+
+    /// Reset the PHY and start auto-negotiation
+    fn phy_init(&mut self) -> Result<(), Error> {
+        if self.phy_read(PHY_ID1)? == 0xffff {
+            return Err(Error::NoPhy);
+        }
+        self.phy_write(PHY_BMCR, BMCR_RESET)?;
+        let mut result = Ok(());
+        wait(
+            || match self.phy_read(PHY_BMCR) {
+                Ok(bmcr) => bmcr & BMCR_RESET == 0,
+                Err(e) => {
+                    result = Err(e);
+                    true
+                }
+            },
+            RESET_TIMEOUT_US,
+            Error::Timeout,
+        )?;
+        result?;
+
+        self.phy_write(PHY_ANAR, ANAR_ALL)?;
+        self.phy_write(PHY_BMCR, BMCR_AN_ENABLE | BMCR_AN_RESTART)
+    }
+
+    /// Read a clause 22 PHY register
+    pub fn phy_read(&mut self, reg: u8) -> Result<u16, Error> {
+        let regs = regs();
+        regs.gmii_addr().write(self.gmii_addr(reg) | GMII_ADDR_GB);
+        wait(
+            || !regs.gmii_addr().is_set(GMII_ADDR_GB),
+            MDIO_TIMEOUT_US,
+            Error::Timeout,
+        )?;
+        Ok(regs.gmii_data().read() as u16)
+    }
+
+    /// Write a clause 22 PHY register
+    pub fn phy_write(&mut self, reg: u8, value: u16) -> Result<(), Error> {
+        let regs = regs();
+        regs.gmii_data().write(value as u32);
+        regs.gmii_addr()
+            .write(self.gmii_addr(reg) | GMII_ADDR_GW | GMII_ADDR_GB);
+        wait(
+            || !regs.gmii_addr().is_set(GMII_ADDR_GB),
+            MDIO_TIMEOUT_US,
+            Error::Timeout,
+        )
+    }
+
+    #[inline]
+    fn gmii_addr(&self, reg: u8) -> u32 {
+        ((self.phy_addr as u32 & 0x1f) << GMII_ADDR_PA_OFFSET)
+            | ((reg as u32 & 0x1f) << GMII_ADDR_GR_OFFSET)
+            | GMII_ADDR_CR_124
+    }
+
+    /// Check the PHY, on a new link match the MAC to the negotiated speed and duplex.
+    ///
+    /// Call periodically, frames are neither sent nor received while the link is down.
+    pub fn poll_link(&mut self) -> Option<Link> {
+        // link status latches low, the first read clears an old drop
+        let _ = self.phy_read(PHY_BMSR);
+        let link = match self.phy_read(PHY_BMSR) {
+            Ok(bmsr) if bmsr & BMSR_LINK != 0 && bmsr & BMSR_AN_COMPLETE != 0 => self.negotiated(),
+            _ => None,
+        };
+
+        if link != self.link {
+            if let Some(link) = link {
+                let mut config = regs().mac_config().read() & !(MAC_CONFIG_FES | MAC_CONFIG_DM);
+                if link.speed == Speed::Mbps100 {
+                    config |= MAC_CONFIG_FES;
+                }
+                if link.duplex == Duplex::Full {
+                    config |= MAC_CONFIG_DM;
+                }
+                regs().mac_config().write(config);
+            }
+            self.link = link;
+        }
+        link
+    }
+
+    /// Best mode both ends advertise
+    fn negotiated(&mut self) -> Option<Link> {
+        let common = self.phy_read(PHY_ANAR).ok()? & self.phy_read(PHY_ANLPAR).ok()?;
+        let (speed, duplex) = match common {
+            c if c & AN_100_FULL != 0 => (Speed::Mbps100, Duplex::Full),
+            c if c & AN_100_HALF != 0 => (Speed::Mbps100, Duplex::Half),
+            c if c & AN_10_FULL != 0 => (Speed::Mbps10, Duplex::Full),
+            _ => (Speed::Mbps10, Duplex::Half),
+        };
+        Some(Link { speed, duplex })
+    }
+
+    /// Link as of the last [`Self::poll_link`]
+    #[inline]
+    pub fn link(&self) -> Option<Link> {
+        self.link
+    }
+
+    #[inline]
+    pub fn is_link_up(&self) -> bool {
+        self.link.is_some()
+    }
+
+    /// Queue `frame` for transmission, without FCS
+    pub fn try_send(&mut self, frame: &[u8]) -> Result<(), Error> {
+        if frame.len() > MTU {
+            return Err(Error::TooLong);
+        }
+        if !self.tx.available() {
+            return Err(Error::Busy);
+        }
+        self.tx.send(frame.len(), |buf| buf.copy_from_slice(frame));
+        Ok(())
+    }
+
+    /// Copy the next received frame into `buf`, returning the frame length.
+    ///
+    /// A frame longer than `buf` is truncated.
+    pub fn try_receive(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let len = self.rx.frame()?;
+        Some(self.rx.receive(len, |frame| {
+            let n = frame.len().min(buf.len());
+            buf[..n].copy_from_slice(&frame[..n]);
+            len
+        }))
+    }
+
+    /// Frames dropped for lack of receive descriptors, since the last call
+    pub fn missed_frames(&self) -> u32 {
+        regs().dma_missed_frames().read() & 0xffff
+    }
+}
+
+impl Drop for Ethernet<'_> {
+    fn drop(&mut self) {
+        let regs = regs();
+        regs.dma_op_mode().clear_bits(OP_MODE_SR | OP_MODE_ST);
+        regs.mac_config().clear_bits(MAC_CONFIG_TE | MAC_CONFIG_RE);
+        rcc::disable(&[Gate::Eth0_500m, Gate::Axi4Eth0], Reset::Eth0);
+        ephy().ctrl().write(EPHY_CTRL_DEFAULT | EPHY_CTRL_SHUTDOWN);
+        pinmux::release_owner("ETH");
+    }
+}
+
+/// Power up the PHY's analog front end, it comes out of reset shut down.
+///
+/// Follows the vendor PHY driver: release the digital reset so the MII
+/// registers respond, enable the analog blocks on page 5, let the PLL settle
+/// and release the analog reset. The ID registers read 0 until written.
+fn ephy_init() {
+    let ephy = ephy();
+    let mut delay = Delay::new();
+
+    ephy.apb_rw_sel().write(EPHY_APB_RW_SEL_APB);
+    ephy.ctrl().write(EPHY_CTRL_DEFAULT);
+    ephy.ctrl().write(EPHY_CTRL_DEFAULT | EPHY_CTRL_DIG_RST_N);
+
+    ephy.page().write(EPHY_PAGE_ANA << 8);
+    ephy.ana().write(EPHY_ANA_PD);
+    ephy.ana().write(EPHY_ANA_PD | EPHY_ANA_EN);
+    delay.delay_ms(1);
+    ephy.ctrl()
+        .write(EPHY_CTRL_DEFAULT | EPHY_CTRL_DIG_RST_N | EPHY_CTRL_ANA_RST_N);
+
+    ephy.page().write(EPHY_PAGE_ANA << 8);
+    ephy.phy_id1().write(EPHY_ID1);
+    ephy.phy_id2().write(EPHY_ID2);
+    // hand the MII registers back to the MAC's MDIO
+    ephy.apb_rw_sel().write(0);
+}
+
+pin_trait!(TxpPin);
+pin_trait!(TxnPin);
+pin_trait!(RxpPin);
+pin_trait!(RxnPin);
+// EPHY_RTX, the bias resistor of the PHY
+pin_trait!(RtxPin);
+
+#[cfg(feature = "smoltcp")]
+mod phy {
+    use smoltcp::phy::{self, ChecksumCapabilities, DeviceCapabilities, Medium};
+    use smoltcp::time::Instant;
+
+    use super::{Ethernet, RxRing, TxRing, MTU};
+
+    pub struct RxToken<'a, 'd> {
+        rx: &'a mut RxRing<'d>,
+        len: usize,
+    }
+
+    impl phy::RxToken for RxToken<'_, '_> {
+        fn consume<R, F>(self, f: F) -> R
+        where
+            F: FnOnce(&[u8]) -> R,
+        {
+            self.rx.receive(self.len, f)
+        }
+    }
+
+    pub struct TxToken<'a, 'd> {
+        tx: &'a mut TxRing<'d>,
+    }
+
+    impl phy::TxToken for TxToken<'_, '_> {
+        /// A frame longer than [`MTU`] is cut to it, smoltcp doesn't build
+        /// one as the capabilities report the MTU
+        fn consume<R, F>(self, len: usize, f: F) -> R
+        where
+            F: FnOnce(&mut [u8]) -> R,
+        {
+            self.tx.send(len.min(MTU), f)
+        }
+    }
+
+    impl<'d> phy::Device for Ethernet<'d> {
+        type RxToken<'a>
+            = RxToken<'a, 'd>
+        where
+            Self: 'a;
+        type TxToken<'a>
+            = TxToken<'a, 'd>
+        where
+            Self: 'a;
+
+        fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+            if self.link.is_none() || !self.tx.available() {
+                return None;
+            }
+            let len = self.rx.frame()?;
+            Some((RxToken { rx: &mut self.rx, len }, TxToken { tx: &mut self.tx }))
+        }
+
+        fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+            if self.link.is_none() || !self.tx.available() {
+                return None;
+            }
+            Some(TxToken { tx: &mut self.tx })
+        }
+
+        fn capabilities(&self) -> DeviceCapabilities {
+            let mut caps = DeviceCapabilities::default();
+            caps.medium = Medium::Ethernet;
+            caps.max_transmission_unit = MTU;
+            caps.max_burst_size = Some(self.tx.desc.len());
+            caps.checksum = ChecksumCapabilities::default();
+            caps
+        }
+    }
+}
+
+#[cfg(feature = "smoltcp")]
+pub use phy::{RxToken, TxToken};
@@ -1,7 +1,7 @@
 //! GPIO
 
 use crate::gpio::sealed::Pin as _Pin;
-use crate::{impl_peripheral, into_ref, pac, peripherals, Peripheral, PeripheralRef};
+use crate::{impl_peripheral, into_ref, pac, peripherals, pinmux, Peripheral, PeripheralRef};
 
 /// Represents a digital input or output level.
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -50,20 +50,89 @@ pub enum SlewRate {
     Slow = 1,
 }
 
+/// Pad function, the value of its FMUX `FUNC_SEL`
+///
+/// What each one routes to depends on the pad, see [`Pin::pad_info`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum AltFunction {
+    F0 = 0,
+    F1 = 1,
+    F2 = 2,
+    F3 = 3,
+    F4 = 4,
+    F5 = 5,
+    F6 = 6,
+    F7 = 7,
+}
+
+impl AltFunction {
+    /// From the low 3 bits of `FUNC_SEL`
+    pub const fn from_bits(bits: u8) -> Self {
+        match bits & 0b111 {
+            0 => Self::F0,
+            1 => Self::F1,
+            2 => Self::F2,
+            3 => Self::F3,
+            4 => Self::F4,
+            5 => Self::F5,
+            6 => Self::F6,
+            _ => Self::F7,
+        }
+    }
+}
+
+/// Pinmux entry of a pad, from `svd/pinmux.csv`
+#[derive(Debug)]
+pub struct PadInfo {
+    /// Pad name in the TRM, without the `PAD_` prefix
+    pub name: &'static str,
+    /// Signal of each [`AltFunction`], `None` where reserved
+    pub functions: [Option<&'static str>; 8],
+    pub(crate) fmux: usize,
+    pub(crate) gpio: Option<AltFunction>,
+    /// What `sealed::Pin::pad_pin_io_num` returns for the pad, `None` for
+    /// pads without a GPIO, which have no pin
+    pub(crate) pad_pin_io_num: Option<u32>,
+}
+
+impl PadInfo {
+    /// Signal routed to the pad by `func`
+    pub fn function(&self, func: AltFunction) -> Option<&'static str> {
+        self.functions[func as usize]
+    }
+
+    /// Function that makes the pad a GPIO, `None` for pads without one
+    /// (PWR_VBAT_DET, PWR_RSTN, XTAL_XIN)
+    pub fn gpio_function(&self) -> Option<AltFunction> {
+        self.gpio
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/pad_functions.rs"));
+
 /// GPIO flexible pin.
 pub struct Flex<'d> {
     pin: PeripheralRef<'d, AnyPin>,
 }
 
 impl<'d> Flex<'d> {
-    /// Wrap the pin in a `Flex`.
+    /// Wrap the pin in a `Flex`, unless another driver holds the pad.
     #[inline]
-    pub fn new(pin: impl Peripheral<P = impl Pin> + 'd) -> Self {
+    pub fn new(pin: impl Peripheral<P = impl Pin> + 'd) -> Result<Self, pinmux::Conflict> {
+        Self::new_for(pin, "GPIO")
+    }
+
+    /// Wrap a pin that a driver uses as a GPIO, claimed for `owner`
+    pub(crate) fn new_for(
+        pin: impl Peripheral<P = impl Pin> + 'd,
+        owner: &'static str,
+    ) -> Result<Self, pinmux::Conflict> {
         into_ref!(pin);
 
-        pin.set_as_gpio();
+        pin.try_claim(pin.gpio_function(), owner)?;
 
-        Self { pin: pin.map_into() }
+        Ok(Self { pin: pin.map_into() })
     }
 
     #[inline]
@@ -135,6 +204,12 @@ impl<'d> Flex<'d> {
     }
 }
 
+impl Drop for Flex<'_> {
+    fn drop(&mut self) {
+        self.pin.release();
+    }
+}
+
 pub(crate) mod sealed {
     use super::*;
 
@@ -179,16 +254,20 @@ pub(crate) mod sealed {
             pinmux.pad(self._pad())
         }
 
+        /// IOBLK register of the pad, `None` for pads without pull/drive control
         #[inline]
-        fn ctrl(&self) -> &'static pac::ioblk_g1::PIN {
-            match self._group() {
-                1 => unsafe { &*pac::IOBLK_G1::PTR }.pin(self._pin()),
-                7 => unsafe { &*pac::IOBLK_G7::PTR }.pin(self._pin()),
-                10 => unsafe { &*pac::IOBLK_G10::PTR }.pin(self._pin()),
-                12 => unsafe { &*pac::IOBLK_G12::PTR }.pin(self._pin()),
-                13 => unsafe { &*pac::IOBLK_RTC::PTR }.pin(self._pin()),
-                _ => unreachable!(),
-            }
+        fn ctrl(&self) -> Option<&'static pac::ioblk_g1::PIN> {
+            let pin = self._pin();
+            Some(match self._group() {
+                0 => return None,
+                1 => unsafe { &*pac::IOBLK_G1::PTR }.pin(pin),
+                7 => unsafe { &*pac::IOBLK_G7::PTR }.pin(pin),
+                10 => unsafe { &*pac::IOBLK_G10::PTR }.pin(pin),
+                12 => unsafe { &*pac::IOBLK_G12::PTR }.pin(pin),
+                13 => unsafe { &*pac::IOBLK_RTC::PTR }.pin(pin),
+                // build.rs rejects other groups
+                _ => return None,
+            })
         }
 
         #[inline]
@@ -225,26 +304,64 @@ pub trait Pin: Peripheral<P = Self> + Into<AnyPin> + sealed::Pin + Sized + 'stat
         self._ioport() as u8
     }
 
+    /// Name and functions of the pad
+    fn pad_info(&self) -> &'static PadInfo {
+        let index = PADS
+            .binary_search_by_key(&self._pad(), |pad| pad.fmux)
+            .expect("every pin is a pad of svd/pads.csv, AnyPin::steal checks it");
+        &PADS[index]
+    }
+
+    /// Function that makes the pad a GPIO, F3 on most pads but F0 on PWR_GPIO0 ~ 2
+    fn gpio_function(&self) -> AltFunction {
+        self.pad_info()
+            .gpio
+            .expect("pins are only generated for pads with a GPIO")
+    }
+
     /// Set pinmux
     #[inline]
-    fn set_alt_function(&self, func: u8) {
-        self.fmux().func_sel().write(|w| w.value().variant(func));
+    fn set_alt_function(&self, func: AltFunction) {
+        self.fmux().func_sel().write(|w| w.value().variant(func as u8));
+    }
+
+    /// Function currently selected on the pad
+    #[inline]
+    fn current_function(&self) -> AltFunction {
+        pinmux::current_function(self.pad_info())
     }
 
+    /// Route `func` to the pad on behalf of `owner`, unless another driver
+    /// holds it, see [`pinmux`]
+    fn try_claim(&self, func: AltFunction, owner: &'static str) -> Result<(), pinmux::Conflict> {
+        pinmux::claim(self.pad_info(), pinmux::Claim { function: func, owner })?;
+        self.set_alt_function(func);
+        Ok(())
+    }
+
+    /// Give the pad up, the function stays selected
+    fn release(&self) {
+        pinmux::release(self.pad_info());
+    }
+
+    /// Function and driver recorded by the last [`Pin::try_claim`]
+    fn owner(&self) -> Option<pinmux::Claim> {
+        pinmux::owner(self.pad_info())
+    }
+
+    /// Select the GPIO function, see [`Pin::gpio_function`]
     #[inline]
     fn set_as_gpio(&self) {
-        // PWR_GPIO[0], PWR_GPIO[1], PWR_GPIO[2] are special case for alt functions settings
-        // On MilkV Duo 250m board, PWR_GPIO[2] is used for LED, the other two are NC
-        if self._ioport() == 4 && self._ionum() <= 2 {
-            self.set_alt_function(0);
-        } else {
-            self.set_alt_function(3); // XGPIO[x] alt functions
-        }
+        self.set_alt_function(self.gpio_function());
     }
 
+    /// Set the pad pull, does nothing on pads without IOBLK control (the audio and ETH pads)
     #[inline]
     fn set_pull(&self, pull: Pull) {
-        self.ctrl().iocfg().modify(|_, w| match pull {
+        let Some(ctrl) = self.ctrl() else {
+            return;
+        };
+        ctrl.iocfg().modify(|_, w| match pull {
             Pull::None => w.pu().clear_bit().pd().clear_bit(),
             Pull::Up => w.pu().set_bit().pd().clear_bit(),
             Pull::Down => w.pu().clear_bit().pd().set_bit(),
@@ -258,13 +375,16 @@ pub struct AnyPin {
 }
 
 impl AnyPin {
-    /// Unsafely create a new type-erased pin.
+    /// Unsafely create a new type-erased pin, `None` if `pad_pin_io_num` is
+    /// not what a pad singleton returns
     ///
     /// # Safety
     ///
     /// You must ensure that you’re only using one instance of this type at a time.
-    pub unsafe fn steal(pad_pin_io_num: u32) -> Self {
-        Self { pad_pin_io_num }
+    pub unsafe fn steal(pad_pin_io_num: u32) -> Option<Self> {
+        PADS.iter()
+            .any(|pad| pad.pad_pin_io_num == Some(pad_pin_io_num))
+            .then_some(Self { pad_pin_io_num })
     }
 }
 
@@ -295,55 +415,5 @@ macro_rules! impl_pin {
     };
 }
 
-// PIN, FMUX_PAD, IOBLK_GROUP, IOBLK_PIN, IO_PORT, IO_PIN
-impl_pin!(PIN_0, 0x70 / 4, 7, 0x3c / 4, 0, 28); // 3 : XGPIOA[28], IIC0_SCL
-impl_pin!(PIN_1, 0x74 / 4, 7, 0x40 / 4, 0, 29); // 3 : XGPIOA[29], IIC0_SDA
-impl_pin!(PIN_2, 0x64 / 4, 7, 0x30 / 4, 0, 19); // 3 : XGPIOA[19], JTAG_CPU_TMS
-impl_pin!(PIN_3, 0x68 / 4, 7, 0x34 / 4, 0, 18); // 3 : XGPIOA[18], JTAG_CPU_TCK
-
-impl_pin!(PIN_4, 0xD4 / 4, 13, 0x5C / 4, 4, 19); // 3 : PWR_GPIO[19], SD1_D2
-impl_pin!(PIN_5, 0xD8 / 4, 13, 0x60 / 4, 4, 20); // 3 : PWR_GPIO[20], SD1_D1
-impl_pin!(PIN_6, 0xE4 / 4, 13, 0x6C / 4, 4, 23); // 3 : PWR_GPIO[23], SD1_CLK
-impl_pin!(PIN_7, 0xE9 / 4, 13, 0x68 / 4, 4, 22); // 3 : PWR_GPIO[22], SD1_CMD
-impl_pin!(PIN_8, 0xDC / 4, 13, 0x64 / 4, 4, 21); // 3 : PWR_GPIO[21], SD1_D0
-impl_pin!(PIN_9, 0xD0 / 4, 13, 0x58 / 4, 4, 18); // 3 : PWR_GPIO[18], SD1_D3
-
-impl_pin!(PIN_10, 0x1AC / 4, 12, 0x78 / 4, 2, 14); // 3 : XGPIOC[14], I2C_SDA, PAD_MIPI_TXM1
-impl_pin!(PIN_11, 0x1B0 / 4, 12, 0x7C / 4, 2, 15); // 3 : XGPIOC[15], I2C_SCL, PAD_MIPI_TXP1
-
-impl_pin!(PIN_12, 0x40 / 4, 7, 0x0C / 4, 0, 16); // 3 : XGPIOA[16], UART0_TX
-impl_pin!(PIN_13, 0x44 / 4, 7, 0x10 / 4, 0, 17); // 3 : XGPIOA[17], UART0_RX
-
-impl_pin!(PIN_14, 0x38 / 4, 7, 0x04 / 4, 0, 14); // 3 : XGPIOA[14], SD0_PWR_EN
-impl_pin!(PIN_15, 0x3C / 4, 7, 0x08 / 4, 0, 15); // 3 : XGPIOA[15], SPK_EN
-
-impl_pin!(PIN_16, 0x5C / 4, 7, 0x28 / 4, 0, 23); // 3 : XGPIOA[23], EMMC_CMD
-impl_pin!(PIN_17, 0x60 / 4, 7, 0x2C / 4, 0, 24); // 3 : XGPIOA[24], EMMC_DAT1
-
-impl_pin!(PIN_18, 0x50 / 4, 7, 0x1C / 4, 0, 22); // 3 : XGPIOA[22], EMMC_CLK
-impl_pin!(PIN_19, 0x54 / 4, 7, 0x20 / 4, 0, 25); // 3 : XGPIOA[25], EMMC_DAT0
-impl_pin!(PIN_20, 0x58 / 4, 7, 0x24 / 4, 0, 27); // 3 : XGPIOA[27], EMMC_DAT3
-impl_pin!(PIN_21, 0x4C / 4, 7, 0x18 / 4, 0, 26); // 3 : XGPIOA[26], EMMC_DAT2
-impl_pin!(PIN_22, 0x88 / 4, 13, 0x0C / 4, 4, 4); // 3 : PWR_GPIO[4], PWR_SEQ2
-
-impl_pin!(PIN_25, 0xAC / 4, 13, 0x34 / 4, 4, 2); // 0: PWR_GPIO[2], LED
-
-// Audio pins
-impl_pin!(PIN_MIC_IN, 0x1BC / 4, 0, 0x00 / 4, 2, 23); // no ioblk
-impl_pin!(PIN_AUDIO_OUT, 0x1C8 / 4, 0, 0x00 / 4, 2, 24); // no ioblk
-
-// SD card, SDIO0
-impl_pin!(PIN_SD0_CLK, 0x1C / 4, 10, 0x00 / 4, 0, 7); // 3 : XGPIOA[7], SD0_CLK
-impl_pin!(PIN_SD0_CMD, 0x20 / 4, 10, 0x04 / 4, 0, 8); // 3 : XGPIOA[8], SD0_CMD
-impl_pin!(PIN_SD0_D0, 0x24 / 4, 10, 0x08 / 4, 0, 9); // 3 : XGPIOA[9], SD0_D0
-impl_pin!(PIN_SD0_D1, 0x28 / 4, 10, 0x0C / 4, 0, 10); // 3 : XGPIOA[10], SD0_D1
-impl_pin!(PIN_SD0_D2, 0x2C / 4, 10, 0x10 / 4, 0, 11); // 3 : XGPIOA[11], SD0_D2
-impl_pin!(PIN_SD0_D3, 0x30 / 4, 10, 0x14 / 4, 0, 12); // 3 : XGPIOA[12], SD0_D3
-impl_pin!(PIN_SD0_CD, 0x34 / 4, 7, 0x00 / 4, 0, 13); // 3 : XGPIOA[13], SD0_CD
-
-// ARM RV SWITCH pin, pulled up externally
-impl_pin!(PIN_ARM_RV_SWITCH, 0x1CC / 4, 12, 0x8C / 4, 1, 23); // 3 : XGPIOB[23], ARM_RV_SWITCH, GPIO_RTX___EPHY_RTX
-
-// 1V8 domain
-impl_pin!(PIN_26, 0xF8 / 4, 1, 0x10 / 4, 1, 3); // 3 : XGPIOB[3], ADC1
-impl_pin!(PIN_27, 0x108 / 4, 1, 0x20 / 4, 1, 6); // 3 : XGPIOB[6], ADC2, USB_VBUS_DET
+// Every pad with a GPIO, generated from svd/pads.csv
+include!(concat!(env!("OUT_DIR"), "/pad_pins.rs"));
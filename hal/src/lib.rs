@@ -4,16 +4,38 @@ pub use milkv_duo_pac as pac;
 pub use peripheral::*;
 
 // macros come first
+mod mmio;
 mod traits;
 
+pub mod adc;
+pub mod audio;
+#[cfg(any(feature = "board-duo", feature = "board-duo256m", feature = "board-duos"))]
+pub mod board;
+pub mod cache;
+pub mod camera;
+pub mod clocks;
+pub mod delay;
+pub mod dma;
+pub mod efuse;
+pub mod eth;
 pub mod gpio;
+pub mod pwm;
+pub mod rcc;
+pub mod rtc;
+pub mod sdmmc;
+pub mod spi;
+pub mod timer;
 pub mod uart;
+pub mod usb;
+pub mod wdt;
 
 // pub mod ddr;
 pub mod signature;
 
 mod peripheral;
 pub mod peripherals;
+pub mod pinmux;
+pub mod plic;
 pub mod sbi;
 
 pub mod rom_api {
@@ -48,6 +70,28 @@ pub mod rom_api {
     }
 }
 
+/// Take the peripherals, after [`board::verify`] if a board is selected.
+///
+/// Panics if the chip doesn't match the board, see [`try_init`].
 pub fn init() -> peripherals::Peripherals {
+    #[cfg(any(feature = "board-duo", feature = "board-duo256m", feature = "board-duos"))]
+    if let Err(mismatch) = board::verify() {
+        panic!("firmware built for {}, found {:x?}", board::NAME, mismatch);
+    }
+
+    take()
+}
+
+/// Take the peripherals if the chip matches the board the firmware was built for
+#[cfg(any(feature = "board-duo", feature = "board-duo256m", feature = "board-duos"))]
+pub fn try_init() -> Result<peripherals::Peripherals, board::Mismatch> {
+    board::verify()?;
+    Ok(take())
+}
+
+fn take() -> peripherals::Peripherals {
+    #[cfg(feature = "time-driver")]
+    timer::time_driver::init();
+
     peripherals::Peripherals::take()
 }
@@ -0,0 +1,137 @@
+//! Raw register access for blocks that are not described in the SVD yet.
+//!
+//! The accessors mirror the `read`/`write`/`modify` shape of the PAC so that
+//! drivers read the same whether a block comes from `pac` or from here.
+
+use core::ptr;
+
+/// A single 32-bit memory mapped register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reg(usize);
+
+#[allow(unused)]
+impl Reg {
+    #[inline(always)]
+    pub const fn at(addr: usize) -> Self {
+        Self(addr)
+    }
+
+    #[inline(always)]
+    pub const fn addr(self) -> usize {
+        self.0
+    }
+
+    #[inline(always)]
+    pub fn read(self) -> u32 {
+        unsafe { ptr::read_volatile(self.0 as *const u32) }
+    }
+
+    #[inline(always)]
+    pub fn write(self, val: u32) {
+        unsafe { ptr::write_volatile(self.0 as *mut u32, val) }
+    }
+
+    #[inline(always)]
+    pub fn modify(self, f: impl FnOnce(u32) -> u32) {
+        self.write(f(self.read()))
+    }
+
+    #[inline(always)]
+    pub fn set_bits(self, mask: u32) {
+        self.modify(|r| r | mask)
+    }
+
+    #[inline(always)]
+    pub fn clear_bits(self, mask: u32) {
+        self.modify(|r| r & !mask)
+    }
+
+    /// Replace the `width` bits field at `offset`.
+    #[inline(always)]
+    pub fn write_field(self, offset: u32, width: u32, val: u32) {
+        let mask = field_mask(width) << offset;
+        self.modify(|r| (r & !mask) | ((val << offset) & mask))
+    }
+
+    #[inline(always)]
+    pub fn read_field(self, offset: u32, width: u32) -> u32 {
+        (self.read() >> offset) & field_mask(width)
+    }
+
+    #[inline(always)]
+    pub fn is_set(self, mask: u32) -> bool {
+        self.read() & mask != 0
+    }
+}
+
+#[inline(always)]
+const fn field_mask(width: u32) -> u32 {
+    if width >= 32 {
+        u32::MAX
+    } else {
+        (1 << width) - 1
+    }
+}
+
+/// Declare a register block as a set of offsets from a base address.
+///
+/// ```ignore
+/// regs! {
+///     /// PWM controller
+///     pub struct Regs {
+///         polarity: 0x40,
+///         period[4]: 0x04 / 0x08,
+///     }
+/// }
+/// ```
+macro_rules! regs {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$fmeta:meta])*
+                $reg:ident $([$dim:expr])? : $offset:literal $(/ $stride:literal)?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug)]
+        $vis struct $name {
+            base: usize,
+        }
+
+        #[allow(unused)]
+        impl $name {
+            #[inline(always)]
+            pub const fn at(base: usize) -> Self {
+                Self { base }
+            }
+
+            #[inline(always)]
+            pub const fn base(&self) -> usize {
+                self.base
+            }
+
+            $(
+                $crate::mmio::regs!(@reg $(#[$fmeta])* $reg $([$dim])? : $offset $(/ $stride)?);
+            )*
+        }
+    };
+    (@reg $(#[$fmeta:meta])* $reg:ident : $offset:literal) => {
+        $(#[$fmeta])*
+        #[inline(always)]
+        pub fn $reg(&self) -> $crate::mmio::Reg {
+            $crate::mmio::Reg::at(self.base + $offset)
+        }
+    };
+    (@reg $(#[$fmeta:meta])* $reg:ident [$dim:expr] : $offset:literal / $stride:literal) => {
+        $(#[$fmeta])*
+        #[inline(always)]
+        pub fn $reg(&self, n: usize) -> $crate::mmio::Reg {
+            debug_assert!(n < $dim);
+            $crate::mmio::Reg::at(self.base + $offset + n * $stride)
+        }
+    };
+}
+
+pub(crate) use regs;
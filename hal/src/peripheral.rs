@@ -274,6 +274,7 @@ mod peripheral_macros {
 
             // expose the new structs
             $(
+                $(#[$cfg])?
                 pub use peripherals::$name;
             )*
         }
@@ -312,6 +313,7 @@ mod peripheral_macros {
                 }
             }
 
+            $(#[$cfg])?
             impl $crate::peripheral::Peripheral for $name {
                 type P = $name;
 
@@ -321,6 +323,7 @@ mod peripheral_macros {
                 }
             }
 
+            $(#[$cfg])?
             impl $crate::peripheral::sealed::Sealed for $name {}
         };
         ($(#[$cfg:meta])? $name:ident <= $base:ident) => {
@@ -351,6 +354,7 @@ mod peripheral_macros {
                 }
             }
 
+            $(#[$cfg])?
             impl core::ops::Deref for $name {
                 type Target = <super::pac::$base as core::ops::Deref>::Target;
 
@@ -359,6 +363,7 @@ mod peripheral_macros {
                 }
             }
 
+            $(#[$cfg])?
             impl core::ops::DerefMut for $name {
 
                 fn deref_mut(&mut self) -> &mut Self::Target {
@@ -366,6 +371,7 @@ mod peripheral_macros {
                 }
             }
 
+            $(#[$cfg])?
             impl $crate::peripheral::Peripheral for $name {
                 type P = $name;
 
@@ -375,6 +381,7 @@ mod peripheral_macros {
                 }
             }
 
+            $(#[$cfg])?
             impl $crate::peripheral::sealed::Sealed for $name {}
         };
     }
@@ -2,63 +2,62 @@ use crate::pac;
 
 // We need to export this in the hal for the drivers to use
 
-crate::peripherals! {
+// The pad singletons, PIN_*, are appended from svd/pads.csv
+include!(concat!(env!("OUT_DIR"), "/pad_peripherals.rs"));
+
+peripherals_with_pads! {
     UART0 <= UART0,
     UART1 <= UART1,
     UART2 <= UART2,
     UART3 <= UART3,
     UART4 <= UART4,
 
-
-    PIN_0 <= virtual,
-    PIN_1 <= virtual,
-    PIN_2 <= virtual,
-    PIN_3 <= virtual,
-    PIN_4 <= virtual,
-    PIN_5 <= virtual,
-    PIN_6 <= virtual,
-    PIN_7 <= virtual,
-    PIN_8 <= virtual,
-    PIN_9 <= virtual,
-    PIN_10 <= virtual,
-    PIN_11 <= virtual,
-    PIN_12 <= virtual,
-    PIN_13 <= virtual,
-    PIN_14 <= virtual,
-    PIN_15 <= virtual,
-    PIN_16 <= virtual,
-    PIN_17 <= virtual,
-    PIN_18 <= virtual,
-    PIN_19 <= virtual,
-    PIN_20 <= virtual,
-    PIN_21 <= virtual,
-    PIN_22 <= virtual,
-
-    PIN_25 <= virtual, // LED
-
-    PIN_26 <= virtual, // ADC1
-    PIN_27 <= virtual, // USB_VBUS_DET
-
-    PIN_MIC_IN <= virtual,
-    PIN_AUDIO_OUT <= virtual,
-
-    // CSI
-    PIN_MIPI0_DN0 <= virtual,
-    PIN_MIPI0_DP0 <= virtual,
-    PIN_MIPI0_DN1 <= virtual,
-    PIN_MIPI0_DP1 <= virtual,
-    PIN_MIPI0_CKN <= virtual,
-    PIN_MIPI0_CKP <= virtual,
-    PIN_SENSOR_RSTN <= virtual,
-    PIN_SENSOR_CLK <= virtual,
-
-    PIN_ARM_RV_SWITCH <= virtual,
-
-    PIN_SD0_CD <= virtual,
-    PIN_SD0_D0 <= virtual,
-    PIN_SD0_D1 <= virtual,
-    PIN_SD0_D2 <= virtual,
-    PIN_SD0_D3 <= virtual,
-    PIN_SD0_CMD <= virtual,
-    PIN_SD0_CLK <= virtual,
+    PWM0 <= virtual,
+    PWM1 <= virtual,
+    PWM2 <= virtual,
+    PWM3 <= virtual,
+
+    SARADC <= virtual,
+    RTC_SARADC <= virtual,
+
+    WDT <= virtual,
+    EFUSE <= EFUSE,
+    RTC <= virtual,
+
+    TIMER0 <= virtual,
+    TIMER1 <= virtual,
+    TIMER2 <= virtual,
+    TIMER3 <= virtual,
+    TIMER4 <= virtual,
+    TIMER5 <= virtual,
+    #[cfg(not(feature = "time-driver"))]
+    TIMER6 <= virtual,
+    #[cfg(not(feature = "time-driver"))]
+    TIMER7 <= virtual,
+
+    SPI0 <= virtual,
+    SPI1 <= virtual,
+    SPI2 <= virtual,
+    SPI3 <= virtual,
+
+    DMA_CH0 <= virtual,
+    DMA_CH1 <= virtual,
+    DMA_CH2 <= virtual,
+    DMA_CH3 <= virtual,
+    DMA_CH4 <= virtual,
+    DMA_CH5 <= virtual,
+    DMA_CH6 <= virtual,
+    DMA_CH7 <= virtual,
+
+    AUDIO_ADC <= virtual,
+    AUDIO_DAC <= virtual,
+
+    CAMERA <= virtual,
+
+    ETH <= virtual,
+    USB <= virtual,
+
+    EMMC <= virtual,
+    SDIO0 <= virtual,
+    SDIO1 <= virtual,
 }
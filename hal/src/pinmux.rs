@@ -0,0 +1,108 @@
+//! Pinmux registry
+//!
+//! Drivers claim the pads they route with [`Pin::try_claim`](crate::gpio::Pin::try_claim),
+//! so a second driver binding a pad that is still in use fails with a
+//! [`Conflict`] instead of silently rewriting its `FUNC_SEL`. Claims are
+//! dropped with the driver, or by hand with [`Pin::release`](crate::gpio::Pin::release).
+//!
+//! [`dump`] prints the function and owner of every pad on the debug UART.
+
+use core::cell::RefCell;
+use core::fmt;
+
+use critical_section::Mutex;
+
+use crate::gpio::{AltFunction, PadInfo, PADS, PAD_COUNT};
+use crate::pac;
+
+/// Function routed to a pad and the driver that did it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Claim {
+    pub function: AltFunction,
+    pub owner: &'static str,
+}
+
+/// A pad is already held by another driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conflict {
+    /// Pad name, see [`PadInfo::name`]
+    pub pad: &'static str,
+    pub held: Claim,
+    pub wanted: Claim,
+}
+
+static CLAIMS: Mutex<RefCell<[Option<Claim>; PAD_COUNT]>> = Mutex::new(RefCell::new([None; PAD_COUNT]));
+
+fn slot(pad: &PadInfo) -> usize {
+    PADS.binary_search_by_key(&pad.fmux, |p| p.fmux).unwrap()
+}
+
+/// Record `wanted` on the pad, the same owner may claim again with another function
+pub(crate) fn claim(pad: &'static PadInfo, wanted: Claim) -> Result<(), Conflict> {
+    critical_section::with(|cs| {
+        let mut claims = CLAIMS.borrow_ref_mut(cs);
+        let entry = &mut claims[slot(pad)];
+        match *entry {
+            Some(held) if held.owner != wanted.owner => Err(Conflict {
+                pad: pad.name,
+                held,
+                wanted,
+            }),
+            _ => {
+                *entry = Some(wanted);
+                Ok(())
+            }
+        }
+    })
+}
+
+/// Run the `claims` of one driver, dropping all claims of `owner` if one fails
+pub(crate) fn claim_all(owner: &'static str, claims: impl FnOnce() -> Result<(), Conflict>) -> Result<(), Conflict> {
+    claims().inspect_err(|_| release_owner(owner))
+}
+
+pub(crate) fn release(pad: &'static PadInfo) {
+    critical_section::with(|cs| CLAIMS.borrow_ref_mut(cs)[slot(pad)] = None);
+}
+
+/// Drop every claim of `owner`, for drivers that don't keep their pins
+pub(crate) fn release_owner(owner: &'static str) {
+    critical_section::with(|cs| {
+        for entry in CLAIMS.borrow_ref_mut(cs).iter_mut() {
+            if entry.is_some_and(|claim| claim.owner == owner) {
+                *entry = None;
+            }
+        }
+    });
+}
+
+pub(crate) fn owner(pad: &'static PadInfo) -> Option<Claim> {
+    critical_section::with(|cs| CLAIMS.borrow_ref(cs)[slot(pad)])
+}
+
+/// Function currently selected on the pad, read back from `FUNC_SEL`
+pub(crate) fn current_function(pad: &PadInfo) -> AltFunction {
+    let pinmux = unsafe { &*pac::PINMUX::PTR };
+    AltFunction::from_bits(pinmux.pad(pad.fmux).func_sel().read().value().bits())
+}
+
+/// Write one line per pad: name, selected function and its signal, owner
+pub fn write_state(w: &mut impl fmt::Write) -> fmt::Result {
+    for pad in &PADS {
+        let func = current_function(pad);
+        let signal = pad.function(func).unwrap_or("-");
+        write!(w, "{:<14} {:?} {:<18}", pad.name, func, signal)?;
+        match owner(pad) {
+            Some(claim) if claim.function == func => writeln!(w, "{}", claim.owner)?,
+            // someone wrote FUNC_SEL behind the owner's back
+            Some(claim) => writeln!(w, "{} wants {:?}", claim.owner, claim.function)?,
+            None => writeln!(w, "-")?,
+        }
+    }
+    Ok(())
+}
+
+/// Print the pinmux state on UART0, see [`write_state`]
+pub fn dump() {
+    let _ = write_state(&mut crate::uart::Uart0);
+}
@@ -0,0 +1,117 @@
+//! PLIC, Platform-Level Interrupt Controller
+//!
+//! The HAL runs in S-mode on hart 0, which is PLIC context 1. OpenSBI has
+//! already granted S-mode access to the PLIC registers.
+//!
+//! Interrupt numbers follow the TRM (and `pac::Interrupt`), which count the
+//! 16 CLINT local interrupts first, so PLIC source id = number - 16.
+
+use core::arch::asm;
+
+use crate::mmio::regs;
+use crate::pac;
+
+const PLIC_BASE: usize = 0x7000_0000;
+
+const IRQ_OFFSET: u16 = 16;
+
+/// Highest interrupt priority, 0 means never interrupt
+pub const MAX_PRIORITY: u8 = 31;
+
+regs! {
+    pub struct Regs {
+        priority[1024]: 0x00_0000 / 0x04,
+        pending[32]: 0x00_1000 / 0x04,
+        /// Context 1, hart 0 S-mode
+        enable[32]: 0x00_2080 / 0x04,
+        threshold: 0x20_1000,
+        claim: 0x20_1004,
+    }
+}
+
+/// Anything that names an interrupt line
+pub trait InterruptNumber: Copy {
+    fn number(self) -> u16;
+}
+
+impl InterruptNumber for pac::Interrupt {
+    #[inline]
+    fn number(self) -> u16 {
+        self as u16
+    }
+}
+
+impl InterruptNumber for u16 {
+    #[inline]
+    fn number(self) -> u16 {
+        self
+    }
+}
+
+#[inline]
+fn regs() -> Regs {
+    Regs::at(PLIC_BASE)
+}
+
+#[inline]
+fn source(irq: impl InterruptNumber) -> usize {
+    let number = irq.number();
+    assert!(
+        number >= IRQ_OFFSET,
+        "interrupt {number} is a CLINT local interrupt, not a PLIC source"
+    );
+    (number - IRQ_OFFSET) as usize
+}
+
+/// Set priority of an interrupt, 1 ~ [`MAX_PRIORITY`]
+pub fn set_priority(irq: impl InterruptNumber, priority: u8) {
+    regs().priority(source(irq)).write(priority.min(MAX_PRIORITY) as u32);
+}
+
+/// Interrupts with priority not greater than `threshold` are masked
+pub fn set_threshold(threshold: u8) {
+    regs().threshold().write(threshold.min(MAX_PRIORITY) as u32);
+}
+
+pub fn enable(irq: impl InterruptNumber) {
+    let id = source(irq);
+    regs().enable(id / 32).set_bits(1 << (id % 32));
+}
+
+pub fn disable(irq: impl InterruptNumber) {
+    let id = source(irq);
+    regs().enable(id / 32).clear_bits(1 << (id % 32));
+}
+
+pub fn is_enabled(irq: impl InterruptNumber) -> bool {
+    let id = source(irq);
+    regs().enable(id / 32).is_set(1 << (id % 32))
+}
+
+pub fn is_pending(irq: impl InterruptNumber) -> bool {
+    let id = source(irq);
+    regs().pending(id / 32).is_set(1 << (id % 32))
+}
+
+/// Claim the highest priority pending interrupt, in TRM numbering
+pub fn claim() -> Option<u16> {
+    match regs().claim().read() {
+        0 => None,
+        id => Some(id as u16 + IRQ_OFFSET),
+    }
+}
+
+/// Signal the end of handling an interrupt returned by [`claim`]
+pub fn complete(irq: impl InterruptNumber) {
+    regs().claim().write(source(irq) as u32);
+}
+
+/// Enable S-mode external interrupts on this hart
+///
+/// # Safety
+///
+/// Interrupt handlers must be ready before calling this.
+pub unsafe fn enable_external_interrupts() {
+    // sie.SEIE
+    asm!("csrs sie, {}", in(reg) 1 << 9);
+}
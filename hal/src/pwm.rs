@@ -0,0 +1,380 @@
+//! PWM, Pulse Width Modulation
+//!
+//! The SG2002 has 4 PWM controllers, each with 4 channels. PWM_n on the pinmux
+//! table is channel `n % 4` of controller `PWMn / 4`.
+
+use core::marker::PhantomData;
+
+use fugit::HertzU32 as Hertz;
+
+use crate::clocks::{clocks, Gate};
+use crate::mmio::regs;
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, pinmux, Peripheral};
+
+/// Maximum value of the pulse counter, 24 bits
+pub const MAX_PULSE_COUNT: u32 = 0xff_ffff;
+
+/// Channel of a PWM controller
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Channel {
+    Ch0 = 0,
+    Ch1 = 1,
+    Ch2 = 2,
+    Ch3 = 3,
+}
+
+impl Channel {
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    #[inline]
+    fn mask(self) -> u32 {
+        1 << (self as u32)
+    }
+}
+
+/// Output polarity
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Polarity {
+    /// Duty cycle is the high level time
+    ActiveHigh,
+    /// Duty cycle is the low level time
+    ActiveLow,
+}
+
+/// Output mode of a channel
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mode {
+    /// Output runs until the channel is stopped
+    Continuous,
+    /// Output stops by itself after the given number of pulses, at most [`MAX_PULSE_COUNT`]
+    PulseCount(u32),
+}
+
+pub struct Config {
+    pub frequency: Hertz,
+    pub polarity: Polarity,
+    pub mode: Mode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            frequency: Hertz::kHz(1),
+            polarity: Polarity::ActiveHigh,
+            mode: Mode::Continuous,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Requested frequency can't be generated from the PWM clock
+    InvalidFrequency,
+    /// Pulse count exceeds [`MAX_PULSE_COUNT`]
+    InvalidPulseCount,
+    /// The pad is held by another driver
+    Pinmux(pinmux::Conflict),
+}
+
+regs! {
+    pub struct Regs {
+        /// Low(or high, with polarity set) level cycles
+        hlperiod[4]: 0x000 / 0x08,
+        /// Period cycles
+        period[4]: 0x004 / 0x08,
+        /// [3:0] polarity, [11:8] pulse count mode
+        polarity: 0x040,
+        pwmstart: 0x044,
+        pwmdone: 0x048,
+        pwmupdate: 0x04c,
+        pcount[4]: 0x050 / 0x04,
+        pulsecount[4]: 0x060 / 0x04,
+        pwm_oe: 0x0d0,
+    }
+}
+
+pub struct Pwm<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> Pwm<'d, T> {
+    /// Create a PWM driver with all channels stopped and outputs disabled.
+    pub fn new(_peri: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(_peri);
+        T::enable_and_reset();
+
+        let regs = T::regs();
+        regs.pwmstart().write(0);
+        regs.pwm_oe().write(0);
+
+        Self { phantom: PhantomData }
+    }
+
+    /// Route `pin` to channel 0 and enable its output driver.
+    pub fn attach_ch0(&mut self, pin: impl Peripheral<P = impl Ch0Pin<T>> + 'd) -> Result<(), Error> {
+        into_ref!(pin);
+        pin.try_claim(pin.alt_function(), T::NAME).map_err(Error::Pinmux)?;
+        self.enable_output(Channel::Ch0);
+        Ok(())
+    }
+
+    /// Route `pin` to channel 1 and enable its output driver.
+    pub fn attach_ch1(&mut self, pin: impl Peripheral<P = impl Ch1Pin<T>> + 'd) -> Result<(), Error> {
+        into_ref!(pin);
+        pin.try_claim(pin.alt_function(), T::NAME).map_err(Error::Pinmux)?;
+        self.enable_output(Channel::Ch1);
+        Ok(())
+    }
+
+    /// Route `pin` to channel 2 and enable its output driver.
+    pub fn attach_ch2(&mut self, pin: impl Peripheral<P = impl Ch2Pin<T>> + 'd) -> Result<(), Error> {
+        into_ref!(pin);
+        pin.try_claim(pin.alt_function(), T::NAME).map_err(Error::Pinmux)?;
+        self.enable_output(Channel::Ch2);
+        Ok(())
+    }
+
+    /// Route `pin` to channel 3 and enable its output driver.
+    pub fn attach_ch3(&mut self, pin: impl Peripheral<P = impl Ch3Pin<T>> + 'd) -> Result<(), Error> {
+        into_ref!(pin);
+        pin.try_claim(pin.alt_function(), T::NAME).map_err(Error::Pinmux)?;
+        self.enable_output(Channel::Ch3);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn enable_output(&mut self, ch: Channel) {
+        T::regs().pwm_oe().set_bits(ch.mask());
+    }
+
+    #[inline]
+    pub fn disable_output(&mut self, ch: Channel) {
+        T::regs().pwm_oe().clear_bits(ch.mask());
+    }
+
+    /// Apply frequency, polarity and mode to a channel, duty cycle is reset to 50%.
+    pub fn configure(&mut self, ch: Channel, config: &Config) -> Result<(), Error> {
+        self.set_frequency(ch, config.frequency)?;
+        self.set_polarity(ch, config.polarity);
+        self.set_mode(ch, config.mode)?;
+        let period = self.period(ch);
+        self.set_duty(ch, period / 2);
+        Ok(())
+    }
+
+    /// PWM source clock, 100MHz from FPLL by default
+    #[inline]
+    pub fn clock(&self) -> Hertz {
+        clocks().pwm
+    }
+
+    /// Set period in PWM clock cycles, duty cycle is kept as a ratio.
+    pub fn set_period(&mut self, ch: Channel, period: u32) -> Result<(), Error> {
+        if period < 2 {
+            return Err(Error::InvalidFrequency);
+        }
+        let old_period = self.period(ch);
+        let duty = self.duty(ch);
+        let regs = T::regs();
+        regs.period(ch.index()).write(period);
+        let duty = if old_period == 0 {
+            period / 2
+        } else {
+            (duty as u64 * period as u64 / old_period as u64) as u32
+        };
+        self.set_duty(ch, duty);
+        Ok(())
+    }
+
+    pub fn set_frequency(&mut self, ch: Channel, freq: Hertz) -> Result<(), Error> {
+        let clock = self.clock().raw();
+        let freq = freq.raw();
+        if freq == 0 || freq > clock / 2 {
+            return Err(Error::InvalidFrequency);
+        }
+        self.set_period(ch, clock / freq)
+    }
+
+    /// Period in PWM clock cycles
+    #[inline]
+    pub fn period(&self, ch: Channel) -> u32 {
+        T::regs().period(ch.index()).read()
+    }
+
+    /// Set duty cycle in PWM clock cycles, clamped to the period.
+    pub fn set_duty(&mut self, ch: Channel, duty: u32) {
+        let regs = T::regs();
+        let period = self.period(ch);
+        let duty = duty.min(period);
+        // HLPERIOD counts the inactive level for both polarities
+        regs.hlperiod(ch.index()).write(period - duty);
+        self.update(ch);
+    }
+
+    /// Duty cycle in PWM clock cycles
+    #[inline]
+    pub fn duty(&self, ch: Channel) -> u32 {
+        let regs = T::regs();
+        self.period(ch).saturating_sub(regs.hlperiod(ch.index()).read())
+    }
+
+    pub fn set_polarity(&mut self, ch: Channel, polarity: Polarity) {
+        let regs = T::regs();
+        match polarity {
+            Polarity::ActiveHigh => regs.polarity().clear_bits(ch.mask()),
+            Polarity::ActiveLow => regs.polarity().set_bits(ch.mask()),
+        }
+    }
+
+    pub fn set_mode(&mut self, ch: Channel, mode: Mode) -> Result<(), Error> {
+        let regs = T::regs();
+        match mode {
+            Mode::Continuous => regs.polarity().clear_bits(ch.mask() << 8),
+            Mode::PulseCount(n) => {
+                if n == 0 || n > MAX_PULSE_COUNT {
+                    return Err(Error::InvalidPulseCount);
+                }
+                regs.pcount(ch.index()).write(n);
+                regs.polarity().set_bits(ch.mask() << 8);
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of pulses already output in pulse count mode
+    #[inline]
+    pub fn pulse_count(&self, ch: Channel) -> u32 {
+        T::regs().pulsecount(ch.index()).read() & MAX_PULSE_COUNT
+    }
+
+    /// Whether a channel in pulse count mode has output all its pulses
+    #[inline]
+    pub fn is_done(&self, ch: Channel) -> bool {
+        T::regs().pwmdone().is_set(ch.mask())
+    }
+
+    #[inline]
+    pub fn start(&mut self, ch: Channel) {
+        self.start_synchronized(&[ch]);
+    }
+
+    /// Start several channels on the same clock edge, so their outputs are in phase.
+    pub fn start_synchronized(&mut self, channels: &[Channel]) {
+        let mask = channels.iter().fold(0, |acc, ch| acc | ch.mask());
+        let regs = T::regs();
+        // rising edge of PWMSTART starts the channel
+        regs.pwmstart().clear_bits(mask);
+        regs.pwmstart().set_bits(mask);
+    }
+
+    #[inline]
+    pub fn stop(&mut self, ch: Channel) {
+        T::regs().pwmstart().clear_bits(ch.mask());
+    }
+
+    #[inline]
+    pub fn is_running(&self, ch: Channel) -> bool {
+        T::regs().pwmstart().is_set(ch.mask())
+    }
+
+    /// Borrow a single channel, for use with `embedded_hal::pwm::SetDutyCycle`.
+    pub fn channel(&mut self, ch: Channel) -> PwmChannel<'_, 'd, T> {
+        PwmChannel { pwm: self, ch }
+    }
+
+    // Latch new period/hlperiod into a running channel.
+    #[inline]
+    fn update(&mut self, ch: Channel) {
+        let regs = T::regs();
+        if regs.pwmstart().is_set(ch.mask()) {
+            regs.pwmupdate().set_bits(ch.mask());
+            regs.pwmupdate().clear_bits(ch.mask());
+        }
+    }
+}
+
+impl<'d, T: Instance> Drop for Pwm<'d, T> {
+    fn drop(&mut self) {
+        pinmux::release_owner(T::NAME);
+        T::disable();
+    }
+}
+
+/// A single channel of a PWM controller
+pub struct PwmChannel<'a, 'd, T: Instance> {
+    pwm: &'a mut Pwm<'d, T>,
+    ch: Channel,
+}
+
+impl<T: Instance> embedded_hal::pwm::ErrorType for PwmChannel<'_, '_, T> {
+    type Error = core::convert::Infallible;
+}
+
+impl<T: Instance> embedded_hal::pwm::SetDutyCycle for PwmChannel<'_, '_, T> {
+    fn max_duty_cycle(&self) -> u16 {
+        self.pwm.period(self.ch).min(u16::MAX as u32) as u16
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        let max = self.max_duty_cycle() as u64;
+        let period = self.pwm.period(self.ch) as u64;
+        // scale when the period doesn't fit in u16
+        let duty = (duty as u64 * period).checked_div(max).unwrap_or(0);
+        self.pwm.set_duty(self.ch, duty as u32);
+        Ok(())
+    }
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Instance {
+        /// Owner of the pads in [`pinmux`]
+        const NAME: &'static str;
+
+        fn regs() -> Regs;
+
+        fn enable_and_reset();
+
+        fn disable();
+    }
+}
+
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static + Send {}
+
+macro_rules! impl_pwm {
+    ($inst:ident, $base:expr, $rst:ident) => {
+        impl sealed::Instance for crate::peripherals::$inst {
+            const NAME: &'static str = stringify!($inst);
+
+            fn regs() -> Regs {
+                Regs::at($base)
+            }
+
+            fn enable_and_reset() {
+                rcc::enable_and_reset(&[Gate::Pwm, Gate::ApbPwm], Reset::$rst);
+            }
+
+            // PWM clocks are shared by all 4 controllers, only hold this one in reset
+            fn disable() {
+                rcc::assert_reset(Reset::$rst);
+            }
+        }
+
+        impl Instance for peripherals::$inst {}
+    };
+}
+
+impl_pwm!(PWM0, 0x0306_0000, Pwm0);
+impl_pwm!(PWM1, 0x0306_1000, Pwm1);
+impl_pwm!(PWM2, 0x0306_2000, Pwm2);
+impl_pwm!(PWM3, 0x0306_3000, Pwm3);
+
+pin_trait!(Ch0Pin, Instance);
+pin_trait!(Ch1Pin, Instance);
+pin_trait!(Ch2Pin, Instance);
+pin_trait!(Ch3Pin, Instance);
@@ -0,0 +1,145 @@
+//! RSTGEN, peripheral soft reset, together with CLKGEN clock gates
+//!
+//! SOFT_RSTN_0 ~ SOFT_RSTN_3 hold one active-low reset per bit, a peripheral
+//! is held in reset while its bit is 0.
+
+use crate::clocks::{self, Gate};
+use crate::mmio::regs;
+
+regs! {
+    pub struct Regs {
+        soft_rstn[4]: 0x000 / 0x04,
+    }
+}
+
+#[inline]
+fn regs() -> Regs {
+    Regs::at(0x0300_3000)
+}
+
+/// Reset lines, as `register index << 5 | bit`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Reset {
+    Ddr = 2,
+    H264c = 3,
+    Jpeg = 4,
+    H265c = 5,
+    VipSys = 6,
+    Tdma = 7,
+    Tpu = 8,
+    TpuSys = 9,
+    Usb = 11,
+    Eth0 = 12,
+    Nand = 14,
+    Emmc = 15,
+    Sd0 = 16,
+    Sd1 = 17,
+    Sdma = 18,
+    I2s0 = 19,
+    I2s1 = 20,
+    I2s2 = 21,
+    I2s3 = 22,
+    Uart0 = 23,
+    Uart1 = 24,
+    Uart2 = 25,
+    Uart3 = 26,
+    I2c0 = 27,
+    I2c1 = 28,
+    I2c2 = 29,
+    I2c3 = 30,
+    I2c4 = 31,
+
+    Pwm0 = 32,
+    Pwm1 = 33,
+    Pwm2 = 34,
+    Pwm3 = 35,
+    Spi0 = 40,
+    Spi1 = 41,
+    Spi2 = 42,
+    Spi3 = 43,
+    Gpio0 = 44,
+    Gpio1 = 45,
+    Gpio2 = 46,
+    Efuse = 47,
+    Wdt = 48,
+    AhbRom = 49,
+    Spic = 50,
+    Tempsen = 51,
+    Saradc = 52,
+    ComboPhy0 = 58,
+    SpiNand = 61,
+    Se = 62,
+
+    Uart4 = 74,
+    Gpio3 = 75,
+    System = 76,
+    Timer = 77,
+    Timer0 = 78,
+    Timer1 = 79,
+    Timer2 = 80,
+    Timer3 = 81,
+    Timer4 = 82,
+    Timer5 = 83,
+    Timer6 = 84,
+    Timer7 = 85,
+    Keyscan = 89,
+    AudDac = 91,
+    AudDacApb = 92,
+    AudAdc = 93,
+    VcSys = 95,
+
+    EthPhy = 96,
+    EthPhyApb = 97,
+    AudSrc = 98,
+    VipCam0 = 99,
+    Wdt1 = 100,
+    Wdt2 = 101,
+}
+
+impl Reset {
+    #[inline]
+    fn mask(self) -> u32 {
+        1 << (self as u32 & 0x1f)
+    }
+}
+
+/// Hold a peripheral in reset
+pub fn assert_reset(rst: Reset) {
+    critical_section::with(|_| regs().soft_rstn(rst as usize >> 5).clear_bits(rst.mask()));
+}
+
+/// Release a peripheral from reset
+pub fn deassert_reset(rst: Reset) {
+    critical_section::with(|_| regs().soft_rstn(rst as usize >> 5).set_bits(rst.mask()));
+}
+
+pub fn is_reset_asserted(rst: Reset) -> bool {
+    !regs().soft_rstn(rst as usize >> 5).is_set(rst.mask())
+}
+
+/// Reset a peripheral, leaving it released
+pub fn reset(rst: Reset) {
+    assert_reset(rst);
+    // a few bus cycles are enough for the reset to propagate
+    for _ in 0..16 {
+        core::hint::spin_loop();
+    }
+    deassert_reset(rst);
+}
+
+/// Ungate the clocks of a peripheral, then pulse its reset
+pub(crate) fn enable_and_reset(gates: &[Gate], rst: Reset) {
+    for &gate in gates {
+        clocks::enable(gate);
+    }
+    reset(rst);
+}
+
+/// Hold a peripheral in reset and gate its clocks
+pub(crate) fn disable(gates: &[Gate], rst: Reset) {
+    assert_reset(rst);
+    for &gate in gates {
+        clocks::disable(gate);
+    }
+}
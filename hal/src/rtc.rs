@@ -0,0 +1,197 @@
+//! RTC, always-on domain real time clock
+//!
+//! RTCSYS keeps running while the rest of the chip is reset, so the second
+//! counter, the alarm and the INFO scratch registers survive warm reboot.
+//! They are all cleared when the board loses power.
+
+use core::marker::PhantomData;
+
+use embedded_hal::delay::DelayNs;
+
+use crate::delay::Delay;
+use crate::mmio::regs;
+use crate::{into_ref, peripherals, plic, Peripheral};
+
+/// RTC alarm interrupt number
+pub const IRQ: u16 = 33;
+
+/// Number of scratch registers available to applications
+///
+/// RTC_INFO0 ~ RTC_INFO3 and RTC_NOPOR_INFO0 ~ RTC_NOPOR_INFO2.
+/// RTC_NOPOR_INFO3 is used by [`crate::wdt`] to record the reset cause.
+pub const SCRATCH_COUNT: usize = 7;
+
+const CTRL0_UNLOCK_KEY: u32 = 0xab18;
+/// Upper half of RTC_CTRL0 is a write mask of the lower half
+const CTRL0_WRITE_MASK: u32 = 0xffff_0000;
+const CTRL0_REQ_SHDN: u32 = 1 << 0;
+const CTRL0_REQ_WARM_RST: u32 = 1 << 4;
+/// Keep these settings from FSBL, PWR_VBAT_DET and the like
+const CTRL0_KEEP: u32 = 0x0800;
+
+/// The new counter value crosses into the 32K domain within a few cycles
+const SET_TIMEOUT_US: u32 = 10_000;
+
+regs! {
+    pub struct Regs {
+        sec_pulse_gen: 0x004,
+        alarm_time: 0x008,
+        alarm_enable: 0x00c,
+        set_sec_cntr_value: 0x010,
+        set_sec_cntr_trig: 0x014,
+        sec_cntr_value: 0x018,
+        info[4]: 0x01c / 0x04,
+        nopor_info[4]: 0x02c / 0x04,
+        en_shdn_req: 0x0c0,
+        en_pwr_cyc_req: 0x0c8,
+        en_warm_rst_req: 0x0cc,
+    }
+}
+
+regs! {
+    /// RTC_CTRL, power sequence control
+    pub struct CtrlRegs {
+        unlock_key: 0x004,
+        ctrl0: 0x008,
+        status0: 0x00c,
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Scratch register index out of [`SCRATCH_COUNT`]
+    InvalidScratch,
+    /// The counter didn't take the new value
+    Timeout,
+}
+
+pub struct Rtc<'d> {
+    phantom: PhantomData<&'d mut peripherals::RTC>,
+}
+
+impl<'d> Rtc<'d> {
+    /// The RTC is already running from FSBL, this doesn't touch the counter.
+    pub fn new(_peri: impl Peripheral<P = peripherals::RTC> + 'd) -> Self {
+        into_ref!(_peri);
+
+        Self { phantom: PhantomData }
+    }
+
+    #[inline]
+    fn regs() -> Regs {
+        Regs::at(0x0502_6000)
+    }
+
+    #[inline]
+    fn ctrl_regs() -> CtrlRegs {
+        CtrlRegs::at(0x0502_5000)
+    }
+
+    /// Seconds since epoch
+    pub fn now(&self) -> u32 {
+        let regs = Self::regs();
+        // counter is in the 32K domain, read until stable
+        loop {
+            let a = regs.sec_cntr_value().read();
+            let b = regs.sec_cntr_value().read();
+            if a == b {
+                return a;
+            }
+        }
+    }
+
+    /// Set seconds since epoch, waits until the counter has latched the value
+    pub fn set(&mut self, secs: u32) -> Result<(), Error> {
+        let regs = Self::regs();
+        regs.set_sec_cntr_value().write(secs);
+        regs.set_sec_cntr_trig().write(1);
+
+        // at or just past `secs`, the counter may tick between polls, while an
+        // old value far above `secs` is not taken for the new one
+        let mut delay = Delay::new();
+        for _ in 0..SET_TIMEOUT_US {
+            if self.now().wrapping_sub(secs) <= 1 {
+                return Ok(());
+            }
+            delay.delay_us(1);
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Fire the alarm when the counter reaches `secs`
+    pub fn set_alarm(&mut self, secs: u32) {
+        let regs = Self::regs();
+        regs.alarm_enable().write(0);
+        regs.alarm_time().write(secs);
+        regs.alarm_enable().write(1);
+    }
+
+    /// Alarm time, if enabled
+    pub fn alarm(&self) -> Option<u32> {
+        let regs = Self::regs();
+        if regs.alarm_enable().read() & 1 != 0 {
+            Some(regs.alarm_time().read())
+        } else {
+            None
+        }
+    }
+
+    /// Disable the alarm, also acknowledges a fired alarm
+    pub fn clear_alarm(&mut self) {
+        Self::regs().alarm_enable().write(0);
+    }
+
+    /// Whether the alarm is enabled and its time has come
+    pub fn is_alarm_fired(&self) -> bool {
+        matches!(self.alarm(), Some(secs) if self.now() >= secs)
+    }
+
+    /// Route the alarm to the PLIC
+    pub fn enable_alarm_interrupt(&mut self) {
+        plic::set_priority(IRQ, 1);
+        plic::enable(IRQ);
+    }
+
+    pub fn disable_alarm_interrupt(&mut self) {
+        plic::disable(IRQ);
+    }
+
+    pub fn read_scratch(&self, index: usize) -> Result<u32, Error> {
+        Ok(scratch(index)?.read())
+    }
+
+    pub fn write_scratch(&mut self, index: usize, value: u32) -> Result<(), Error> {
+        scratch(index)?.write(value);
+        Ok(())
+    }
+
+    /// Ask the power sequencer to turn the board off
+    pub fn power_off(&mut self) -> ! {
+        Self::regs().en_shdn_req().write(1);
+        Self::request(CTRL0_REQ_SHDN)
+    }
+
+    /// Reset everything but RTCSYS
+    pub fn warm_reset(&mut self) -> ! {
+        Self::regs().en_warm_rst_req().write(1);
+        Self::request(CTRL0_REQ_WARM_RST)
+    }
+
+    fn request(req: u32) -> ! {
+        let ctrl = Self::ctrl_regs();
+        ctrl.unlock_key().write(CTRL0_UNLOCK_KEY);
+        ctrl.ctrl0().write(CTRL0_WRITE_MASK | CTRL0_KEEP | req);
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+fn scratch(index: usize) -> Result<crate::mmio::Reg, Error> {
+    let regs = Rtc::regs();
+    match index {
+        0..=3 => Ok(regs.info(index)),
+        4..=6 => Ok(regs.nopor_info(index - 4)),
+        _ => Err(Error::InvalidScratch),
+    }
+}
@@ -0,0 +1,504 @@
+//! SD/MMC, SDHCI host controllers
+//!
+//! The SG2002 has 3 SDHCI hosts: SDIO0 wired to the micro SD slot, EMMC and
+//! SDIO1. [`Sdmmc`] drives SD memory cards, [`emmc::Emmc`] eMMC devices and
+//! [`sdio::Sdio`] SDIO functions like Wi-Fi modules, all on top of the same
+//! host code. The controllers are polled, data moves with ADMA2.
+//!
+//! SD cards are identified at 400kHz on a 1-bit bus, then switched to a 4-bit
+//! bus and, when the card supports it, high speed 50MHz. SDSC, SDHC and SDXC
+//! memory cards are supported, UHS-I is not.
+//!
+//! With the `embedded-sdmmc` feature, [`Sdmmc`] and [`emmc::Emmc`] also
+//! implement `embedded_sdmmc::BlockDevice`.
+//!
+//! With the `fatfs` feature, `fat` mounts FAT filesystems on those.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+use embedded_hal::delay::DelayNs;
+use fugit::HertzU32 as Hertz;
+
+pub use self::host::Regs;
+use self::host::{cmd, Aligned, Command, Data, Host, Response, Timing};
+use crate::clocks::{clocks, Gate};
+use crate::delay::Delay;
+use crate::gpio::Pull;
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, pinmux, Peripheral};
+
+#[cfg(feature = "embedded-sdmmc")]
+mod compat;
+pub mod emmc;
+#[cfg(feature = "fatfs")]
+pub mod fat;
+mod host;
+pub mod sdio;
+
+/// Size of a data block
+pub const BLOCK_SIZE: usize = 512;
+
+const DEFAULT_SPEED_CLOCK: u32 = 25_000_000;
+const HIGH_SPEED_CLOCK: u32 = 50_000_000;
+
+/// OCR, card power up done
+const OCR_BUSY: u32 = 1 << 31;
+/// OCR, card capacity status(ACMD41 response) or host capacity support(ACMD41 argument)
+const OCR_CCS: u32 = 1 << 30;
+/// OCR, 2.7V ~ 3.6V
+const OCR_VOLTAGE_WINDOW: u32 = 0x00ff_8000;
+/// CMD8 argument, 2.7V ~ 3.6V and check pattern
+const IF_COND: u32 = 0x1aa;
+
+const POWER_UP_TIMEOUT_MS: u32 = 1_000;
+
+const SEND_RELATIVE_ADDR: Command = cmd(3, Response::R6);
+const SWITCH_FUNC: Command = cmd(6, Response::R1);
+const SEND_IF_COND: Command = cmd(8, Response::R7);
+const SET_BUS_WIDTH: Command = cmd(6, Response::R1);
+const SD_SEND_OP_COND: Command = cmd(41, Response::R3);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    /// No card in the slot
+    NoCard,
+    /// Card is not initialized, see [`Sdmmc::init_card`]
+    NotInitialized,
+    /// Card didn't answer a command
+    CommandTimeout,
+    CommandCrc,
+    /// Other command line errors, or an error in the card status
+    Command,
+    DataTimeout,
+    DataCrc,
+    /// Other data line errors
+    Data,
+    /// ADMA2 descriptor or bus error
+    Adma,
+    /// Controller didn't finish in time
+    Timeout,
+    /// No working sampling point found for HS200
+    Tuning,
+    /// Not the expected kind of card, or the card doesn't take 3.3V
+    UnsupportedCard,
+    /// Block address beyond the end of the card
+    OutOfRange,
+    /// Invalid argument, like an SDIO function number or transfer size
+    InvalidArgument,
+    /// A pad is held by another driver
+    Pinmux(pinmux::Conflict),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BusWidth {
+    One,
+    Four,
+}
+
+pub struct Config {
+    /// Data bus width after identification
+    pub bus_width: BusWidth,
+    /// Switch to high speed 50MHz if the card supports it
+    pub high_speed: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bus_width: BusWidth::Four,
+            high_speed: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CardType {
+    /// Standard capacity, byte addressed, up to 2GB
+    Sdsc,
+    /// High or extended capacity, block addressed
+    Sdhc,
+}
+
+/// Card identified by [`Sdmmc::init_card`]
+#[derive(Debug, Clone, Copy)]
+pub struct CardInfo {
+    pub card_type: CardType,
+    pub rca: u16,
+    /// Card identification register, bits [127:8]
+    pub cid: u128,
+    /// Capacity in blocks of [`BLOCK_SIZE`]
+    pub blocks: u32,
+    pub bus_width: BusWidth,
+    pub clock: Hertz,
+}
+
+/// A 512-byte block, cache line aligned for DMA
+#[derive(Clone)]
+#[repr(C, align(64))]
+pub struct Block {
+    pub contents: [u8; BLOCK_SIZE],
+}
+
+impl Block {
+    pub const fn new() -> Self {
+        Self {
+            contents: [0; BLOCK_SIZE],
+        }
+    }
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for Block {
+    type Target = [u8; BLOCK_SIZE];
+
+    fn deref(&self) -> &Self::Target {
+        &self.contents
+    }
+}
+
+impl DerefMut for Block {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.contents
+    }
+}
+
+/// Index of a block on the card
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct BlockIdx(pub u32);
+
+/// Number of blocks
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct BlockCount(pub u32);
+
+/// Block storage in [`Block`]s, which are aligned for DMA
+pub trait BlockDevice {
+    type Error: core::fmt::Debug;
+
+    /// Read `blocks.len()` blocks starting at `start_block_idx`
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), Self::Error>;
+
+    /// Write `blocks.len()` blocks starting at `start_block_idx`
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error>;
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error>;
+}
+
+impl<D: BlockDevice> BlockDevice for &D {
+    type Error = D::Error;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        (**self).read(blocks, start_block_idx)
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        (**self).write(blocks, start_block_idx)
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        (**self).num_blocks()
+    }
+}
+
+/// Route bus pins to the host, command and data lines are pulled up.
+/// `Err(Error::Pinmux)` if a pad is held by another driver, none is claimed then.
+macro_rules! attach_pins {
+    ($clk:ident $(, $line:ident)*) => {
+        $crate::pinmux::claim_all(T::NAME, || {
+            $clk.try_claim($clk.alt_function(), T::NAME)?;
+            $($line.try_claim($line.alt_function(), T::NAME)?;)*
+            Ok(())
+        })
+        .map(|()| {
+            $($line.set_pull($crate::gpio::Pull::Up);)*
+        })
+        .map_err($crate::sdmmc::Error::Pinmux)
+    };
+}
+use attach_pins;
+
+/// SD memory card driver
+pub struct Sdmmc<'d, T: Instance> {
+    host: Host<T>,
+    config: Config,
+    card: Cell<Option<CardInfo>>,
+    phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> Sdmmc<'d, T> {
+    /// Create a driver with a 4-bit data bus. The card is not touched until [`Sdmmc::init_card`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_4bit(
+        _peri: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T>> + 'd,
+        cmd: impl Peripheral<P = impl CmdPin<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        d1: impl Peripheral<P = impl D1Pin<T>> + 'd,
+        d2: impl Peripheral<P = impl D2Pin<T>> + 'd,
+        d3: impl Peripheral<P = impl D3Pin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, clk, cmd, d0, d1, d2, d3);
+        attach_pins!(clk, cmd, d0, d1, d2, d3)?;
+
+        Self::new_inner(config)
+    }
+
+    /// Create a driver with a 1-bit data bus, `config.bus_width` is ignored.
+    pub fn new_1bit(
+        _peri: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T>> + 'd,
+        cmd: impl Peripheral<P = impl CmdPin<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, clk, cmd, d0);
+        attach_pins!(clk, cmd, d0)?;
+
+        Self::new_inner(Config {
+            bus_width: BusWidth::One,
+            ..config
+        })
+    }
+
+    fn new_inner(config: Config) -> Result<Self, Error> {
+        Ok(Self {
+            host: Host::new()?,
+            config,
+            card: Cell::new(None),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Use the card detect pad instead of assuming a card is present.
+    pub fn attach_card_detect(&mut self, pin: impl Peripheral<P = impl CdPin<T>> + 'd) -> Result<(), Error> {
+        into_ref!(pin);
+        pin.try_claim(pin.alt_function(), T::NAME).map_err(Error::Pinmux)?;
+        pin.set_pull(Pull::Up);
+        self.host.use_card_detect();
+        Ok(())
+    }
+
+    /// Whether a card is in the slot, always true without [`Sdmmc::attach_card_detect`]
+    #[inline]
+    pub fn is_card_present(&self) -> bool {
+        self.host.is_card_present()
+    }
+
+    /// The initialized card, if any
+    #[inline]
+    pub fn card(&self) -> Option<CardInfo> {
+        self.card.get()
+    }
+
+    /// Identify and select the card, then switch bus width and speed according to the config.
+    pub fn init_card(&mut self) -> Result<CardInfo, Error> {
+        self.card.set(None);
+        if !self.is_card_present() {
+            return Err(Error::NoCard);
+        }
+
+        let host = &self.host;
+        host.reset_bus()?;
+        host.send_command(host::GO_IDLE_STATE, 0)?;
+
+        // only version 2.00+ cards answer CMD8
+        let v2 = match host.send_command(SEND_IF_COND, IF_COND) {
+            Ok(resp) if resp[0] & 0xfff == IF_COND => true,
+            Ok(_) => return Err(Error::UnsupportedCard),
+            Err(Error::CommandTimeout) => false,
+            Err(e) => return Err(e),
+        };
+
+        let arg = OCR_VOLTAGE_WINDOW | if v2 { OCR_CCS } else { 0 };
+        let mut ocr = 0;
+        for _ in 0..POWER_UP_TIMEOUT_MS {
+            ocr = host.send_app_command(SD_SEND_OP_COND, 0, arg)?[0];
+            if ocr & OCR_BUSY != 0 {
+                break;
+            }
+            Delay::new().delay_ms(1);
+        }
+        if ocr & OCR_BUSY == 0 {
+            return Err(Error::UnsupportedCard);
+        }
+        let card_type = if ocr & OCR_CCS != 0 {
+            CardType::Sdhc
+        } else {
+            CardType::Sdsc
+        };
+
+        let cid = host::response_136(host.send_command(host::ALL_SEND_CID, 0)?);
+        let rca = (host.send_command(SEND_RELATIVE_ADDR, 0)?[0] >> 16) as u16;
+        let csd = host::response_136(host.send_command(host::SEND_CSD, (rca as u32) << 16)?);
+        host.send_command(host::SELECT_CARD, (rca as u32) << 16)?;
+
+        if card_type == CardType::Sdsc {
+            host.send_command(host::SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+        }
+
+        if self.config.bus_width == BusWidth::Four {
+            host.send_app_command(SET_BUS_WIDTH, rca, 0b10)?;
+            host.set_bus_width(BusWidth::Four);
+        }
+
+        let clock = if self.config.high_speed && self.switch_high_speed()? {
+            host.set_timing(Timing::HighSpeed);
+            host.set_clock(HIGH_SPEED_CLOCK)?
+        } else {
+            host.set_clock(DEFAULT_SPEED_CLOCK)?
+        };
+
+        let card = CardInfo {
+            card_type,
+            rca,
+            cid,
+            blocks: csd_blocks(csd),
+            bus_width: self.config.bus_width,
+            clock,
+        };
+        self.card.set(Some(card));
+        Ok(card)
+    }
+
+    /// Read blocks starting at `start`.
+    pub fn read_blocks(&self, start: u32, blocks: &mut [Block]) -> Result<(), Error> {
+        let card = self.checked_card(start, blocks.len())?;
+        self.host.read_blocks(start, card.card_type == CardType::Sdsc, blocks)
+    }
+
+    /// Write blocks starting at `start`.
+    pub fn write_blocks(&self, start: u32, blocks: &[Block]) -> Result<(), Error> {
+        let card = self.checked_card(start, blocks.len())?;
+        self.host.write_blocks(start, card.card_type == CardType::Sdsc, blocks)
+    }
+
+    fn checked_card(&self, start: u32, count: usize) -> Result<CardInfo, Error> {
+        let card = self.card.get().ok_or(Error::NotInitialized)?;
+        if start as u64 + count as u64 > card.blocks as u64 {
+            return Err(Error::OutOfRange);
+        }
+        Ok(card)
+    }
+
+    /// CMD6, ask for high speed access mode. False if the card doesn't support it.
+    fn switch_high_speed(&self) -> Result<bool, Error> {
+        let mut status = Aligned([0; 64]);
+        // mode 1(switch), function group 1 = 1(high speed), others unchanged
+        self.host.transfer(SWITCH_FUNC, 0x80ff_fff1, Data::read(&mut status))?;
+        // bits [379:376], function selected in group 1
+        let switched = status.0[16] & 0xf == 1;
+        if switched {
+            // 8 clocks before the new timing, more than enough at 25MHz
+            Delay::new().delay_us(1);
+        }
+        Ok(switched)
+    }
+}
+
+impl<T: Instance> BlockDevice for Sdmmc<'_, T> {
+    type Error = Error;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        self.read_blocks(start_block_idx.0, blocks)
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        self.write_blocks(start_block_idx.0, blocks)
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        self.card
+            .get()
+            .map(|card| BlockCount(card.blocks))
+            .ok_or(Error::NotInitialized)
+    }
+}
+
+/// Capacity in blocks of [`BLOCK_SIZE`] from an SD CSD
+fn csd_blocks(csd: u128) -> u32 {
+    match csd_bits(csd, 126, 2) {
+        // CSD version 2.0, (C_SIZE + 1) * 512KiB
+        1 => (csd_bits(csd, 48, 22) + 1) << 10,
+        _ => csd_v1_blocks(csd),
+    }
+}
+
+/// Capacity in blocks from a CSD version 1.0 layout, also used by eMMC up to 2GB
+///
+/// (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) * 2^READ_BL_LEN bytes
+fn csd_v1_blocks(csd: u128) -> u32 {
+    let c_size = csd_bits(csd, 62, 12) as u64;
+    let c_size_mult = csd_bits(csd, 47, 3);
+    let read_bl_len = csd_bits(csd, 80, 4);
+    (((c_size + 1) << (c_size_mult + 2 + read_bl_len)) / BLOCK_SIZE as u64) as u32
+}
+
+#[inline]
+fn csd_bits(csd: u128, offset: u32, width: u32) -> u32 {
+    ((csd >> offset) & ((1 << width) - 1)) as u32
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Instance {
+        /// Owner of the pads in [`pinmux`]
+        const NAME: &'static str;
+
+        fn regs() -> Regs;
+
+        /// Base clock of the SD clock divider
+        fn clock() -> Hertz;
+
+        fn enable_and_reset();
+
+        fn disable();
+    }
+}
+
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static + Send {}
+
+macro_rules! impl_sdmmc {
+    ($inst:ident, $base:expr, $clock:ident, $rst:ident, [$($gate:ident),*]) => {
+        impl sealed::Instance for crate::peripherals::$inst {
+            const NAME: &'static str = stringify!($inst);
+
+            fn regs() -> Regs {
+                Regs::at($base)
+            }
+
+            fn clock() -> Hertz {
+                clocks().$clock
+            }
+
+            fn enable_and_reset() {
+                rcc::enable_and_reset(&[$(Gate::$gate),*], Reset::$rst);
+            }
+
+            fn disable() {
+                rcc::disable(&[$(Gate::$gate),*], Reset::$rst);
+            }
+        }
+
+        impl Instance for peripherals::$inst {}
+    };
+}
+
+impl_sdmmc!(EMMC, 0x0430_0000, emmc, Emmc, [Axi4Emmc, Emmc, Emmc100k]);
+impl_sdmmc!(SDIO0, 0x0431_0000, sd0, Sd0, [Axi4Sd0, Sd0, Sd0_100k]);
+impl_sdmmc!(SDIO1, 0x0432_0000, sd1, Sd1, [Axi4Sd1, Sd1, Sd1_100k]);
+
+pin_trait!(ClkPin, Instance);
+pin_trait!(CmdPin, Instance);
+pin_trait!(D0Pin, Instance);
+pin_trait!(D1Pin, Instance);
+pin_trait!(D2Pin, Instance);
+pin_trait!(D3Pin, Instance);
+pin_trait!(CdPin, Instance);
@@ -0,0 +1,70 @@
+//! `embedded_sdmmc::BlockDevice` for the card drivers and USB mass storage
+//!
+//! `embedded_sdmmc::Block` has no alignment, so data moves through an aligned
+//! bounce buffer of [`BOUNCE_BLOCKS`] blocks.
+
+use embedded_sdmmc as esd;
+
+use super::emmc::Emmc;
+use super::{Block, BlockDevice, BlockIdx, Instance, Sdmmc};
+use crate::usb::host::msc::MassStorage;
+
+/// Blocks per transfer through the bounce buffer
+const BOUNCE_BLOCKS: usize = 8;
+
+fn read<D: BlockDevice>(device: &D, blocks: &mut [esd::Block], start: esd::BlockIdx) -> Result<(), D::Error> {
+    let mut bounce: [Block; BOUNCE_BLOCKS] = core::array::from_fn(|_| Block::new());
+    let mut idx = start.0;
+    for chunk in blocks.chunks_mut(BOUNCE_BLOCKS) {
+        let bounce = &mut bounce[..chunk.len()];
+        device.read(bounce, BlockIdx(idx))?;
+        for (block, data) in chunk.iter_mut().zip(bounce.iter()) {
+            block.contents = data.contents;
+        }
+        idx += chunk.len() as u32;
+    }
+    Ok(())
+}
+
+fn write<D: BlockDevice>(device: &D, blocks: &[esd::Block], start: esd::BlockIdx) -> Result<(), D::Error> {
+    let mut bounce: [Block; BOUNCE_BLOCKS] = core::array::from_fn(|_| Block::new());
+    let mut idx = start.0;
+    for chunk in blocks.chunks(BOUNCE_BLOCKS) {
+        let bounce = &mut bounce[..chunk.len()];
+        for (data, block) in bounce.iter_mut().zip(chunk) {
+            data.contents = block.contents;
+        }
+        device.write(bounce, BlockIdx(idx))?;
+        idx += chunk.len() as u32;
+    }
+    Ok(())
+}
+
+macro_rules! impl_block_device {
+    ([$($generics:tt)*] $driver:ty) => {
+        impl<$($generics)*> esd::BlockDevice for $driver {
+            type Error = <Self as BlockDevice>::Error;
+
+            fn read(
+                &self,
+                blocks: &mut [esd::Block],
+                start_block_idx: esd::BlockIdx,
+                _reason: &str,
+            ) -> Result<(), Self::Error> {
+                read(self, blocks, start_block_idx)
+            }
+
+            fn write(&self, blocks: &[esd::Block], start_block_idx: esd::BlockIdx) -> Result<(), Self::Error> {
+                write(self, blocks, start_block_idx)
+            }
+
+            fn num_blocks(&self) -> Result<esd::BlockCount, Self::Error> {
+                BlockDevice::num_blocks(self).map(|count| esd::BlockCount(count.0))
+            }
+        }
+    };
+}
+
+impl_block_device!([T: Instance] Sdmmc<'_, T>);
+impl_block_device!([T: Instance] Emmc<'_, T>);
+impl_block_device!([] MassStorage<'_, '_>);
@@ -0,0 +1,379 @@
+//! eMMC devices
+//!
+//! Devices are identified at 400kHz on a 1-bit bus, then switched to the
+//! configured bus width and speed through EXT_CSD. The boot partitions are
+//! reached with [`Emmc::select_partition`].
+//!
+//! The SG2002 only bonds out EMMC_DAT0 ~ EMMC_DAT3, so there is no 8-bit mode.
+//! HS200 needs 1.8V on the EMMC pads, which is a board matter.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use embedded_hal::delay::DelayNs;
+use fugit::HertzU32 as Hertz;
+
+use super::host::{self, cmd, Aligned, Command, Data, Host, Response, Timing};
+use super::{
+    attach_pins, Block, BlockCount, BlockDevice, BlockIdx, BusWidth, ClkPin, CmdPin, D0Pin, D1Pin, D2Pin, D3Pin, Error,
+    Instance,
+};
+use crate::delay::Delay;
+use crate::{into_ref, Peripheral};
+
+const LEGACY_CLOCK: u32 = 26_000_000;
+const HIGH_SPEED_CLOCK: u32 = 52_000_000;
+const HS200_CLOCK: u32 = 200_000_000;
+
+/// Relative card address, assigned by the host
+const RCA: u16 = 1;
+
+/// OCR, device power up done
+const OCR_BUSY: u32 = 1 << 31;
+/// OCR, sector addressing
+const OCR_SECTOR_MODE: u32 = 1 << 30;
+/// CMD1 argument, sector mode, 1.7V ~ 1.95V and 2.7V ~ 3.6V
+const OCR_ARG: u32 = OCR_SECTOR_MODE | 0x00ff_8080;
+
+const POWER_UP_TIMEOUT_MS: u32 = 1_000;
+
+/// Card status, SWITCH_ERROR
+const STATUS_SWITCH_ERROR: u32 = 1 << 7;
+
+// EXT_CSD bytes
+const EXT_CSD_PARTITION_CONFIG: usize = 179;
+const EXT_CSD_BUS_WIDTH: usize = 183;
+const EXT_CSD_HS_TIMING: usize = 185;
+const EXT_CSD_DEVICE_TYPE: usize = 196;
+const EXT_CSD_SEC_COUNT: usize = 212;
+const EXT_CSD_BOOT_SIZE_MULT: usize = 226;
+
+const DEVICE_TYPE_HS52: u8 = 1 << 1;
+const DEVICE_TYPE_HS200_1V8: u8 = 1 << 4;
+
+/// PARTITION_ACCESS, [2:0] of PARTITION_CONFIG
+const PARTITION_ACCESS_MASK: u8 = 0b111;
+/// BOOT_PARTITION_ENABLE, [5:3] of PARTITION_CONFIG
+const BOOT_PARTITION_ENABLE_MASK: u8 = 0b111 << 3;
+
+/// SWITCH access mode, write byte
+const SWITCH_WRITE_BYTE: u32 = 0b11 << 24;
+
+/// Tuning block of CMD21 on a 4-bit bus
+const TUNING_BLOCK_SIZE: usize = 64;
+
+const SEND_OP_COND: Command = cmd(1, Response::R3);
+const SET_RELATIVE_ADDR: Command = cmd(3, Response::R1);
+const SWITCH: Command = cmd(6, Response::R1b);
+const SEND_EXT_CSD: Command = cmd(8, Response::R1);
+const SEND_TUNING_BLOCK: Command = cmd(21, Response::R1);
+
+/// Bus speed mode
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Speed {
+    /// Backwards compatible, up to 26MHz
+    Legacy,
+    /// HS52, up to 52MHz
+    HighSpeed,
+    /// HS200, up to 200MHz with tuning, needs 1.8V IO and a 4-bit bus
+    Hs200,
+}
+
+pub struct Config {
+    /// Data bus width after identification
+    pub bus_width: BusWidth,
+    /// Fastest speed mode to use, lowered to what the device supports
+    pub speed: Speed,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bus_width: BusWidth::Four,
+            speed: Speed::HighSpeed,
+        }
+    }
+}
+
+/// Hardware partitions
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Partition {
+    User = 0,
+    Boot1 = 1,
+    Boot2 = 2,
+}
+
+/// Device identified by [`Emmc::init_card`]
+#[derive(Debug, Clone, Copy)]
+pub struct EmmcInfo {
+    pub rca: u16,
+    /// Card identification register, bits [127:8]
+    pub cid: u128,
+    /// Block addressed, larger than 2GB
+    pub sector_mode: bool,
+    /// User area capacity in blocks of [`super::BLOCK_SIZE`]
+    pub user_blocks: u32,
+    /// Capacity of each boot partition in blocks, 0 without boot partitions
+    pub boot_blocks: u32,
+    pub bus_width: BusWidth,
+    pub speed: Speed,
+    pub clock: Hertz,
+}
+
+/// eMMC device driver
+pub struct Emmc<'d, T: Instance> {
+    host: Host<T>,
+    config: Config,
+    card: Cell<Option<EmmcInfo>>,
+    partition: Cell<Partition>,
+    partition_config: Cell<u8>,
+    phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> Emmc<'d, T> {
+    /// Create a driver with a 4-bit data bus. The device is not touched until [`Emmc::init_card`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_4bit(
+        _peri: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T>> + 'd,
+        cmd: impl Peripheral<P = impl CmdPin<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        d1: impl Peripheral<P = impl D1Pin<T>> + 'd,
+        d2: impl Peripheral<P = impl D2Pin<T>> + 'd,
+        d3: impl Peripheral<P = impl D3Pin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, clk, cmd, d0, d1, d2, d3);
+        attach_pins!(clk, cmd, d0, d1, d2, d3)?;
+
+        Self::new_inner(config)
+    }
+
+    /// Create a driver with a 1-bit data bus, `config.bus_width` is ignored.
+    pub fn new_1bit(
+        _peri: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T>> + 'd,
+        cmd: impl Peripheral<P = impl CmdPin<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, clk, cmd, d0);
+        attach_pins!(clk, cmd, d0)?;
+
+        Self::new_inner(Config {
+            bus_width: BusWidth::One,
+            ..config
+        })
+    }
+
+    fn new_inner(config: Config) -> Result<Self, Error> {
+        Ok(Self {
+            host: Host::new()?,
+            config,
+            card: Cell::new(None),
+            partition: Cell::new(Partition::User),
+            partition_config: Cell::new(0),
+            phantom: PhantomData,
+        })
+    }
+
+    /// The initialized device, if any
+    #[inline]
+    pub fn card(&self) -> Option<EmmcInfo> {
+        self.card.get()
+    }
+
+    /// Identify and select the device, then switch bus width and speed according to the config.
+    ///
+    /// The user area is selected afterwards.
+    pub fn init_card(&mut self) -> Result<EmmcInfo, Error> {
+        self.card.set(None);
+        if self.config.speed == Speed::Hs200 && self.config.bus_width == BusWidth::One {
+            return Err(Error::InvalidArgument);
+        }
+
+        let host = &self.host;
+        host.reset_bus()?;
+        host.send_command(host::GO_IDLE_STATE, 0)?;
+
+        let mut ocr = 0;
+        for _ in 0..POWER_UP_TIMEOUT_MS {
+            ocr = host.send_command(SEND_OP_COND, OCR_ARG)?[0];
+            if ocr & OCR_BUSY != 0 {
+                break;
+            }
+            Delay::new().delay_ms(1);
+        }
+        if ocr & OCR_BUSY == 0 {
+            return Err(Error::UnsupportedCard);
+        }
+        let sector_mode = ocr & OCR_SECTOR_MODE != 0;
+
+        let cid = host::response_136(host.send_command(host::ALL_SEND_CID, 0)?);
+        host.send_command(SET_RELATIVE_ADDR, (RCA as u32) << 16)?;
+        let csd = host::response_136(host.send_command(host::SEND_CSD, (RCA as u32) << 16)?);
+        host.send_command(host::SELECT_CARD, (RCA as u32) << 16)?;
+        if !sector_mode {
+            host.send_command(host::SET_BLOCKLEN, super::BLOCK_SIZE as u32)?;
+        }
+        host.set_clock(LEGACY_CLOCK)?;
+
+        let mut ext_csd = Aligned([0; 512]);
+        host.transfer(SEND_EXT_CSD, 0, Data::read(&mut ext_csd))?;
+        let ext_csd = &ext_csd.0;
+        let sec_count = u32::from_le_bytes(ext_csd[EXT_CSD_SEC_COUNT..EXT_CSD_SEC_COUNT + 4].try_into().unwrap());
+        let user_blocks = if sector_mode {
+            sec_count
+        } else {
+            // byte addressed devices are up to 2GB, capacity comes from CSD
+            super::csd_v1_blocks(csd)
+        };
+        // 128KiB units
+        let boot_blocks = ext_csd[EXT_CSD_BOOT_SIZE_MULT] as u32 * 256;
+        let device_type = ext_csd[EXT_CSD_DEVICE_TYPE];
+        self.partition_config
+            .set(ext_csd[EXT_CSD_PARTITION_CONFIG] & !PARTITION_ACCESS_MASK);
+
+        if self.config.bus_width == BusWidth::Four {
+            self.switch(EXT_CSD_BUS_WIDTH, 1)?;
+            host.set_bus_width(BusWidth::Four);
+            self.check_switch()?;
+        }
+
+        let speed = match self.config.speed {
+            Speed::Hs200 if device_type & DEVICE_TYPE_HS200_1V8 != 0 => Speed::Hs200,
+            Speed::Hs200 | Speed::HighSpeed if device_type & DEVICE_TYPE_HS52 != 0 => Speed::HighSpeed,
+            _ => Speed::Legacy,
+        };
+        let clock = match speed {
+            Speed::Legacy => host.set_clock(LEGACY_CLOCK)?,
+            Speed::HighSpeed => {
+                self.switch(EXT_CSD_HS_TIMING, 1)?;
+                host.set_timing(Timing::HighSpeed);
+                let clock = host.set_clock(HIGH_SPEED_CLOCK)?;
+                self.check_switch()?;
+                clock
+            }
+            Speed::Hs200 => {
+                self.switch(EXT_CSD_HS_TIMING, 2)?;
+                host.set_timing(Timing::Hs200);
+                let clock = host.set_clock(HS200_CLOCK)?;
+                self.check_switch()?;
+                host.tune(SEND_TUNING_BLOCK, TUNING_BLOCK_SIZE)?;
+                clock
+            }
+        };
+
+        let card = EmmcInfo {
+            rca: RCA,
+            cid,
+            sector_mode,
+            user_blocks,
+            boot_blocks,
+            bus_width: self.config.bus_width,
+            speed,
+            clock,
+        };
+        self.partition.set(Partition::User);
+        self.card.set(Some(card));
+        Ok(card)
+    }
+
+    /// The partition reads and writes go to
+    #[inline]
+    pub fn partition(&self) -> Partition {
+        self.partition.get()
+    }
+
+    /// Direct reads and writes to `partition`
+    pub fn select_partition(&mut self, partition: Partition) -> Result<(), Error> {
+        let card = self.card.get().ok_or(Error::NotInitialized)?;
+        if partition != Partition::User && card.boot_blocks == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.write_partition_config(self.partition_config.get() | partition as u8)?;
+        self.partition.set(partition);
+        Ok(())
+    }
+
+    /// Choose the partition the device boots from, `None` disables boot.
+    pub fn set_boot_partition(&mut self, partition: Option<Partition>) -> Result<(), Error> {
+        self.card.get().ok_or(Error::NotInitialized)?;
+        let enable = match partition {
+            None => 0,
+            Some(Partition::Boot1) => 1,
+            Some(Partition::Boot2) => 2,
+            Some(Partition::User) => 7,
+        };
+        let config = (self.partition_config.get() & !BOOT_PARTITION_ENABLE_MASK) | (enable << 3);
+        self.write_partition_config(config | self.partition.get() as u8)?;
+        self.partition_config.set(config);
+        Ok(())
+    }
+
+    /// Capacity of the selected partition in blocks
+    pub fn partition_blocks(&self) -> Result<u32, Error> {
+        let card = self.card.get().ok_or(Error::NotInitialized)?;
+        Ok(match self.partition.get() {
+            Partition::User => card.user_blocks,
+            Partition::Boot1 | Partition::Boot2 => card.boot_blocks,
+        })
+    }
+
+    /// Read blocks of the selected partition starting at `start`.
+    pub fn read_blocks(&self, start: u32, blocks: &mut [Block]) -> Result<(), Error> {
+        let card = self.checked_card(start, blocks.len())?;
+        self.host.read_blocks(start, !card.sector_mode, blocks)
+    }
+
+    /// Write blocks of the selected partition starting at `start`.
+    pub fn write_blocks(&self, start: u32, blocks: &[Block]) -> Result<(), Error> {
+        let card = self.checked_card(start, blocks.len())?;
+        self.host.write_blocks(start, !card.sector_mode, blocks)
+    }
+
+    fn checked_card(&self, start: u32, count: usize) -> Result<EmmcInfo, Error> {
+        let card = self.card.get().ok_or(Error::NotInitialized)?;
+        if start as u64 + count as u64 > self.partition_blocks()? as u64 {
+            return Err(Error::OutOfRange);
+        }
+        Ok(card)
+    }
+
+    fn write_partition_config(&self, value: u8) -> Result<(), Error> {
+        self.switch(EXT_CSD_PARTITION_CONFIG, value)?;
+        self.check_switch()
+    }
+
+    /// CMD6, write an EXT_CSD byte and wait while the device is busy
+    fn switch(&self, index: usize, value: u8) -> Result<(), Error> {
+        let arg = SWITCH_WRITE_BYTE | ((index as u32) << 16) | ((value as u32) << 8);
+        self.host.send_command(SWITCH, arg)?;
+        Ok(())
+    }
+
+    /// Whether the last [`Emmc::switch`] was accepted
+    fn check_switch(&self) -> Result<(), Error> {
+        let status = self.host.send_command(host::SEND_STATUS, (RCA as u32) << 16)?[0];
+        if status & STATUS_SWITCH_ERROR != 0 {
+            return Err(Error::Command);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Instance> BlockDevice for Emmc<'_, T> {
+    type Error = Error;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        self.read_blocks(start_block_idx.0, blocks)
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        self.write_blocks(start_block_idx.0, blocks)
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        self.partition_blocks().map(BlockCount)
+    }
+}
@@ -0,0 +1,73 @@
+//! FAT filesystems on a card or flash stick, behind the `fatfs` feature
+//!
+//! The filesystem is [`embedded_sdmmc`], re-exported here, on the
+//! `embedded_sdmmc::BlockDevice` impls of [`Sdmmc`](super::Sdmmc),
+//! [`Emmc`](super::emmc::Emmc) and
+//! [`MassStorage`](crate::usb::host::msc::MassStorage). [`boot_volume`]
+//! opens the first MBR partition, on a Duo boot card that is the `boot`
+//! partition holding `fip.bin`. Media without a partition table can't be
+//! mounted.
+//!
+//! Names are 8.3, long file names are neither read nor written. Timestamps
+//! are left at the FAT epoch, build an [`embedded_sdmmc::VolumeManager`] with
+//! another [`TimeSource`] for real ones.
+//!
+//! ```ignore
+//! let mut fs = fat::mount(sd);
+//! let mut volume = fat::boot_volume(&mut fs)?;
+//! let mut root = volume.open_root_dir()?;
+//! let mut used = 0;
+//! root.iterate_dir(|entry| used += entry.size)?;
+//! let mut log = fat::open_append(&mut root, "LOG.TXT")?;
+//! log.write(b"boot\n")?;
+//! ```
+//!
+//! Every handle borrows the one below it mutably, close or drop a file
+//! before opening the next one in the same directory.
+
+pub use embedded_sdmmc;
+use embedded_sdmmc::{BlockDevice, Mode, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+
+/// Directories, files and volumes open at the same time
+const MAX_DIRS: usize = 4;
+const MAX_FILES: usize = 4;
+const MAX_VOLUMES: usize = 1;
+
+pub type FileSystem<D> = VolumeManager<D, FatEpoch, MAX_DIRS, MAX_FILES, MAX_VOLUMES>;
+pub type Volume<'a, D> = embedded_sdmmc::Volume<'a, D, FatEpoch, MAX_DIRS, MAX_FILES, MAX_VOLUMES>;
+pub type Dir<'a, D> = embedded_sdmmc::Directory<'a, D, FatEpoch, MAX_DIRS, MAX_FILES, MAX_VOLUMES>;
+pub type File<'a, D> = embedded_sdmmc::File<'a, D, FatEpoch, MAX_DIRS, MAX_FILES, MAX_VOLUMES>;
+pub use embedded_sdmmc::DirEntry;
+/// Errors of filesystem operations
+pub type Error<E> = embedded_sdmmc::Error<E>;
+
+/// Stamps every change with 1980-01-01 00:00, the FAT epoch
+pub struct FatEpoch;
+
+impl TimeSource for FatEpoch {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp {
+            year_since_1970: 10,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+        }
+    }
+}
+
+/// Put a filesystem layer on `device`, nothing is read until a volume is opened
+pub fn mount<D: BlockDevice>(device: D) -> FileSystem<D> {
+    VolumeManager::new_with_limits(device, FatEpoch, 0)
+}
+
+/// Open the first MBR partition
+pub fn boot_volume<D: BlockDevice>(fs: &mut FileSystem<D>) -> Result<Volume<'_, D>, Error<D::Error>> {
+    fs.open_volume(VolumeIdx(0))
+}
+
+/// Open `name` in `dir` for appending, creating it when missing
+pub fn open_append<'a, D: BlockDevice>(dir: &'a mut Dir<'_, D>, name: &str) -> Result<File<'a, D>, Error<D::Error>> {
+    dir.open_file_in_dir(name, Mode::ReadWriteCreateOrAppend)
+}
@@ -0,0 +1,654 @@
+//! SDHCI host, shared by the SD, eMMC and SDIO drivers
+//!
+//! Knows about registers, clocks, commands and data transfers, nothing about
+//! the card protocol on top.
+
+use core::cell::RefCell;
+use core::marker::PhantomData;
+
+use embedded_hal::delay::DelayNs;
+use fugit::HertzU32 as Hertz;
+
+use super::{Block, BusWidth, Error, Instance, BLOCK_SIZE};
+use crate::cache;
+use crate::delay::{self, Delay};
+use crate::mmio::regs;
+use crate::pinmux;
+
+// Transfer mode, lower half of CMD
+const XFER_DMA_EN: u32 = 1 << 0;
+const XFER_BLK_CNT_EN: u32 = 1 << 1;
+const XFER_AUTO_CMD12: u32 = 0b01 << 2;
+const XFER_READ: u32 = 1 << 4;
+const XFER_MULTI_BLK: u32 = 1 << 5;
+
+// Command, upper half of CMD
+const CMD_RESP_136: u32 = 0b01 << 16;
+const CMD_RESP_48: u32 = 0b10 << 16;
+const CMD_RESP_48_BUSY: u32 = 0b11 << 16;
+const CMD_CRC_CHECK: u32 = 1 << 19;
+const CMD_INDEX_CHECK: u32 = 1 << 20;
+const CMD_DATA_PRESENT: u32 = 1 << 21;
+const CMD_INDEX_OFFSET: u32 = 24;
+
+const PRESENT_CMD_INHIBIT: u32 = 1 << 0;
+const PRESENT_DAT_INHIBIT: u32 = 1 << 1;
+const PRESENT_CARD_INSERTED: u32 = 1 << 16;
+
+const HOST_BUS_4BIT: u32 = 1 << 1;
+const HOST_HIGH_SPEED: u32 = 1 << 2;
+const HOST_DMA_ADMA2_32: u32 = 0b10 << 3;
+const HOST_DMA_MASK: u32 = 0b11 << 3;
+/// Card detect from the test level instead of the CD pad
+const HOST_CD_TEST_INSERTED: u32 = (1 << 6) | (1 << 7);
+const POWER_ON_3V3: u32 = (0b111 << 9) | (1 << 8);
+
+// Host control 2, upper half of HOST_CTRL2
+const UHS_MODE_OFFSET: u32 = 16;
+const UHS_MODE_WIDTH: u32 = 3;
+const UHS_MODE_SDR104: u32 = 0b011;
+const SIGNALING_1V8: u32 = 1 << 19;
+const EXECUTE_TUNING: u32 = 1 << 22;
+const SAMPLING_CLK_SEL: u32 = 1 << 23;
+
+const CLK_INTERNAL_EN: u32 = 1 << 0;
+const CLK_INTERNAL_STABLE: u32 = 1 << 1;
+const CLK_SD_EN: u32 = 1 << 2;
+/// Longest data timeout, TMCLK * 2^27
+const TIMEOUT_MAX: u32 = 0xe;
+const RESET_ALL: u32 = 1 << 24;
+const RESET_CMD: u32 = 1 << 25;
+const RESET_DAT: u32 = 1 << 26;
+
+const INT_CMD_COMPLETE: u32 = 1 << 0;
+const INT_XFER_COMPLETE: u32 = 1 << 1;
+const INT_BUF_WR_READY: u32 = 1 << 4;
+const INT_BUF_RD_READY: u32 = 1 << 5;
+const INT_ERROR: u32 = 1 << 15;
+const INT_CMD_TIMEOUT: u32 = 1 << 16;
+const INT_CMD_CRC: u32 = 1 << 17;
+const INT_DATA_TIMEOUT: u32 = 1 << 20;
+const INT_DATA_CRC: u32 = 1 << 21;
+const INT_ADMA: u32 = 1 << 25;
+const INT_CMD_ERRORS: u32 = 0x000f_0000;
+const INT_ALL: u32 = 0xffff_ffff;
+
+const ADMA_VALID: u16 = 1 << 0;
+const ADMA_END: u16 = 1 << 1;
+const ADMA_ACT_TRAN: u16 = 0b10 << 4;
+/// Bytes per ADMA2 descriptor, a multiple of [`BLOCK_SIZE`]
+const ADMA_MAX_LEN: usize = 0x8000;
+const ADMA_DESC_COUNT: usize = 16;
+/// Blocks moved by a single command
+const MAX_BLOCKS_PER_XFER: usize = ADMA_DESC_COUNT * ADMA_MAX_LEN / BLOCK_SIZE;
+
+/// Card status bits of R1 that are errors
+const R1_ERRORS: u32 = 0xfdf9_0008;
+/// Response flags of R5 that are errors, COM_CRC_ERROR, ILLEGAL_COMMAND, ERROR,
+/// FUNCTION_NUMBER and OUT_OF_RANGE
+const R5_ERRORS: u32 = 0xcb00;
+
+const CMD_TIMEOUT_US: u32 = 100_000;
+const DATA_TIMEOUT_US: u32 = 1_000_000;
+/// Software resets and the internal clock settle well within this
+const RESET_TIMEOUT_US: u32 = 100_000;
+/// Tuning commands before giving up, 40 per SDHCI
+const TUNING_LOOPS: usize = 40;
+
+regs! {
+    pub struct Regs {
+        sdma_addr: 0x000,
+        /// [11:0] block size, [31:16] block count
+        blk: 0x004,
+        argument: 0x008,
+        /// [15:0] transfer mode, [31:16] command
+        cmd: 0x00c,
+        resp[4]: 0x010 / 0x04,
+        buf_data: 0x020,
+        present_state: 0x024,
+        /// [7:0] host control 1, [15:8] power control
+        host_ctrl: 0x028,
+        /// [15:0] clock control, [19:16] timeout, [26:24] software reset
+        clk_ctrl: 0x02c,
+        /// [15:0] normal, [31:16] error, write 1 to clear
+        int_status: 0x030,
+        int_status_en: 0x034,
+        int_signal_en: 0x038,
+        /// [15:0] auto command error, [31:16] host control 2
+        host_ctrl2: 0x03c,
+        capabilities: 0x040,
+        capabilities1: 0x044,
+        adma_err: 0x054,
+        adma_addr_lo: 0x058,
+        adma_addr_hi: 0x05c,
+    }
+}
+
+/// ADMA2 descriptor, 32-bit addressing
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct AdmaDesc {
+    attr: u16,
+    len: u16,
+    addr: u32,
+}
+
+#[repr(C, align(64))]
+struct AdmaTable([AdmaDesc; ADMA_DESC_COUNT]);
+
+#[derive(Clone, Copy)]
+pub(super) enum Response {
+    None,
+    R1,
+    R1b,
+    R2,
+    R3,
+    R4,
+    R5,
+    R6,
+    R7,
+}
+
+impl Response {
+    fn flags(self) -> u32 {
+        match self {
+            Response::None => 0,
+            Response::R2 => CMD_RESP_136 | CMD_CRC_CHECK,
+            Response::R3 | Response::R4 => CMD_RESP_48,
+            Response::R1 | Response::R5 | Response::R6 | Response::R7 => CMD_RESP_48 | CMD_CRC_CHECK | CMD_INDEX_CHECK,
+            Response::R1b => CMD_RESP_48_BUSY | CMD_CRC_CHECK | CMD_INDEX_CHECK,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(super) struct Command {
+    index: u8,
+    response: Response,
+}
+
+pub(super) const fn cmd(index: u8, response: Response) -> Command {
+    Command { index, response }
+}
+
+pub(super) const GO_IDLE_STATE: Command = cmd(0, Response::None);
+pub(super) const ALL_SEND_CID: Command = cmd(2, Response::R2);
+pub(super) const SELECT_CARD: Command = cmd(7, Response::R1b);
+pub(super) const SEND_CSD: Command = cmd(9, Response::R2);
+pub(super) const SEND_STATUS: Command = cmd(13, Response::R1);
+pub(super) const SET_BLOCKLEN: Command = cmd(16, Response::R1);
+const READ_SINGLE_BLOCK: Command = cmd(17, Response::R1);
+const READ_MULTIPLE_BLOCK: Command = cmd(18, Response::R1);
+const WRITE_BLOCK: Command = cmd(24, Response::R1);
+const WRITE_MULTIPLE_BLOCK: Command = cmd(25, Response::R1);
+
+/// Direction of a data transfer
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(super) enum Direction {
+    Read,
+    Write,
+}
+
+/// Memory side of a DMA data transfer
+pub(super) struct Data {
+    addr: usize,
+    block_size: usize,
+    blocks: usize,
+    dir: Direction,
+}
+
+impl Data {
+    /// The controller writes into `blocks`
+    fn read_blocks(blocks: &mut [Block]) -> Self {
+        Self {
+            addr: blocks.as_mut_ptr() as usize,
+            block_size: BLOCK_SIZE,
+            blocks: blocks.len(),
+            dir: Direction::Read,
+        }
+    }
+
+    fn write_blocks(blocks: &[Block]) -> Self {
+        Self {
+            addr: blocks.as_ptr() as usize,
+            block_size: BLOCK_SIZE,
+            blocks: blocks.len(),
+            dir: Direction::Write,
+        }
+    }
+
+    /// Read into a small cache line aligned buffer
+    pub(super) fn read<const N: usize>(buf: &mut Aligned<N>) -> Self {
+        Self {
+            addr: buf.0.as_mut_ptr() as usize,
+            block_size: N,
+            blocks: 1,
+            dir: Direction::Read,
+        }
+    }
+}
+
+/// Cache line aligned buffer for short DMA reads, like status registers
+#[repr(C, align(64))]
+pub(super) struct Aligned<const N: usize>(pub [u8; N]);
+
+/// Bus timing
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(super) enum Timing {
+    /// Default speed, data on the falling edge
+    Legacy,
+    /// SD high speed and eMMC HS26/HS52
+    HighSpeed,
+    /// eMMC HS200, 1.8V signaling
+    Hs200,
+}
+
+pub(super) struct Host<T: Instance> {
+    adma: RefCell<AdmaTable>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Instance> Host<T> {
+    /// Reset the controller and power the bus at 3.3V, no clock to the card yet.
+    pub(super) fn new() -> Result<Self, Error> {
+        T::enable_and_reset();
+        // built first so a failed reset below still releases the pads and gates
+        let host = Self {
+            adma: RefCell::new(AdmaTable([AdmaDesc::default(); ADMA_DESC_COUNT])),
+            phantom: PhantomData,
+        };
+
+        let regs = T::regs();
+        regs.clk_ctrl().set_bits(RESET_ALL);
+        delay::wait(|| !regs.clk_ctrl().is_set(RESET_ALL), RESET_TIMEOUT_US, Error::Timeout)?;
+
+        regs.host_ctrl()
+            .write(POWER_ON_3V3 | HOST_DMA_ADMA2_32 | HOST_CD_TEST_INSERTED);
+        regs.clk_ctrl().write_field(16, 4, TIMEOUT_MAX);
+        // status only, no interrupt signals, the drivers poll
+        regs.int_status_en().write(INT_ALL);
+        regs.int_signal_en().write(0);
+        regs.int_status().write(INT_ALL);
+        Delay::new().delay_ms(1);
+
+        Ok(host)
+    }
+
+    /// Take card presence from the CD pad instead of assuming a card
+    pub(super) fn use_card_detect(&self) {
+        T::regs().host_ctrl().clear_bits(HOST_CD_TEST_INSERTED);
+    }
+
+    #[inline]
+    pub(super) fn is_card_present(&self) -> bool {
+        T::regs().present_state().is_set(PRESENT_CARD_INSERTED)
+    }
+
+    /// Back to identification mode, 1-bit bus at 400kHz and legacy timing
+    pub(super) fn reset_bus(&self) -> Result<(), Error> {
+        self.set_bus_width(BusWidth::One);
+        self.set_timing(Timing::Legacy);
+        self.set_clock(400_000)?;
+        // at least 74 clocks before the first command
+        Delay::new().delay_ms(1);
+        Ok(())
+    }
+
+    /// SD clock, `freq` or the closest below it. Returns the actual frequency.
+    pub(super) fn set_clock(&self, freq: u32) -> Result<Hertz, Error> {
+        let regs = T::regs();
+        let base = T::clock().raw();
+        // SDCLK = base / (2 * div), div 0 passes base through
+        let div = if freq >= base {
+            0
+        } else {
+            base.div_ceil(2 * freq).min(0x3ff)
+        };
+
+        regs.clk_ctrl().clear_bits(CLK_SD_EN);
+        let field = ((div & 0xff) << 8) | ((div >> 8) << 6) | CLK_INTERNAL_EN;
+        regs.clk_ctrl().write_field(0, 16, field);
+        delay::wait(
+            || regs.clk_ctrl().is_set(CLK_INTERNAL_STABLE),
+            RESET_TIMEOUT_US,
+            Error::Timeout,
+        )?;
+        regs.clk_ctrl().set_bits(CLK_SD_EN);
+
+        Ok(Hertz::from_raw(if div == 0 { base } else { base / (2 * div) }))
+    }
+
+    pub(super) fn set_bus_width(&self, width: BusWidth) {
+        let host_ctrl = T::regs().host_ctrl();
+        match width {
+            BusWidth::One => host_ctrl.clear_bits(HOST_BUS_4BIT),
+            BusWidth::Four => host_ctrl.set_bits(HOST_BUS_4BIT),
+        }
+    }
+
+    /// Switch the host side timing, the card has to be switched first.
+    pub(super) fn set_timing(&self, timing: Timing) {
+        let regs = T::regs();
+        // SD clock must be off while changing the UHS mode
+        let clk_on = regs.clk_ctrl().is_set(CLK_SD_EN);
+        regs.clk_ctrl().clear_bits(CLK_SD_EN);
+        match timing {
+            Timing::Legacy => {
+                regs.host_ctrl().clear_bits(HOST_HIGH_SPEED);
+                regs.host_ctrl2().write_field(UHS_MODE_OFFSET, UHS_MODE_WIDTH, 0);
+                regs.host_ctrl2().clear_bits(SIGNALING_1V8);
+            }
+            Timing::HighSpeed => {
+                regs.host_ctrl().set_bits(HOST_HIGH_SPEED);
+                regs.host_ctrl2().write_field(UHS_MODE_OFFSET, UHS_MODE_WIDTH, 0);
+            }
+            Timing::Hs200 => {
+                regs.host_ctrl().set_bits(HOST_HIGH_SPEED);
+                regs.host_ctrl2().set_bits(SIGNALING_1V8);
+                regs.host_ctrl2()
+                    .write_field(UHS_MODE_OFFSET, UHS_MODE_WIDTH, UHS_MODE_SDR104);
+            }
+        }
+        if clk_on {
+            regs.clk_ctrl().set_bits(CLK_SD_EN);
+        }
+    }
+
+    /// Find the sampling point with the tuning command, e.g. eMMC CMD21 with `block_size` bytes
+    pub(super) fn tune(&self, command: Command, block_size: usize) -> Result<(), Error> {
+        let regs = T::regs();
+        regs.host_ctrl2().clear_bits(SAMPLING_CLK_SEL);
+        regs.host_ctrl2().set_bits(EXECUTE_TUNING);
+        regs.blk().write((1 << 16) | block_size as u32);
+
+        for _ in 0..TUNING_LOOPS {
+            // the tuning block stays in the controller, no need to read it
+            self.issue(command, 0, XFER_READ)?;
+            self.wait_int(INT_BUF_RD_READY, CMD_TIMEOUT_US)?;
+            if !regs.host_ctrl2().is_set(EXECUTE_TUNING) {
+                break;
+            }
+        }
+
+        if regs.host_ctrl2().is_set(EXECUTE_TUNING) || !regs.host_ctrl2().is_set(SAMPLING_CLK_SEL) {
+            regs.host_ctrl2().clear_bits(EXECUTE_TUNING | SAMPLING_CLK_SEL);
+            self.reset_lines(INT_ALL)?;
+            return Err(Error::Tuning);
+        }
+        Ok(())
+    }
+
+    pub(super) fn send_app_command(&self, command: Command, rca: u16, arg: u32) -> Result<[u32; 4], Error> {
+        const APP_CMD: Command = cmd(55, Response::R1);
+        self.send_command(APP_CMD, (rca as u32) << 16)?;
+        self.send_command(command, arg)
+    }
+
+    pub(super) fn send_command(&self, command: Command, arg: u32) -> Result<[u32; 4], Error> {
+        self.issue(command, arg, 0)?;
+        if let Response::R1b = command.response {
+            self.wait_int(INT_XFER_COMPLETE, DATA_TIMEOUT_US)?;
+        }
+        self.response(command)
+    }
+
+    /// Read blocks starting at `start`, `byte_addressed` for SDSC and small eMMC.
+    pub(super) fn read_blocks(&self, start: u32, byte_addressed: bool, blocks: &mut [Block]) -> Result<(), Error> {
+        for (i, chunk) in blocks.chunks_mut(MAX_BLOCKS_PER_XFER).enumerate() {
+            let command = if chunk.len() == 1 {
+                READ_SINGLE_BLOCK
+            } else {
+                READ_MULTIPLE_BLOCK
+            };
+            let block = start + (i * MAX_BLOCKS_PER_XFER) as u32;
+            let arg = data_address(block, byte_addressed);
+            self.transfer(command, arg, Data::read_blocks(chunk))?;
+        }
+        Ok(())
+    }
+
+    /// Write blocks starting at `start`, `byte_addressed` for SDSC and small eMMC.
+    pub(super) fn write_blocks(&self, start: u32, byte_addressed: bool, blocks: &[Block]) -> Result<(), Error> {
+        for (i, chunk) in blocks.chunks(MAX_BLOCKS_PER_XFER).enumerate() {
+            let command = if chunk.len() == 1 {
+                WRITE_BLOCK
+            } else {
+                WRITE_MULTIPLE_BLOCK
+            };
+            let block = start + (i * MAX_BLOCKS_PER_XFER) as u32;
+            let arg = data_address(block, byte_addressed);
+            self.transfer(command, arg, Data::write_blocks(chunk))?;
+        }
+        Ok(())
+    }
+
+    /// Issue a data command and move `data` with ADMA2
+    pub(super) fn transfer(&self, command: Command, arg: u32, data: Data) -> Result<(), Error> {
+        let Data {
+            addr,
+            block_size,
+            blocks,
+            dir,
+        } = data;
+        let len = block_size * blocks;
+        let regs = T::regs();
+
+        let mut adma = self.adma.borrow_mut();
+        let chunks = len.div_ceil(ADMA_MAX_LEN);
+        debug_assert!(chunks <= ADMA_DESC_COUNT);
+        for (i, desc) in adma.0.iter_mut().take(chunks).enumerate() {
+            let offset = i * ADMA_MAX_LEN;
+            let mut attr = ADMA_VALID | ADMA_ACT_TRAN;
+            if i == chunks - 1 {
+                attr |= ADMA_END;
+            }
+            *desc = AdmaDesc {
+                attr,
+                len: (len - offset).min(ADMA_MAX_LEN) as u16,
+                addr: (addr + offset) as u32,
+            };
+        }
+        let table = adma.0.as_ptr() as usize;
+        cache::clean(table, core::mem::size_of::<AdmaTable>());
+        match dir {
+            Direction::Write => cache::clean(addr, len),
+            // nothing dirty may be evicted over the incoming data
+            Direction::Read => cache::flush(addr, len),
+        }
+
+        regs.host_ctrl().modify(|r| (r & !HOST_DMA_MASK) | HOST_DMA_ADMA2_32);
+        regs.adma_addr_lo().write(table as u32);
+        regs.adma_addr_hi().write(0);
+        regs.blk().write(((blocks as u32) << 16) | block_size as u32);
+
+        let mut mode = XFER_DMA_EN | XFER_BLK_CNT_EN;
+        if blocks > 1 {
+            mode |= XFER_MULTI_BLK | XFER_AUTO_CMD12;
+        }
+        if dir == Direction::Read {
+            mode |= XFER_READ;
+        }
+
+        let result = self
+            .issue(command, arg, mode)
+            .and_then(|_| self.response(command))
+            .and_then(|_| self.wait_int(INT_XFER_COMPLETE, DATA_TIMEOUT_US));
+        if dir == Direction::Read {
+            cache::invalidate(addr, len);
+        }
+        result
+    }
+
+    /// Issue a data command and move `buf` through the buffer data port, for
+    /// buffers without DMA alignment. `buf.len()` must be `block_size * blocks`.
+    /// No CMD12 is sent, as SDIO CMD53 doesn't take one.
+    pub(super) fn transfer_pio(
+        &self,
+        command: Command,
+        arg: u32,
+        mut buf: Pio<'_>,
+        block_size: usize,
+        blocks: usize,
+    ) -> Result<[u32; 4], Error> {
+        let regs = T::regs();
+        debug_assert_eq!(buf.len(), block_size * blocks);
+        regs.blk().write(((blocks as u32) << 16) | block_size as u32);
+
+        let mut mode = XFER_BLK_CNT_EN;
+        if blocks > 1 {
+            mode |= XFER_MULTI_BLK;
+        }
+        if let Pio::Read(_) = buf {
+            mode |= XFER_READ;
+        }
+        self.issue(command, arg, mode)?;
+        let resp = self.response(command)?;
+
+        for block in 0..blocks {
+            let range = block * block_size..(block + 1) * block_size;
+            match &mut buf {
+                Pio::Read(buf) => {
+                    self.wait_int(INT_BUF_RD_READY, DATA_TIMEOUT_US)?;
+                    for chunk in buf[range].chunks_mut(4) {
+                        let word = regs.buf_data().read().to_le_bytes();
+                        chunk.copy_from_slice(&word[..chunk.len()]);
+                    }
+                }
+                Pio::Write(buf) => {
+                    self.wait_int(INT_BUF_WR_READY, DATA_TIMEOUT_US)?;
+                    for chunk in buf[range].chunks(4) {
+                        let mut word = [0; 4];
+                        word[..chunk.len()].copy_from_slice(chunk);
+                        regs.buf_data().write(u32::from_le_bytes(word));
+                    }
+                }
+            }
+        }
+        self.wait_int(INT_XFER_COMPLETE, DATA_TIMEOUT_US)?;
+        Ok(resp)
+    }
+
+    fn issue(&self, command: Command, arg: u32, xfer_mode: u32) -> Result<(), Error> {
+        let regs = T::regs();
+        let mut inhibit = PRESENT_CMD_INHIBIT;
+        if xfer_mode != 0 || matches!(command.response, Response::R1b) {
+            inhibit |= PRESENT_DAT_INHIBIT;
+        }
+        self.wait(|| !regs.present_state().is_set(inhibit), CMD_TIMEOUT_US)?;
+
+        regs.int_status().write(INT_ALL);
+        regs.argument().write(arg);
+        let mut word = ((command.index as u32) << CMD_INDEX_OFFSET) | command.response.flags();
+        if xfer_mode != 0 {
+            word |= CMD_DATA_PRESENT;
+        }
+        regs.cmd().write(word | xfer_mode);
+
+        self.wait_int(INT_CMD_COMPLETE, CMD_TIMEOUT_US)
+    }
+
+    fn response(&self, command: Command) -> Result<[u32; 4], Error> {
+        let regs = T::regs();
+        let resp = [
+            regs.resp(0).read(),
+            regs.resp(1).read(),
+            regs.resp(2).read(),
+            regs.resp(3).read(),
+        ];
+        match command.response {
+            Response::R1 | Response::R1b if resp[0] & R1_ERRORS != 0 => Err(Error::Command),
+            Response::R5 if resp[0] & R5_ERRORS != 0 => Err(Error::Command),
+            _ => Ok(resp),
+        }
+    }
+
+    /// Wait for any of `mask` in the normal interrupt status, then clear it.
+    fn wait_int(&self, mask: u32, timeout_us: u32) -> Result<(), Error> {
+        let regs = T::regs();
+        let mut error = None;
+        self.wait(
+            || {
+                let status = regs.int_status().read();
+                if status & INT_ERROR != 0 {
+                    error = Some(status);
+                    true
+                } else {
+                    status & mask != 0
+                }
+            },
+            timeout_us,
+        )?;
+
+        if let Some(status) = error {
+            regs.int_status().write(status);
+            self.reset_lines(status)?;
+            return Err(match status {
+                s if s & INT_CMD_TIMEOUT != 0 => Error::CommandTimeout,
+                s if s & INT_CMD_CRC != 0 => Error::CommandCrc,
+                s if s & INT_CMD_ERRORS != 0 => Error::Command,
+                s if s & INT_DATA_TIMEOUT != 0 => Error::DataTimeout,
+                s if s & INT_DATA_CRC != 0 => Error::DataCrc,
+                s if s & INT_ADMA != 0 => Error::Adma,
+                _ => Error::Data,
+            });
+        }
+        regs.int_status().write(mask);
+        Ok(())
+    }
+
+    fn wait(&self, done: impl FnMut() -> bool, timeout_us: u32) -> Result<(), Error> {
+        delay::wait(done, timeout_us, Error::Timeout).or_else(|e| {
+            self.reset_lines(INT_ALL)?;
+            Err(e)
+        })
+    }
+
+    /// Software reset the command and/or data line state machines after an error
+    fn reset_lines(&self, status: u32) -> Result<(), Error> {
+        let regs = T::regs();
+        let mut reset = RESET_CMD;
+        if status & !INT_CMD_ERRORS & 0xffff_0000 != 0 {
+            reset |= RESET_DAT;
+        }
+        regs.clk_ctrl().set_bits(reset);
+        delay::wait(|| !regs.clk_ctrl().is_set(reset), RESET_TIMEOUT_US, Error::Timeout)
+    }
+}
+
+impl<T: Instance> Drop for Host<T> {
+    fn drop(&mut self) {
+        pinmux::release_owner(T::NAME);
+        T::disable();
+    }
+}
+
+/// Memory side of a buffer data port transfer
+pub(super) enum Pio<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+impl Pio<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Pio::Read(buf) => buf.len(),
+            Pio::Write(buf) => buf.len(),
+        }
+    }
+}
+
+/// Data address argument, bytes or blocks
+#[inline]
+fn data_address(block: u32, byte_addressed: bool) -> u32 {
+    if byte_addressed {
+        block * BLOCK_SIZE as u32
+    } else {
+        block
+    }
+}
+
+/// R2 responses come without the CRC byte, shift them back to bits [127:8]
+pub(super) fn response_136(resp: [u32; 4]) -> u128 {
+    let raw = resp.iter().rev().fold(0u128, |acc, &word| (acc << 32) | word as u128);
+    raw << 8
+}
@@ -0,0 +1,369 @@
+//! SDIO functions, e.g. Wi-Fi modules on SDIO1
+//!
+//! [`Sdio::init_card`] brings the card to a 4-bit bus and high speed when it
+//! supports them. Registers are accessed with CMD52, one byte at a time, and
+//! CMD53 for larger transfers through the buffer data port, so buffers need no
+//! DMA alignment.
+//!
+//! Card interrupts are not routed, poll [`Sdio::pending_interrupts`].
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use embedded_hal::delay::DelayNs;
+use fugit::HertzU32 as Hertz;
+
+use super::host::{self, cmd, Command, Host, Pio, Response, Timing};
+use super::{attach_pins, BusWidth, ClkPin, CmdPin, Config, D0Pin, D1Pin, D2Pin, D3Pin, Error, Instance};
+use crate::delay::Delay;
+use crate::{into_ref, Peripheral};
+
+const LOW_SPEED_CLOCK: u32 = 400_000;
+const DEFAULT_SPEED_CLOCK: u32 = 25_000_000;
+const HIGH_SPEED_CLOCK: u32 = 50_000_000;
+
+/// Highest function number
+pub const MAX_FUNCTION: u8 = 7;
+/// Register addresses are 17 bits
+const MAX_ADDRESS: u32 = 0x1_ffff;
+/// Byte mode transfers up to 512 bytes
+const MAX_BYTE_COUNT: usize = 512;
+/// Block mode transfers up to 511 blocks
+const MAX_BLOCK_COUNT: usize = 511;
+
+/// OCR, card power up done
+const OCR_BUSY: u32 = 1 << 31;
+/// OCR, 2.7V ~ 3.6V
+const OCR_VOLTAGE_WINDOW: u32 = 0x00ff_8000;
+const OCR_MEMORY_PRESENT: u32 = 1 << 27;
+
+const POWER_UP_TIMEOUT_MS: u32 = 1_000;
+const FUNCTION_READY_TIMEOUT_MS: u32 = 1_000;
+
+// CCCR, card common control registers of function 0
+const CCCR_IO_ENABLE: u32 = 0x02;
+const CCCR_IO_READY: u32 = 0x03;
+const CCCR_INT_ENABLE: u32 = 0x04;
+const CCCR_INT_PENDING: u32 = 0x05;
+const CCCR_BUS_INTERFACE: u32 = 0x07;
+const CCCR_CAPABILITY: u32 = 0x08;
+const CCCR_BLOCK_SIZE: u32 = 0x10;
+const CCCR_BUS_SPEED: u32 = 0x13;
+/// FBR, function basic registers of function n at `n * FBR_SIZE`
+const FBR_SIZE: u32 = 0x100;
+const FBR_BLOCK_SIZE: u32 = 0x10;
+
+const INT_ENABLE_MASTER: u8 = 1 << 0;
+const BUS_WIDTH_MASK: u8 = 0b11;
+const BUS_WIDTH_4BIT: u8 = 0b10;
+/// Multi-block CMD53
+const CAPABILITY_SMB: u8 = 1 << 1;
+/// Low speed card, 400kHz only
+const CAPABILITY_LSC: u8 = 1 << 6;
+/// Low speed card that supports a 4-bit bus
+const CAPABILITY_4BLS: u8 = 1 << 7;
+const BUS_SPEED_SHS: u8 = 1 << 0;
+const BUS_SPEED_EHS: u8 = 1 << 1;
+
+const IO_SEND_OP_COND: Command = cmd(5, Response::R4);
+const SEND_RELATIVE_ADDR: Command = cmd(3, Response::R6);
+const IO_RW_DIRECT: Command = cmd(52, Response::R5);
+const IO_RW_EXTENDED: Command = cmd(53, Response::R5);
+
+/// Card identified by [`Sdio::init_card`]
+#[derive(Debug, Clone, Copy)]
+pub struct SdioInfo {
+    pub rca: u16,
+    /// Number of I/O functions besides function 0
+    pub functions: u8,
+    /// Combo card, also has a memory part
+    pub memory_present: bool,
+    /// Multi-block CMD53 supported
+    pub multi_block: bool,
+    pub bus_width: BusWidth,
+    pub clock: Hertz,
+}
+
+/// SDIO card driver
+pub struct Sdio<'d, T: Instance> {
+    host: Host<T>,
+    config: Config,
+    card: Cell<Option<SdioInfo>>,
+    /// CMD53 block size of each function
+    block_sizes: Cell<[u16; MAX_FUNCTION as usize + 1]>,
+    phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> Sdio<'d, T> {
+    /// Create a driver with a 4-bit data bus. The card is not touched until [`Sdio::init_card`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_4bit(
+        _peri: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T>> + 'd,
+        cmd: impl Peripheral<P = impl CmdPin<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        d1: impl Peripheral<P = impl D1Pin<T>> + 'd,
+        d2: impl Peripheral<P = impl D2Pin<T>> + 'd,
+        d3: impl Peripheral<P = impl D3Pin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, clk, cmd, d0, d1, d2, d3);
+        attach_pins!(clk, cmd, d0, d1, d2, d3)?;
+
+        Self::new_inner(config)
+    }
+
+    /// Create a driver with a 1-bit data bus, `config.bus_width` is ignored.
+    pub fn new_1bit(
+        _peri: impl Peripheral<P = T> + 'd,
+        clk: impl Peripheral<P = impl ClkPin<T>> + 'd,
+        cmd: impl Peripheral<P = impl CmdPin<T>> + 'd,
+        d0: impl Peripheral<P = impl D0Pin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, clk, cmd, d0);
+        attach_pins!(clk, cmd, d0)?;
+
+        Self::new_inner(Config {
+            bus_width: BusWidth::One,
+            ..config
+        })
+    }
+
+    fn new_inner(config: Config) -> Result<Self, Error> {
+        Ok(Self {
+            host: Host::new()?,
+            config,
+            card: Cell::new(None),
+            block_sizes: Cell::new([0; MAX_FUNCTION as usize + 1]),
+            phantom: PhantomData,
+        })
+    }
+
+    /// The initialized card, if any
+    #[inline]
+    pub fn card(&self) -> Option<SdioInfo> {
+        self.card.get()
+    }
+
+    /// Identify and select the card, then switch bus width and speed according to the config.
+    pub fn init_card(&mut self) -> Result<SdioInfo, Error> {
+        self.card.set(None);
+        self.block_sizes.set([0; MAX_FUNCTION as usize + 1]);
+
+        let host = &self.host;
+        host.reset_bus()?;
+
+        let ocr = host.send_command(IO_SEND_OP_COND, 0)?[0];
+        let functions = ((ocr >> 28) & 0b111) as u8;
+        if functions == 0 || ocr & OCR_VOLTAGE_WINDOW == 0 {
+            return Err(Error::UnsupportedCard);
+        }
+        let mut ocr = 0;
+        for _ in 0..POWER_UP_TIMEOUT_MS {
+            ocr = host.send_command(IO_SEND_OP_COND, OCR_VOLTAGE_WINDOW)?[0];
+            if ocr & OCR_BUSY != 0 {
+                break;
+            }
+            Delay::new().delay_ms(1);
+        }
+        if ocr & OCR_BUSY == 0 {
+            return Err(Error::UnsupportedCard);
+        }
+
+        let rca = (host.send_command(SEND_RELATIVE_ADDR, 0)?[0] >> 16) as u16;
+        host.send_command(host::SELECT_CARD, (rca as u32) << 16)?;
+
+        let capability = self.io_rw_direct(false, 0, CCCR_CAPABILITY, 0)?;
+        let low_speed = capability & CAPABILITY_LSC != 0;
+
+        let mut bus_width = BusWidth::One;
+        if self.config.bus_width == BusWidth::Four && (!low_speed || capability & CAPABILITY_4BLS != 0) {
+            let bus = self.io_rw_direct(false, 0, CCCR_BUS_INTERFACE, 0)?;
+            self.io_rw_direct(true, 0, CCCR_BUS_INTERFACE, (bus & !BUS_WIDTH_MASK) | BUS_WIDTH_4BIT)?;
+            host.set_bus_width(BusWidth::Four);
+            bus_width = BusWidth::Four;
+        }
+
+        let clock = if low_speed {
+            host.set_clock(LOW_SPEED_CLOCK)?
+        } else if self.config.high_speed && self.switch_high_speed()? {
+            host.set_timing(Timing::HighSpeed);
+            host.set_clock(HIGH_SPEED_CLOCK)?
+        } else {
+            host.set_clock(DEFAULT_SPEED_CLOCK)?
+        };
+
+        let card = SdioInfo {
+            rca,
+            functions,
+            memory_present: ocr & OCR_MEMORY_PRESENT != 0,
+            multi_block: capability & CAPABILITY_SMB != 0,
+            bus_width,
+            clock,
+        };
+        self.card.set(Some(card));
+        Ok(card)
+    }
+
+    /// CMD52, read a register of `func`
+    pub fn read_byte(&self, func: u8, addr: u32) -> Result<u8, Error> {
+        self.check(func, addr)?;
+        self.io_rw_direct(false, func, addr, 0)
+    }
+
+    /// CMD52, write a register of `func`
+    pub fn write_byte(&self, func: u8, addr: u32, value: u8) -> Result<(), Error> {
+        self.check(func, addr)?;
+        self.io_rw_direct(true, func, addr, value)?;
+        Ok(())
+    }
+
+    /// Enable I/O function `func` and wait until it's ready
+    pub fn enable_function(&mut self, func: u8) -> Result<(), Error> {
+        self.check_function(func)?;
+        let mask = 1 << func;
+        let enabled = self.io_rw_direct(false, 0, CCCR_IO_ENABLE, 0)?;
+        self.io_rw_direct(true, 0, CCCR_IO_ENABLE, enabled | mask)?;
+
+        for _ in 0..FUNCTION_READY_TIMEOUT_MS {
+            if self.io_rw_direct(false, 0, CCCR_IO_READY, 0)? & mask != 0 {
+                return Ok(());
+            }
+            Delay::new().delay_ms(1);
+        }
+        Err(Error::Timeout)
+    }
+
+    pub fn disable_function(&mut self, func: u8) -> Result<(), Error> {
+        self.check_function(func)?;
+        let enabled = self.io_rw_direct(false, 0, CCCR_IO_ENABLE, 0)?;
+        self.io_rw_direct(true, 0, CCCR_IO_ENABLE, enabled & !(1 << func))?;
+        Ok(())
+    }
+
+    /// Let `func` raise card interrupts, see [`Sdio::pending_interrupts`]
+    pub fn enable_interrupt(&mut self, func: u8) -> Result<(), Error> {
+        self.check_function(func)?;
+        let enabled = self.io_rw_direct(false, 0, CCCR_INT_ENABLE, 0)?;
+        self.io_rw_direct(true, 0, CCCR_INT_ENABLE, enabled | (1 << func) | INT_ENABLE_MASTER)?;
+        Ok(())
+    }
+
+    pub fn disable_interrupt(&mut self, func: u8) -> Result<(), Error> {
+        self.check_function(func)?;
+        let mut enabled = self.io_rw_direct(false, 0, CCCR_INT_ENABLE, 0)? & !(1 << func);
+        if enabled & !INT_ENABLE_MASTER == 0 {
+            enabled = 0;
+        }
+        self.io_rw_direct(true, 0, CCCR_INT_ENABLE, enabled)?;
+        Ok(())
+    }
+
+    /// Functions with a pending interrupt, bit n for function n
+    pub fn pending_interrupts(&self) -> Result<u8, Error> {
+        self.card.get().ok_or(Error::NotInitialized)?;
+        self.io_rw_direct(false, 0, CCCR_INT_PENDING, 0)
+    }
+
+    /// Block size of `func` for block mode CMD53, up to 2048 bytes
+    pub fn set_block_size(&mut self, func: u8, size: u16) -> Result<(), Error> {
+        self.check(func, 0)?;
+        if size == 0 || size > 2048 {
+            return Err(Error::InvalidArgument);
+        }
+        let base = if func == 0 {
+            CCCR_BLOCK_SIZE
+        } else {
+            func as u32 * FBR_SIZE + FBR_BLOCK_SIZE
+        };
+        let [lo, hi] = size.to_le_bytes();
+        self.io_rw_direct(true, 0, base, lo)?;
+        self.io_rw_direct(true, 0, base + 1, hi)?;
+
+        let mut sizes = self.block_sizes.get();
+        sizes[func as usize] = size;
+        self.block_sizes.set(sizes);
+        Ok(())
+    }
+
+    /// CMD53, read `buf.len()` bytes from `func` at `addr`.
+    ///
+    /// Up to 512 bytes go in byte mode, longer reads must be a multiple of the
+    /// block size set with [`Sdio::set_block_size`]. With `increment` the
+    /// address advances for each byte, otherwise all bytes come from a FIFO at `addr`.
+    pub fn read(&self, func: u8, addr: u32, buf: &mut [u8], increment: bool) -> Result<(), Error> {
+        let (arg, block_size, blocks) = self.extended_arg(false, func, addr, buf.len(), increment)?;
+        self.host
+            .transfer_pio(IO_RW_EXTENDED, arg, Pio::Read(buf), block_size, blocks)?;
+        Ok(())
+    }
+
+    /// CMD53, write `buf` to `func` at `addr`, see [`Sdio::read`] for the size rules.
+    pub fn write(&self, func: u8, addr: u32, buf: &[u8], increment: bool) -> Result<(), Error> {
+        let (arg, block_size, blocks) = self.extended_arg(true, func, addr, buf.len(), increment)?;
+        self.host
+            .transfer_pio(IO_RW_EXTENDED, arg, Pio::Write(buf), block_size, blocks)?;
+        Ok(())
+    }
+
+    /// CMD53 argument, block size and block count for a `len` bytes transfer
+    fn extended_arg(
+        &self,
+        write: bool,
+        func: u8,
+        addr: u32,
+        len: usize,
+        increment: bool,
+    ) -> Result<(u32, usize, usize), Error> {
+        let card = self.check(func, addr)?;
+        let mut arg = ((write as u32) << 31) | ((func as u32) << 28) | ((increment as u32) << 26) | (addr << 9);
+
+        if len > 0 && len <= MAX_BYTE_COUNT {
+            // byte mode, a count of 0 means 512
+            arg |= (len % MAX_BYTE_COUNT) as u32;
+            return Ok((arg, len, 1));
+        }
+
+        let block_size = self.block_sizes.get()[func as usize] as usize;
+        if !card.multi_block || block_size == 0 || !len.is_multiple_of(block_size) || len / block_size > MAX_BLOCK_COUNT
+        {
+            return Err(Error::InvalidArgument);
+        }
+        let blocks = len / block_size;
+        arg |= (1 << 27) | blocks as u32;
+        Ok((arg, block_size, blocks))
+    }
+
+    fn check(&self, func: u8, addr: u32) -> Result<SdioInfo, Error> {
+        let card = self.card.get().ok_or(Error::NotInitialized)?;
+        if func > card.functions || addr > MAX_ADDRESS {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(card)
+    }
+
+    fn check_function(&self, func: u8) -> Result<(), Error> {
+        if func == 0 {
+            return Err(Error::InvalidArgument);
+        }
+        self.check(func, 0).map(|_| ())
+    }
+
+    /// Enable high speed in the CCCR, false if the card doesn't support it
+    fn switch_high_speed(&self) -> Result<bool, Error> {
+        let speed = self.io_rw_direct(false, 0, CCCR_BUS_SPEED, 0)?;
+        if speed & BUS_SPEED_SHS == 0 {
+            return Ok(false);
+        }
+        self.io_rw_direct(true, 0, CCCR_BUS_SPEED, speed | BUS_SPEED_EHS)?;
+        Ok(true)
+    }
+
+    /// CMD52, returns the register value, after the write for writes
+    fn io_rw_direct(&self, write: bool, func: u8, addr: u32, data: u8) -> Result<u8, Error> {
+        let arg = ((write as u32) << 31) | ((func as u32) << 28) | (addr << 9) | data as u32;
+        let arg = if write { arg | (1 << 27) } else { arg };
+        Ok(self.host.send_command(IO_RW_DIRECT, arg)?[0] as u8)
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(non_camel_case_types)]
-use core::ptr;
+use crate::efuse::{self, SecureBoot, FTSN};
+use crate::pac;
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -99,6 +100,32 @@ impl DDR_Vendor {
     }
 }
 
+/// SoC family the vendor ships with the package and DDR of [`ChipInfo::chip_id`]
+///
+/// This is a guess, not an identification: the chip can't report its part
+/// number, only its package and SiP DDR. The CV1812C and CV1812H carry the
+/// same 2Gbit DDR3 as the SG2002 and show up as `SG200x` as well.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum ChipVariant {
+    Unknown,
+    /// CV1800B, CV1801C, 512Mbit DDR2, no ARM core
+    CV180x,
+    /// CV1811C, CV1811H, 1Gbit DDR3
+    CV181x,
+    /// SG2002 with 2Gbit DDR3, SG2000 with 4Gbit DDR3
+    SG200x,
+}
+impl ChipVariant {
+    fn from_chip_id(chip_id: u32) -> ChipVariant {
+        match chip_id {
+            0x1810c | 0x1810f => ChipVariant::CV180x,
+            0x1811c | 0x1811f => ChipVariant::CV181x,
+            0x1812c | 0x1812f | 0x1813f => ChipVariant::SG200x,
+            _ => ChipVariant::Unknown,
+        }
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
 pub struct ChipInfo {
     pub ddr_vendor: DDR_Vendor,
@@ -106,16 +133,17 @@ pub struct ChipInfo {
     pub ddr_type: DDR_Type,
     pub package: ChipPackage,
     pub chip_id: u32,
+    /// Guessed from `chip_id`, see [`ChipVariant`]
+    pub variant: ChipVariant,
+    /// FTSN3:FTSN4, see [`crate::efuse::Efuse::unique_id`] for 128 bits
+    pub serial: u64,
+    pub secure_boot: SecureBoot,
 }
 
 pub fn read_chip_info() -> ChipInfo {
-    let conf_info = unsafe { ptr::read_volatile(0x03000004 as *const u32) };
-    let efuse_leakage = unsafe { ptr::read_volatile(0x03050108 as *const u32) };
-    //let ftsn3 = ptr::read_volatile(0x0305010c as *const u32);
-    //let ftsn4 = ptr::read_volatile(0x03050110 as *const u32);
-
-    // pkg_type = FIELD_GET(conf_info, 30, 28);
-    let pkg_type = (conf_info >> 28) & 0x7;
+    let top = unsafe { &*pac::TOP::PTR };
+    let pkg_type = top.conf_info().read().pkg_type().bits();
+    let efuse_leakage = efuse::shadow(FTSN[2]);
 
     let mut ddr_vendor = DDR_Vendor::from_u32(0);
     let mut ddr_capacity = DDR_Capacity::Unknown;
@@ -186,6 +214,9 @@ pub fn read_chip_info() -> ChipInfo {
         ddr_type,
         package,
         chip_id,
+        variant: ChipVariant::from_chip_id(chip_id),
+        serial: efuse::serial(),
+        secure_boot: efuse::secure_boot(),
     };
     chip_info
 }
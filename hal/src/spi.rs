@@ -0,0 +1,506 @@
+//! SPI, DesignWare APB SSI in master mode
+//!
+//! The SG2002 has 4 SPI controllers, the Duo header brings out SPI2 on GP6
+//! (SCK), GP7 (MOSI) and GP8 (MISO). Chip select is not driven by the
+//! driver, use a GPIO, e.g. through `embedded-hal-bus`. The controller's own
+//! select line drops whenever the TX FIFO runs empty, which breaks long
+//! transfers.
+//!
+//! Frames are 8 bits. Blocking transfers go through the FIFOs, the `dma_*`
+//! methods hand the data register to [`crate::dma`] channels.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use embedded_hal::spi::{Mode, Phase, Polarity, MODE_0};
+use fugit::HertzU32 as Hertz;
+
+use crate::clocks::{clocks, Gate};
+use crate::dma::{self, Transfer, TransferOptions};
+use crate::gpio::Pull;
+use crate::mmio::regs;
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, pinmux, Peripheral};
+
+// CTRLR0
+/// Frame size - 1, [3:0] on 16-bit configurations
+const CTRLR0_DFS_OFFSET: u32 = 0;
+/// Frame size - 1, [20:16] on 32-bit configurations, reserved otherwise
+const CTRLR0_DFS_32_OFFSET: u32 = 16;
+const CTRLR0_SCPH: u32 = 1 << 6;
+const CTRLR0_SCPOL: u32 = 1 << 7;
+const CTRLR0_TMOD_OFFSET: u32 = 8;
+const CTRLR0_TMOD_WIDTH: u32 = 2;
+
+// SR
+const SR_BUSY: u32 = 1 << 0;
+const SR_TFNF: u32 = 1 << 1;
+const SR_TFE: u32 = 1 << 2;
+const SR_RFNE: u32 = 1 << 3;
+
+/// RISR, receive FIFO overflow
+const RISR_RXOIR: u32 = 1 << 3;
+
+// DMACR
+const DMACR_RDMAE: u32 = 1 << 0;
+const DMACR_TDMAE: u32 = 1 << 1;
+
+/// Frames of one RX only transfer, CTRLR1 is 16 bits
+const MAX_RX_ONLY_FRAMES: usize = 0x1_0000;
+/// Largest FIFO the depth probe looks for
+const MAX_FIFO_DEPTH: u32 = 256;
+const FRAME_BITS: u32 = 8;
+
+regs! {
+    pub struct Regs {
+        ctrlr0: 0x00,
+        ctrlr1: 0x04,
+        ssienr: 0x08,
+        ser: 0x10,
+        baudr: 0x14,
+        txftlr: 0x18,
+        rxftlr: 0x1c,
+        txflr: 0x20,
+        rxflr: 0x24,
+        sr: 0x28,
+        imr: 0x2c,
+        risr: 0x34,
+        icr: 0x48,
+        dmacr: 0x4c,
+        dmatdlr: 0x50,
+        dmardlr: 0x54,
+        dr: 0x60,
+    }
+}
+
+/// Transfer mode, CTRLR0.TMOD
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum TransferMode {
+    Duplex = 0,
+    TxOnly = 1,
+    RxOnly = 2,
+}
+
+pub struct Config {
+    pub frequency: Hertz,
+    pub mode: Mode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            frequency: Hertz::MHz(1),
+            mode: MODE_0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Requested frequency can't be generated from the SPI clock
+    InvalidFrequency,
+    /// Received frames were lost
+    Overrun,
+    /// Read and write buffers of a DMA transfer differ in length
+    InvalidLength,
+    Dma(dma::Error),
+    /// A pad is held by another driver
+    Pinmux(pinmux::Conflict),
+}
+
+impl From<dma::Error> for Error {
+    fn from(e: dma::Error) -> Self {
+        Error::Dma(e)
+    }
+}
+
+pub struct Spi<'d, T: Instance> {
+    fifo_depth: usize,
+    phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> Spi<'d, T> {
+    pub fn new(
+        _peri: impl Peripheral<P = T> + 'd,
+        sck: impl Peripheral<P = impl SckPin<T>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        miso: impl Peripheral<P = impl MisoPin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, sck, mosi, miso);
+
+        pinmux::claim_all(T::NAME, || {
+            sck.try_claim(sck.alt_function(), T::NAME)?;
+            mosi.try_claim(mosi.alt_function(), T::NAME)?;
+            miso.try_claim(miso.alt_function(), T::NAME)
+        })
+        .map_err(Error::Pinmux)?;
+        miso.set_pull(Pull::Up);
+
+        Self::new_inner(config)
+    }
+
+    /// Transmit only, MISO is not routed
+    pub fn new_txonly(
+        _peri: impl Peripheral<P = T> + 'd,
+        sck: impl Peripheral<P = impl SckPin<T>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T>> + 'd,
+        config: Config,
+    ) -> Result<Self, Error> {
+        into_ref!(_peri, sck, mosi);
+
+        pinmux::claim_all(T::NAME, || {
+            sck.try_claim(sck.alt_function(), T::NAME)?;
+            mosi.try_claim(mosi.alt_function(), T::NAME)
+        })
+        .map_err(Error::Pinmux)?;
+
+        Self::new_inner(config)
+    }
+
+    fn new_inner(config: Config) -> Result<Self, Error> {
+        T::enable_and_reset();
+
+        let regs = T::regs();
+        regs.ssienr().write(0);
+        regs.imr().write(0);
+        regs.ser().write(1);
+
+        let mut spi = Self {
+            fifo_depth: fifo_depth(regs),
+            phantom: PhantomData,
+        };
+        spi.set_config(&config)?;
+        Ok(spi)
+    }
+
+    /// Change clock and mode between transfers
+    pub fn set_config(&mut self, config: &Config) -> Result<(), Error> {
+        let div = clocks().spi.raw().div_ceil(config.frequency.raw().max(1));
+        // SCKDV is even, at least 2
+        let div = (div + (div & 1)).max(2);
+        if div > 0xfffe {
+            return Err(Error::InvalidFrequency);
+        }
+
+        let mut ctrlr0 = ((FRAME_BITS - 1) << CTRLR0_DFS_OFFSET) | ((FRAME_BITS - 1) << CTRLR0_DFS_32_OFFSET);
+        if config.mode.phase == Phase::CaptureOnSecondTransition {
+            ctrlr0 |= CTRLR0_SCPH;
+        }
+        if config.mode.polarity == Polarity::IdleHigh {
+            ctrlr0 |= CTRLR0_SCPOL;
+        }
+
+        let regs = T::regs();
+        regs.ssienr().write(0);
+        regs.ctrlr0().write(ctrlr0);
+        regs.baudr().write(div);
+        regs.ssienr().write(1);
+        Ok(())
+    }
+
+    /// Actual SCK frequency
+    pub fn frequency(&self) -> Hertz {
+        let div = T::regs().baudr().read().max(2);
+        Hertz::from_raw(clocks().spi.raw() / div)
+    }
+
+    /// Write `write` while reading into `read`, clocking the longer of the two.
+    ///
+    /// Missing write bytes are sent as 0, extra read bytes are discarded.
+    pub fn blocking_transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        let len = read.len().max(write.len());
+        self.blocking_run(
+            len,
+            |i| write.get(i).copied().unwrap_or(0),
+            |i, b| {
+                if let Some(slot) = read.get_mut(i) {
+                    *slot = b;
+                }
+            },
+        )
+    }
+
+    pub fn blocking_transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        let words = Cell::from_mut(words).as_slice_of_cells();
+        self.blocking_run(words.len(), |i| words[i].get(), |i, b| words[i].set(b))
+    }
+
+    pub fn blocking_write(&mut self, words: &[u8]) -> Result<(), Error> {
+        self.blocking_run(words.len(), |i| words[i], |_, _| {})
+    }
+
+    /// Read while sending 0
+    pub fn blocking_read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        self.blocking_run(words.len(), |_| 0, |i, b| words[i] = b)
+    }
+
+    /// Clock `len` frames in duplex mode, keeping no more in flight than the RX FIFO holds
+    fn blocking_run(
+        &mut self,
+        len: usize,
+        mut tx: impl FnMut(usize) -> u8,
+        mut rx: impl FnMut(usize, u8),
+    ) -> Result<(), Error> {
+        let regs = T::regs();
+        self.set_transfer_mode(TransferMode::Duplex);
+
+        let (mut sent, mut received) = (0, 0);
+        while received < len {
+            while sent < len && sent - received < self.fifo_depth && regs.sr().is_set(SR_TFNF) {
+                regs.dr().write(tx(sent) as u32);
+                sent += 1;
+            }
+            while received < sent && regs.sr().is_set(SR_RFNE) {
+                rx(received, regs.dr().read() as u8);
+                received += 1;
+            }
+            if regs.risr().is_set(RISR_RXOIR) {
+                regs.icr().read();
+                return Err(Error::Overrun);
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until the last frame left the shift register
+    pub fn blocking_flush(&mut self) -> Result<(), Error> {
+        let regs = T::regs();
+        while !regs.sr().is_set(SR_TFE) || regs.sr().is_set(SR_BUSY) {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Write `words` through a DMA channel, received frames are dropped
+    pub fn dma_write<C: dma::Channel>(&mut self, channel: impl Peripheral<P = C>, words: &[u8]) -> Result<(), Error> {
+        into_ref!(channel);
+        let regs = T::regs();
+        self.set_transfer_mode(TransferMode::TxOnly);
+        regs.dmatdlr().write(self.fifo_depth as u32 - 1);
+        regs.dmacr().write(DMACR_TDMAE);
+
+        let result = (|| {
+            for chunk in words.chunks(dma::MAX_TRANSFER_ITEMS) {
+                // SAFETY: DR is paced by the TX request, the transfer completes before `chunk` is released
+                let mut transfer =
+                    unsafe { Transfer::new_write(&mut *channel, T::TX_REQUEST, chunk, self.dr(), Default::default())? };
+                transfer.blocking_wait()?;
+            }
+            self.blocking_flush()
+        })();
+
+        regs.dmacr().write(0);
+        result
+    }
+
+    /// Read into `words` through a DMA channel, in receive only mode
+    pub fn dma_read<C: dma::Channel>(
+        &mut self,
+        channel: impl Peripheral<P = C>,
+        words: &mut [u8],
+    ) -> Result<(), Error> {
+        into_ref!(channel);
+        let regs = T::regs();
+        regs.dmardlr().write(0);
+        regs.dmacr().write(DMACR_RDMAE);
+
+        let result = (|| {
+            let max = dma::MAX_TRANSFER_ITEMS.min(MAX_RX_ONLY_FRAMES);
+            for chunk in words.chunks_mut(max) {
+                // RX only mode clocks CTRLR1 + 1 frames once DR is written
+                regs.ssienr().write(0);
+                regs.ctrlr0()
+                    .write_field(CTRLR0_TMOD_OFFSET, CTRLR0_TMOD_WIDTH, TransferMode::RxOnly as u32);
+                regs.ctrlr1().write(chunk.len() as u32 - 1);
+                regs.ssienr().write(1);
+
+                // SAFETY: DR is paced by the RX request, the transfer completes before `chunk` is released
+                let mut transfer = unsafe {
+                    Transfer::new_read(
+                        &mut *channel,
+                        T::RX_REQUEST,
+                        self.dr(),
+                        chunk,
+                        TransferOptions::default(),
+                    )?
+                };
+                // any write starts the frames
+                regs.dr().write(0xff);
+                transfer.blocking_wait()?;
+            }
+            Ok(())
+        })();
+
+        regs.dmacr().write(0);
+        result
+    }
+
+    /// Full duplex DMA transfer, `read` and `write` must have the same length
+    pub fn dma_transfer<Tx: dma::Channel, Rx: dma::Channel>(
+        &mut self,
+        tx_channel: impl Peripheral<P = Tx>,
+        rx_channel: impl Peripheral<P = Rx>,
+        read: &mut [u8],
+        write: &[u8],
+    ) -> Result<(), Error> {
+        if read.len() != write.len() {
+            return Err(Error::InvalidLength);
+        }
+        into_ref!(tx_channel, rx_channel);
+        let regs = T::regs();
+        self.set_transfer_mode(TransferMode::Duplex);
+        // TX only refills up to what RX still has room for
+        regs.dmatdlr().write(self.fifo_depth as u32 / 2);
+        regs.dmardlr().write(0);
+
+        let result = (|| {
+            let chunks = read
+                .chunks_mut(dma::MAX_TRANSFER_ITEMS)
+                .zip(write.chunks(dma::MAX_TRANSFER_ITEMS));
+            for (read, write) in chunks {
+                // SAFETY: DR is paced by both requests, the transfers complete before the chunks are released
+                let mut rx = unsafe {
+                    Transfer::new_read(&mut *rx_channel, T::RX_REQUEST, self.dr(), read, Default::default())?
+                };
+                let mut tx = unsafe {
+                    Transfer::new_write(&mut *tx_channel, T::TX_REQUEST, write, self.dr(), Default::default())?
+                };
+                regs.dmacr().write(DMACR_RDMAE | DMACR_TDMAE);
+                tx.blocking_wait()?;
+                rx.blocking_wait()?;
+                regs.dmacr().write(0);
+            }
+            Ok(())
+        })();
+
+        regs.dmacr().write(0);
+        result
+    }
+
+    #[inline]
+    fn dr(&self) -> *mut u8 {
+        T::regs().dr().addr() as *mut u8
+    }
+
+    fn set_transfer_mode(&mut self, mode: TransferMode) {
+        let regs = T::regs();
+        if regs.ctrlr0().read_field(CTRLR0_TMOD_OFFSET, CTRLR0_TMOD_WIDTH) != mode as u32 {
+            regs.ssienr().write(0);
+            regs.ctrlr0()
+                .write_field(CTRLR0_TMOD_OFFSET, CTRLR0_TMOD_WIDTH, mode as u32);
+            regs.ssienr().write(1);
+        }
+    }
+}
+
+impl<T: Instance> Drop for Spi<'_, T> {
+    fn drop(&mut self) {
+        pinmux::release_owner(T::NAME);
+        T::disable();
+    }
+}
+
+/// FIFO depth, the highest TX threshold the controller accepts plus one
+fn fifo_depth(regs: Regs) -> usize {
+    let mut depth = 1;
+    while depth < MAX_FIFO_DEPTH {
+        regs.txftlr().write(depth);
+        if regs.txftlr().read() != depth {
+            break;
+        }
+        depth += 1;
+    }
+    regs.txftlr().write(0);
+    depth as usize
+}
+
+// eh
+
+impl embedded_hal::spi::Error for Error {
+    fn kind(&self) -> embedded_hal::spi::ErrorKind {
+        match self {
+            Error::Overrun => embedded_hal::spi::ErrorKind::Overrun,
+            _ => embedded_hal::spi::ErrorKind::Other,
+        }
+    }
+}
+
+impl<T: Instance> embedded_hal::spi::ErrorType for Spi<'_, T> {
+    type Error = Error;
+}
+
+impl<T: Instance> embedded_hal::spi::SpiBus for Spi<'_, T> {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.blocking_read(words)
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.blocking_write(words)
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.blocking_transfer(read, write)
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.blocking_transfer_in_place(words)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.blocking_flush()
+    }
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Instance {
+        /// Owner of the pads in [`pinmux`]
+        const NAME: &'static str;
+        const TX_REQUEST: dma::Request;
+        const RX_REQUEST: dma::Request;
+
+        fn regs() -> Regs;
+
+        fn enable_and_reset();
+
+        fn disable();
+    }
+}
+
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static + Send {}
+
+macro_rules! impl_spi {
+    ($inst:ident, $base:expr, $rst:ident, $apb:ident, $tx:ident, $rx:ident) => {
+        impl sealed::Instance for crate::peripherals::$inst {
+            const NAME: &'static str = stringify!($inst);
+            const TX_REQUEST: dma::Request = dma::Request::$tx;
+            const RX_REQUEST: dma::Request = dma::Request::$rx;
+
+            fn regs() -> Regs {
+                Regs::at($base)
+            }
+
+            fn enable_and_reset() {
+                rcc::enable_and_reset(&[Gate::Spi, Gate::$apb], Reset::$rst);
+            }
+
+            // the SPI clock is shared by all 4 controllers, only gate the bus clock
+            fn disable() {
+                rcc::disable(&[Gate::$apb], Reset::$rst);
+            }
+        }
+
+        impl Instance for peripherals::$inst {}
+    };
+}
+
+impl_spi!(SPI0, 0x0418_0000, Spi0, ApbSpi0, Spi0Tx, Spi0Rx);
+impl_spi!(SPI1, 0x0419_0000, Spi1, ApbSpi1, Spi1Tx, Spi1Rx);
+impl_spi!(SPI2, 0x041a_0000, Spi2, ApbSpi2, Spi2Tx, Spi2Rx);
+impl_spi!(SPI3, 0x041b_0000, Spi3, ApbSpi3, Spi3Tx, Spi3Rx);
+
+pin_trait!(SckPin, Instance);
+pin_trait!(MosiPin, Instance);
+pin_trait!(MisoPin, Instance);
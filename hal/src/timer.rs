@@ -0,0 +1,376 @@
+//! Timer, DesignWare APB timers
+//!
+//! 8 independent 32-bit down counters clocked from XTAL. Besides the usual
+//! free-running and periodic modes, one-shot is done by stopping the timer
+//! on its first expiry.
+
+use core::marker::PhantomData;
+
+use fugit::MicrosDurationU32 as MicrosDuration;
+
+use crate::clocks::Gate;
+use crate::mmio::{regs, Reg};
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, Peripheral};
+
+/// Timer clock, XTAL
+pub const TIMER_CLOCK: u32 = crate::clocks::XTAL;
+
+const TICKS_PER_US: u32 = TIMER_CLOCK / 1_000_000;
+
+const CTRL_ENABLE: u32 = 1 << 0;
+/// User-defined count mode, reload from LoadCount instead of 0xFFFF_FFFF
+const CTRL_USER_MODE: u32 = 1 << 1;
+const CTRL_INT_MASK: u32 = 1 << 2;
+
+/// TimersRawIntStatus, one bit per timer
+const TIMERS_RAW_INT_STATUS: Reg = Reg::at(0x030a_00a8);
+
+regs! {
+    pub struct Regs {
+        load_count: 0x00,
+        current_value: 0x04,
+        /// [0] enable, [1] mode, [2] interrupt mask
+        control: 0x08,
+        /// read to clear the interrupt
+        eoi: 0x0c,
+        int_status: 0x10,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mode {
+    /// Count down from 0xFFFF_FFFF and wrap around
+    FreeRunning,
+    /// Expire every given number of ticks
+    Periodic(u32),
+    /// Expire once after the given number of ticks, then stop
+    OneShot(u32),
+}
+
+pub struct Timer<'d, T: Instance> {
+    mode: Mode,
+    phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> Timer<'d, T> {
+    pub fn new(_peri: impl Peripheral<P = T> + 'd) -> Self {
+        into_ref!(_peri);
+        T::enable_and_reset();
+
+        let regs = T::regs();
+        regs.control().write(CTRL_INT_MASK);
+        let _ = regs.eoi().read();
+
+        Self {
+            mode: Mode::FreeRunning,
+            phantom: PhantomData,
+        }
+    }
+
+    /// (Re)start the timer in `mode`, interrupt masking is kept.
+    pub fn start(&mut self, mode: Mode) {
+        let regs = T::regs();
+        let mask = regs.control().read() & CTRL_INT_MASK;
+        regs.control().write(mask);
+        let _ = regs.eoi().read();
+        match mode {
+            Mode::FreeRunning => {
+                regs.load_count().write(u32::MAX);
+                regs.control().write(mask | CTRL_ENABLE);
+            }
+            Mode::Periodic(ticks) | Mode::OneShot(ticks) => {
+                regs.load_count().write(ticks.max(1));
+                regs.control().write(mask | CTRL_USER_MODE | CTRL_ENABLE);
+            }
+        }
+        self.mode = mode;
+    }
+
+    #[inline]
+    pub fn start_periodic(&mut self, period: MicrosDuration) {
+        self.start(Mode::Periodic(period.ticks().saturating_mul(TICKS_PER_US)));
+    }
+
+    #[inline]
+    pub fn start_oneshot(&mut self, timeout: MicrosDuration) {
+        self.start(Mode::OneShot(timeout.ticks().saturating_mul(TICKS_PER_US)));
+    }
+
+    pub fn stop(&mut self) {
+        T::regs().control().clear_bits(CTRL_ENABLE);
+    }
+
+    #[inline]
+    pub fn is_running(&self) -> bool {
+        T::regs().control().is_set(CTRL_ENABLE)
+    }
+
+    #[inline]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Current value of the down counter
+    #[inline]
+    pub fn counter(&self) -> u32 {
+        T::regs().current_value().read()
+    }
+
+    /// Ticks since the last (re)load
+    #[inline]
+    pub fn elapsed(&self) -> u32 {
+        T::regs().load_count().read().wrapping_sub(self.counter())
+    }
+
+    /// Whether the timer expired since the last [`Timer::clear_interrupt`], ignoring the mask
+    #[inline]
+    pub fn has_expired(&self) -> bool {
+        TIMERS_RAW_INT_STATUS.is_set(1 << T::index())
+    }
+
+    /// Block until the next expiry. One-shot timers are stopped afterwards.
+    pub fn wait(&mut self) {
+        while !self.has_expired() {
+            core::hint::spin_loop();
+        }
+        self.on_interrupt();
+    }
+
+    pub fn enable_interrupt(&mut self) {
+        T::regs().control().clear_bits(CTRL_INT_MASK);
+    }
+
+    pub fn disable_interrupt(&mut self) {
+        T::regs().control().set_bits(CTRL_INT_MASK);
+    }
+
+    pub fn clear_interrupt(&mut self) {
+        let _ = T::regs().eoi().read();
+    }
+
+    /// Acknowledge an expiry, stopping one-shot timers. Call this from the interrupt handler.
+    pub fn on_interrupt(&mut self) {
+        if let Mode::OneShot(_) = self.mode {
+            self.stop();
+        }
+        self.clear_interrupt();
+    }
+
+    /// Interrupt number of this timer
+    #[inline]
+    pub fn irq(&self) -> u16 {
+        T::IRQ
+    }
+}
+
+impl<'d, T: Instance> Drop for Timer<'d, T> {
+    fn drop(&mut self) {
+        T::disable();
+    }
+}
+
+impl<T: Instance> embedded_hal::delay::DelayNs for Timer<'_, T> {
+    fn delay_ns(&mut self, ns: u32) {
+        // at most ~107M ticks, no overflow
+        let ticks = (ns as u64 * TIMER_CLOCK as u64).div_ceil(1_000_000_000) as u32;
+        let mode = self.mode;
+        let running = self.is_running();
+
+        self.start(Mode::OneShot(ticks));
+        self.wait();
+
+        if running {
+            self.start(mode);
+        } else {
+            self.mode = mode;
+        }
+    }
+}
+
+pub(crate) mod sealed {
+    use super::*;
+
+    pub trait Instance {
+        const IRQ: u16;
+
+        fn index() -> usize;
+
+        fn regs() -> Regs;
+
+        fn enable_and_reset();
+
+        fn disable();
+    }
+}
+
+pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static + Send {}
+
+macro_rules! impl_timer {
+    ($inst:ident, $index:expr, $irq:expr, $gate:ident) => {
+        impl sealed::Instance for crate::peripherals::$inst {
+            const IRQ: u16 = $irq;
+
+            #[inline(always)]
+            fn index() -> usize {
+                $index
+            }
+
+            fn regs() -> Regs {
+                Regs::at(0x030a_0000 + $index * 0x14)
+            }
+
+            fn enable_and_reset() {
+                rcc::enable_and_reset(&[Gate::$gate], Reset::$gate);
+            }
+
+            fn disable() {
+                rcc::disable(&[Gate::$gate], Reset::$gate);
+            }
+        }
+
+        impl Instance for peripherals::$inst {}
+    };
+}
+
+impl_timer!(TIMER0, 0, 95, Timer0);
+impl_timer!(TIMER1, 1, 96, Timer1);
+impl_timer!(TIMER2, 2, 97, Timer2);
+impl_timer!(TIMER3, 3, 98, Timer3);
+impl_timer!(TIMER4, 4, 99, Timer4);
+impl_timer!(TIMER5, 5, 100, Timer5);
+#[cfg(not(feature = "time-driver"))]
+impl_timer!(TIMER6, 6, 101, Timer6);
+#[cfg(not(feature = "time-driver"))]
+impl_timer!(TIMER7, 7, 102, Timer7);
+
+/// `embassy-time` driver on TIMER6(counter) and TIMER7(alarm), at 1MHz tick rate
+///
+/// The HAL doesn't dispatch interrupts by itself, call [`on_interrupt`](time_driver::on_interrupt)
+/// when the PLIC claims [`time_driver::COUNTER_IRQ`] or [`time_driver::ALARM_IRQ`].
+#[cfg(feature = "time-driver")]
+pub mod time_driver {
+    use core::cell::{Cell, RefCell};
+    use core::task::Waker;
+
+    use critical_section::{CriticalSection, Mutex};
+    use embassy_time_driver::Driver;
+    use embassy_time_queue_utils::Queue;
+
+    use super::*;
+    use crate::plic;
+
+    pub const COUNTER_IRQ: u16 = 101;
+    pub const ALARM_IRQ: u16 = 102;
+
+    const COUNTER: Regs = Regs::at(0x030a_0000 + 6 * 0x14);
+    const ALARM: Regs = Regs::at(0x030a_0000 + 7 * 0x14);
+    const COUNTER_BIT: u32 = 1 << 6;
+
+    struct TimeDriver {
+        /// Wraps of the counter, the upper 32 bits of the 64-bit timer tick
+        wraps: Mutex<Cell<u32>>,
+        queue: Mutex<RefCell<Queue>>,
+    }
+
+    embassy_time_driver::time_driver_impl!(static DRIVER: TimeDriver = TimeDriver {
+        wraps: Mutex::new(Cell::new(0)),
+        queue: Mutex::new(RefCell::new(Queue::new())),
+    });
+
+    pub(crate) fn init() {
+        rcc::enable_and_reset(&[Gate::Timer6], Reset::Timer6);
+        rcc::enable_and_reset(&[Gate::Timer7], Reset::Timer7);
+
+        for regs in [COUNTER, ALARM] {
+            regs.control().write(CTRL_INT_MASK);
+            let _ = regs.eoi().read();
+        }
+        COUNTER.load_count().write(u32::MAX);
+        COUNTER.control().write(CTRL_ENABLE);
+
+        for irq in [COUNTER_IRQ, ALARM_IRQ] {
+            plic::set_priority(irq, plic::MAX_PRIORITY);
+            plic::enable(irq);
+        }
+    }
+
+    /// Handle counter wrap and alarm interrupts
+    pub fn on_interrupt() {
+        critical_section::with(|cs| {
+            DRIVER.handle_wrap(cs);
+            if ALARM.int_status().read() != 0 {
+                ALARM.control().write(CTRL_INT_MASK);
+                let _ = ALARM.eoi().read();
+                DRIVER.dispatch(cs);
+            }
+        })
+    }
+
+    impl TimeDriver {
+        fn handle_wrap(&self, cs: CriticalSection) {
+            if TIMERS_RAW_INT_STATUS.is_set(COUNTER_BIT) {
+                let _ = COUNTER.eoi().read();
+                let wraps = self.wraps.borrow(cs);
+                wraps.set(wraps.get() + 1);
+            }
+        }
+
+        fn timer_ticks(&self, cs: CriticalSection) -> u64 {
+            self.handle_wrap(cs);
+            let mut low = !COUNTER.current_value().read();
+            // wrapped between the check and the read
+            if TIMERS_RAW_INT_STATUS.is_set(COUNTER_BIT) {
+                self.handle_wrap(cs);
+                low = !COUNTER.current_value().read();
+            }
+            ((self.wraps.borrow(cs).get() as u64) << 32) | low as u64
+        }
+
+        fn now_cs(&self, cs: CriticalSection) -> u64 {
+            self.timer_ticks(cs) / TICKS_PER_US as u64
+        }
+
+        /// Program the alarm for `at`, false if it's already due
+        fn set_alarm(&self, cs: CriticalSection, at: u64) -> bool {
+            ALARM.control().write(CTRL_INT_MASK);
+            let _ = ALARM.eoi().read();
+
+            if at == u64::MAX {
+                return true;
+            }
+            let now = self.now_cs(cs);
+            if at <= now {
+                return false;
+            }
+            let ticks = (at - now).saturating_mul(TICKS_PER_US as u64).min(u32::MAX as u64) as u32;
+            ALARM.load_count().write(ticks);
+            ALARM.control().write(CTRL_USER_MODE | CTRL_ENABLE);
+            true
+        }
+
+        fn dispatch(&self, cs: CriticalSection) {
+            let mut queue = self.queue.borrow(cs).borrow_mut();
+            let mut next = queue.next_expiration(self.now_cs(cs));
+            while !self.set_alarm(cs, next) {
+                next = queue.next_expiration(self.now_cs(cs));
+            }
+        }
+    }
+
+    impl Driver for TimeDriver {
+        fn now(&self) -> u64 {
+            critical_section::with(|cs| self.now_cs(cs))
+        }
+
+        fn schedule_wake(&self, at: u64, waker: &Waker) {
+            critical_section::with(|cs| {
+                let mut queue = self.queue.borrow(cs).borrow_mut();
+                if queue.schedule_wake(at, waker) {
+                    drop(queue);
+                    self.dispatch(cs);
+                }
+            })
+        }
+    }
+}
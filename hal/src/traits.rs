@@ -3,17 +3,34 @@
 macro_rules! pin_trait {
     ($signal:ident, $instance:path) => {
         pub trait $signal<T: $instance>: crate::gpio::Pin {
-            fn af_num(&self) -> u8;
+            fn alt_function(&self) -> crate::gpio::AltFunction;
+        }
+    };
+    // single instance peripherals
+    ($signal:ident) => {
+        pub trait $signal: crate::gpio::Pin {
+            fn alt_function(&self) -> crate::gpio::AltFunction;
         }
     };
 }
 macro_rules! pin_trait_impl {
-    (crate::$mod:ident::$trait:ident, $instance:ident, $pin:ident, $af_num:expr) => {
+    (crate::$mod:ident::$trait:ident, $instance:ident, $pin:ident, $func:ident) => {
         impl crate::$mod::$trait<crate::peripherals::$instance> for crate::peripherals::$pin {
             #[inline(always)]
-            fn af_num(&self) -> u8 {
-                $af_num
+            fn alt_function(&self) -> crate::gpio::AltFunction {
+                crate::gpio::AltFunction::$func
+            }
+        }
+    };
+    (crate::$mod:ident::$trait:ident, $pin:ident, $func:ident) => {
+        impl crate::$mod::$trait for crate::peripherals::$pin {
+            #[inline(always)]
+            fn alt_function(&self) -> crate::gpio::AltFunction {
+                crate::gpio::AltFunction::$func
             }
         }
     };
 }
+
+// Every pad function with a driver, generated from svd/pinmux.csv
+include!(concat!(env!("OUT_DIR"), "/pin_traits.rs"));
@@ -1,7 +1,10 @@
 use core::marker::PhantomData;
 
+use crate::clocks::{clocks, Gate};
+use crate::dma::{self, Transfer, TransferOptions};
 use crate::gpio::Pull;
-use crate::{into_ref, pac, peripherals, Peripheral};
+use crate::rcc::{self, Reset};
+use crate::{into_ref, pac, peripherals, pinmux, Peripheral};
 
 pub struct Config {
     pub baudrate: u32,
@@ -18,9 +21,25 @@ pub enum Error {
     Overrun,
     Parity,
     Framing,
+    Dma(dma::Error),
+    /// A TX or RX pad is held by another driver
+    Pinmux(pinmux::Conflict),
 }
 
+impl From<dma::Error> for Error {
+    fn from(e: dma::Error) -> Self {
+        Error::Dma(e)
+    }
+}
+
+/// FCR, FIFO enable
+const FCR_FIFOEN: u32 = 1 << 0;
+/// FCR, DMA mode 1
+const FCR_DMAMODE: u32 = 1 << 3;
+
 pub struct Uart<'d, T: Instance> {
+    /// Last value written to FCR, which reads back as IIR
+    fcr: u32,
     phantom: PhantomData<&'d mut T>,
 }
 
@@ -30,13 +49,15 @@ impl<'d, T: Instance> Uart<'d, T> {
         tx: impl Peripheral<P = impl TxPin<T>> + 'd,
         rx: impl Peripheral<P = impl RxPin<T>> + 'd,
         config: Config,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         into_ref!(_peri, tx, rx);
+        pinmux::claim_all(T::NAME, || {
+            tx.try_claim(tx.alt_function(), T::NAME)?;
+            rx.try_claim(rx.alt_function(), T::NAME)
+        })
+        .map_err(Error::Pinmux)?;
         T::enable_and_reset();
 
-        tx.set_alt_function(tx.af_num());
-        rx.set_alt_function(rx.af_num());
-
         // set pull down
         tx.set_pull(Pull::Down);
         rx.set_pull(Pull::Down);
@@ -61,7 +82,7 @@ impl<'d, T: Instance> Uart<'d, T> {
             }
         }
 
-        let uart_clock = 25_000_000;
+        let uart_clock = clocks().uart.raw();
         // let divisor = uart_clock / (16 * config.baudrate);
         // avoid rounding
         let divisor = (uart_clock + config.baudrate * 8) / (config.baudrate * 16);
@@ -75,7 +96,8 @@ impl<'d, T: Instance> Uart<'d, T> {
         }
         uart.lpdll().write(|w| w.lpdll().variant((divisor & 0xff) as u8));
         uart.lpdlh().write(|w| w.lpdlh().variant(((divisor >> 8) & 0xff) as u8));
-        uart.fcr().write(|w| w.fifoen().set_bit());
+        let fcr = FCR_FIFOEN;
+        uart.fcr().write(|w| unsafe { w.bits(fcr) });
 
         loop {
             uart.lcr().modify(|_, w| w.dlab().clear_bit());
@@ -84,7 +106,10 @@ impl<'d, T: Instance> Uart<'d, T> {
             }
         }
 
-        Self { phantom: PhantomData }
+        Ok(Self {
+            fcr,
+            phantom: PhantomData,
+        })
     }
 
     fn check_error(&self) -> Result<(), Error> {
@@ -140,6 +165,49 @@ impl<'d, T: Instance> Uart<'d, T> {
         }
         Ok(())
     }
+
+    /// Write `buf` through a DMA channel, returns once the TX FIFO took the last byte
+    pub fn dma_write<C: dma::Channel>(&mut self, channel: impl Peripheral<P = C>, buf: &[u8]) -> Result<(), Error> {
+        into_ref!(channel);
+        let thr = T::regs().thr().as_ptr() as *mut u8;
+        self.enable_dma();
+
+        for chunk in buf.chunks(dma::MAX_TRANSFER_ITEMS) {
+            // SAFETY: THR is paced by the TX request, the transfer completes before `chunk` is released
+            let mut transfer =
+                unsafe { Transfer::new_write(&mut *channel, T::TX_REQUEST, chunk, thr, TransferOptions::default())? };
+            transfer.blocking_wait()?;
+        }
+        Ok(())
+    }
+
+    /// Fill `buf` through a DMA channel, blocks until `buf.len()` bytes arrived
+    pub fn dma_read<C: dma::Channel>(&mut self, channel: impl Peripheral<P = C>, buf: &mut [u8]) -> Result<(), Error> {
+        into_ref!(channel);
+        let rbr = T::regs().rbr().as_ptr() as *mut u8;
+        self.enable_dma();
+
+        for chunk in buf.chunks_mut(dma::MAX_TRANSFER_ITEMS) {
+            // SAFETY: RBR is paced by the RX request, the transfer completes before `chunk` is released
+            let mut transfer =
+                unsafe { Transfer::new_read(&mut *channel, T::RX_REQUEST, rbr, chunk, TransferOptions::default())? };
+            transfer.blocking_wait()?;
+        }
+        self.check_error()
+    }
+
+    /// DMA mode 1, requests follow the FIFO trigger levels set in FCR
+    fn enable_dma(&mut self) {
+        self.fcr |= FCR_DMAMODE;
+        T::regs().fcr().write(|w| unsafe { w.bits(self.fcr) });
+    }
+}
+
+impl<'d, T: Instance> Drop for Uart<'d, T> {
+    fn drop(&mut self) {
+        pinmux::release_owner(T::NAME);
+        T::disable();
+    }
 }
 
 // eh
@@ -180,65 +248,76 @@ pub(crate) mod sealed {
     pub trait Instance {
         //  type Interrupt: interrupt::Interrupt;
 
+        /// Owner of the pads in [`pinmux`]
+        const NAME: &'static str;
+        const TX_REQUEST: dma::Request;
+        const RX_REQUEST: dma::Request;
+
         fn regs() -> &'static pac::uart0::RegisterBlock;
 
         fn enable_and_reset();
+
+        fn disable();
     }
 }
 
 pub trait Instance: Peripheral<P = Self> + sealed::Instance + 'static + Send {}
 
 macro_rules! impl_uart {
-    ($inst:ident) => {
+    // The debug console, shared with `println!`, never reset or gated
+    ($inst:ident, console, [$($gate:ident),*], $tx:ident, $rx:ident) => {
+        impl sealed::Instance for crate::peripherals::$inst {
+            const NAME: &'static str = stringify!($inst);
+            const TX_REQUEST: dma::Request = dma::Request::$tx;
+            const RX_REQUEST: dma::Request = dma::Request::$rx;
+
+            fn regs() -> &'static crate::pac::uart0::RegisterBlock {
+                unsafe { &*crate::pac::$inst::PTR }
+            }
+
+            fn enable_and_reset() {
+                $(crate::clocks::enable(Gate::$gate);)*
+            }
+
+            fn disable() {}
+        }
+
+        impl Instance for peripherals::$inst {}
+    };
+    ($inst:ident, $rst:ident, [$($gate:ident),*], $tx:ident, $rx:ident) => {
         impl sealed::Instance for crate::peripherals::$inst {
             // type Interrupt = crate::interrupt::$irq;
+            const NAME: &'static str = stringify!($inst);
+            const TX_REQUEST: dma::Request = dma::Request::$tx;
+            const RX_REQUEST: dma::Request = dma::Request::$rx;
 
             fn regs() -> &'static crate::pac::uart0::RegisterBlock {
                 unsafe { &*crate::pac::$inst::PTR }
             }
 
-            fn enable_and_reset() {}
+            fn enable_and_reset() {
+                rcc::enable_and_reset(&[$(Gate::$gate),*], Reset::$rst);
+            }
+
+            fn disable() {
+                rcc::disable(&[$(Gate::$gate),*], Reset::$rst);
+            }
         }
 
         impl Instance for peripherals::$inst {}
     };
 }
 
-impl_uart!(UART0);
-impl_uart!(UART1);
-impl_uart!(UART2);
-impl_uart!(UART3);
+impl_uart!(UART0, console, [Uart0, ApbUart0], Uart0Tx, Uart0Rx);
+impl_uart!(UART1, Uart1, [Uart1, ApbUart1], Uart1Tx, Uart1Rx);
+impl_uart!(UART2, Uart2, [Uart2, ApbUart2], Uart2Tx, Uart2Rx);
+impl_uart!(UART3, Uart3, [Uart3, ApbUart3], Uart3Tx, Uart3Rx);
 
 pin_trait!(RxPin, Instance);
 pin_trait!(TxPin, Instance);
 pin_trait!(RtsPin, Instance);
 pin_trait!(CtsPin, Instance);
 
-pin_trait_impl!(crate::uart::TxPin, UART0, PIN_12, 0);
-pin_trait_impl!(crate::uart::RxPin, UART0, PIN_13, 0);
-
-pin_trait_impl!(crate::uart::TxPin, UART1, PIN_12, 4);
-pin_trait_impl!(crate::uart::RxPin, UART1, PIN_13, 4);
-pin_trait_impl!(crate::uart::RtsPin, UART1, PIN_2, 4);
-pin_trait_impl!(crate::uart::CtsPin, UART1, PIN_3, 4);
-
-pin_trait_impl!(crate::uart::TxPin, UART1, PIN_2, 6);
-pin_trait_impl!(crate::uart::RxPin, UART1, PIN_3, 6);
-
-pin_trait_impl!(crate::uart::TxPin, UART1, PIN_0, 1);
-pin_trait_impl!(crate::uart::TxPin, UART2, PIN_0, 2);
-
-pin_trait_impl!(crate::uart::RxPin, UART1, PIN_1, 1);
-pin_trait_impl!(crate::uart::RxPin, UART2, PIN_1, 2);
-
-pin_trait_impl!(crate::uart::TxPin, UART2, PIN_4, 2);
-pin_trait_impl!(crate::uart::TxPin, UART3, PIN_4, 5);
-
-pin_trait_impl!(crate::uart::RxPin, UART2, PIN_5, 2);
-pin_trait_impl!(crate::uart::RxPin, UART3, PIN_5, 5);
-
-pin_trait_impl!(crate::uart::RtsPin, UART3, PIN_8, 5);
-
 /// Uart0 for debug, use GP12 and GP13
 pub struct Uart0;
 
@@ -261,7 +340,8 @@ impl Uart0 {
         ioblk.pin(gp13_pin).iocfg().modify(|_, w| w.pd().set_bit());
 
         // uart_clock / (16 * baudrate);
-        let lpdl: u32 = 14;
+        let baudrate = 115200;
+        let lpdl = (clocks().uart.raw() + baudrate * 8) / (baudrate * 16);
 
         let uart = unsafe { &*pac::UART0::PTR };
         uart.lcr().modify(|_, w| w.dlab().set_bit());
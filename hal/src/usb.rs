@@ -0,0 +1,187 @@
+//! USB 2.0, Synopsys DWC2 OTG controller
+//!
+//! In device mode [`UsbBus`] implements `usb_device::bus::UsbBus`, so any
+//! `usb-device` class (CDC-ACM, DFU, ...) runs on it. The controller is used in
+//! slave mode: packets move through the FIFOs by the CPU from
+//! [`usb_device::bus::UsbBus::poll`], no DMA and no cache maintenance involved.
+//! Call `poll` from a loop or from the [`IRQ`] handler.
+//!
+//! VBUS is sensed on the USB_VBUS_DET pad (`PIN_27` on the Duo). Boards that
+//! don't route it use [`UsbBus::new_without_vbus`], which forces the B-session
+//! valid signal so the device always attaches.
+//!
+//! ```ignore
+//! let bus = UsbBusAllocator::new(UsbBus::new(p.USB, p.PIN_27, Config::default())?);
+//! let mut serial = usbd_serial::SerialPort::new(&bus);
+//! let mut device = UsbDeviceBuilder::new(&bus, UsbVidPid(0x16c0, 0x27dd)).build();
+//! loop {
+//!     if device.poll(&mut [&mut serial]) { ... }
+//! }
+//! ```
+//!
+//! Host mode lives in [`host`], with mass storage and HID keyboard class drivers.
+//!
+//! Device mode needs the `usb-device` feature.
+
+use crate::clocks::Gate;
+use crate::delay::wait;
+use crate::mmio::regs;
+
+#[cfg(feature = "usb-device")]
+mod device;
+pub mod host;
+
+#[cfg(feature = "usb-device")]
+pub use device::{Config, Speed, UsbBus, MAX_ENDPOINTS, MAX_PACKET_SIZE};
+
+/// USB interrupt number
+pub const IRQ: u16 = 30;
+
+/// GAHBCFG, global interrupt enable
+pub(crate) const GAHBCFG_GINTMSK: u32 = 1 << 0;
+
+// GUSBCFG
+pub(crate) const GUSBCFG_PHYIF16: u32 = 1 << 3;
+pub(crate) const GUSBCFG_TRDT_OFFSET: u32 = 10;
+pub(crate) const GUSBCFG_TRDT_WIDTH: u32 = 4;
+pub(crate) const GUSBCFG_FHMOD: u32 = 1 << 29;
+pub(crate) const GUSBCFG_FDMOD: u32 = 1 << 30;
+
+// GRSTCTL
+pub(crate) const GRSTCTL_CSRST: u32 = 1 << 0;
+pub(crate) const GRSTCTL_RXFFLSH: u32 = 1 << 4;
+pub(crate) const GRSTCTL_TXFFLSH: u32 = 1 << 5;
+pub(crate) const GRSTCTL_TXFNUM_OFFSET: u32 = 6;
+/// TXFNUM selecting every TX FIFO
+pub(crate) const GRSTCTL_TXFNUM_ALL: u32 = 0x10;
+pub(crate) const GRSTCTL_AHBIDL: u32 = 1 << 31;
+
+// GINTSTS, GINTMSK
+pub(crate) const GINT_RXFLVL: u32 = 1 << 4;
+
+// GRXSTSP
+pub(crate) const GRXSTS_BCNT_OFFSET: u32 = 4;
+pub(crate) const GRXSTS_BCNT_MASK: u32 = 0x7ff;
+pub(crate) const GRXSTS_PKTSTS_OFFSET: u32 = 17;
+pub(crate) const GRXSTS_PKTSTS_MASK: u32 = 0xf;
+
+regs! {
+    pub(crate) struct Regs {
+        /// [6] B-valid override enable, [7] override value, [19] B-session valid
+        gotgctl: 0x000,
+        gotgint: 0x004,
+        gahbcfg: 0x008,
+        gusbcfg: 0x00c,
+        grstctl: 0x010,
+        gintsts: 0x014,
+        gintmsk: 0x018,
+        grxstsr: 0x01c,
+        grxstsp: 0x020,
+        grxfsiz: 0x024,
+        /// Non-periodic TX FIFO in host mode, EP0 TX FIFO in device mode
+        gnptxfsiz: 0x028,
+        gnptxsts: 0x02c,
+        gsnpsid: 0x040,
+        ghwcfg2: 0x048,
+        ghwcfg3: 0x04c,
+        /// Periodic TX FIFO in host mode
+        hptxfsiz: 0x100,
+        /// TX FIFO of IN endpoint n + 1, [15:0] start, [31:16] depth in words
+        dieptxf[15]: 0x104 / 0x04,
+
+        hcfg: 0x400,
+        hfir: 0x404,
+        hfnum: 0x408,
+        hptxsts: 0x410,
+        haint: 0x414,
+        haintmsk: 0x418,
+        /// Root port, [0] connected, [2] enabled, [8] reset, [12] power, [18:17] speed
+        hprt: 0x440,
+        hcchar[16]: 0x500 / 0x20,
+        hcsplt[16]: 0x504 / 0x20,
+        hcint[16]: 0x508 / 0x20,
+        hcintmsk[16]: 0x50c / 0x20,
+        hctsiz[16]: 0x510 / 0x20,
+
+        dcfg: 0x800,
+        dctl: 0x804,
+        dsts: 0x808,
+        diepmsk: 0x810,
+        doepmsk: 0x814,
+        daint: 0x818,
+        daintmsk: 0x81c,
+        diepctl[16]: 0x900 / 0x20,
+        diepint[16]: 0x908 / 0x20,
+        dieptsiz[16]: 0x910 / 0x20,
+        /// Free words in the TX FIFO of IN endpoint n
+        dtxfsts[16]: 0x918 / 0x20,
+        doepctl[16]: 0xb00 / 0x20,
+        doepint[16]: 0xb08 / 0x20,
+        doeptsiz[16]: 0xb10 / 0x20,
+        pcgcctl: 0xe00,
+        /// Push to the TX FIFO of endpoint or channel n, pop the RX FIFO at any of them
+        fifo[16]: 0x1000 / 0x1000,
+    }
+}
+
+#[inline]
+pub(crate) fn regs() -> Regs {
+    Regs::at(0x0434_0000)
+}
+
+pub(crate) const GATES: [Gate; 5] = [Gate::Axi4Usb, Gate::ApbUsb, Gate::Usb125m, Gate::Usb33k, Gate::Usb12m];
+
+/// AHB idle, core soft reset and FIFO flushes take a few PHY clocks
+const RESET_TIMEOUT_US: u32 = 10_000;
+
+/// The core didn't come out of a soft reset or FIFO flush
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) struct Timeout;
+
+/// Wait for the AHB side to idle, then soft reset the core
+pub(crate) fn core_reset() -> Result<(), Timeout> {
+    let grstctl = regs().grstctl();
+    wait(|| grstctl.is_set(GRSTCTL_AHBIDL), RESET_TIMEOUT_US, Timeout)?;
+    grstctl.set_bits(GRSTCTL_CSRST);
+    wait(|| !grstctl.is_set(GRSTCTL_CSRST), RESET_TIMEOUT_US, Timeout)
+}
+
+/// Turnaround time for the UTMI+ data width the core was built with
+pub(crate) fn set_turnaround() {
+    let usbcfg = regs().gusbcfg();
+    let trdt = if usbcfg.is_set(GUSBCFG_PHYIF16) { 5 } else { 9 };
+    usbcfg.write_field(GUSBCFG_TRDT_OFFSET, GUSBCFG_TRDT_WIDTH, trdt);
+}
+
+pub(crate) fn flush_fifos() -> Result<(), Timeout> {
+    let grstctl = regs().grstctl();
+    grstctl.write((GRSTCTL_TXFNUM_ALL << GRSTCTL_TXFNUM_OFFSET) | GRSTCTL_TXFFLSH);
+    wait(|| !grstctl.is_set(GRSTCTL_TXFFLSH), RESET_TIMEOUT_US, Timeout)?;
+    grstctl.write(GRSTCTL_RXFFLSH);
+    wait(|| !grstctl.is_set(GRSTCTL_RXFFLSH), RESET_TIMEOUT_US, Timeout)
+}
+
+/// Push `data` into the TX FIFO of endpoint or channel `n`, padding the last word
+pub(crate) fn write_fifo(n: usize, data: &[u8]) {
+    let fifo = regs().fifo(n);
+    for chunk in data.chunks(4) {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        fifo.write(u32::from_le_bytes(word));
+    }
+}
+
+/// Pop `len` bytes from the RX FIFO into `buf`, dropping what doesn't fit
+pub(crate) fn read_fifo(buf: &mut [u8], len: usize) {
+    let fifo = regs().fifo(0);
+    for i in (0..len).step_by(4) {
+        let word = fifo.read().to_le_bytes();
+        let n = (len - i).min(4);
+        if let Some(dst) = buf.get_mut(i..i + n) {
+            dst.copy_from_slice(&word[..n]);
+        }
+    }
+}
+
+// USB_VBUS_DET, needed by the device mode only
+pin_trait!(VbusDetPin);
@@ -0,0 +1,524 @@
+//! Device mode, see [`super`]
+
+use core::cell::RefCell;
+use core::marker::PhantomData;
+
+use critical_section::Mutex;
+use embedded_hal::delay::DelayNs;
+use usb_device::bus::PollResult;
+use usb_device::endpoint::{EndpointAddress, EndpointType};
+use usb_device::{UsbDirection, UsbError};
+
+use super::{
+    core_reset, flush_fifos, read_fifo, regs, set_turnaround, write_fifo, Timeout, VbusDetPin, GAHBCFG_GINTMSK, GATES,
+    GINT_RXFLVL, GRXSTS_BCNT_MASK, GRXSTS_BCNT_OFFSET, GRXSTS_PKTSTS_MASK, GRXSTS_PKTSTS_OFFSET, GUSBCFG_FDMOD,
+    GUSBCFG_FHMOD,
+};
+use crate::delay::Delay;
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, pinmux, Peripheral};
+
+/// Endpoints per direction, including EP0
+pub const MAX_ENDPOINTS: usize = 8;
+/// Largest packet an endpoint takes, a high speed bulk packet
+pub const MAX_PACKET_SIZE: usize = 512;
+const MAX_PACKET_WORDS: usize = MAX_PACKET_SIZE / 4;
+/// Packet sizes EP0 takes, the order of the DOEPCTL0/DIEPCTL0 MPSIZ codes
+const EP0_PACKET_SIZES: [u16; 4] = [64, 32, 16, 8];
+
+/// RX FIFO: 2 max packets plus status words and SETUP packets, databook sizing
+const RX_FIFO_WORDS: u32 = 2 * (MAX_PACKET_WORDS as u32 + 1) + 11;
+/// Smallest TX FIFO, 16 words
+const MIN_TX_FIFO_WORDS: u32 = 16;
+
+// GOTGCTL
+/// B-valid override enable and value
+const GOTGCTL_BVALOEN: u32 = 1 << 6;
+const GOTGCTL_BVALOVAL: u32 = 1 << 7;
+const GOTGCTL_BSESVLD: u32 = 1 << 19;
+
+// GINTSTS, GINTMSK
+const GINT_USBSUSP: u32 = 1 << 11;
+const GINT_USBRST: u32 = 1 << 12;
+const GINT_ENUMDNE: u32 = 1 << 13;
+const GINT_IEPINT: u32 = 1 << 18;
+const GINT_OEPINT: u32 = 1 << 19;
+const GINT_WKUPINT: u32 = 1 << 31;
+
+// GRXSTSP
+const GRXSTS_CHEPNUM_MASK: u32 = 0xf;
+const PKTSTS_OUT_DATA: u32 = 2;
+const PKTSTS_SETUP_DATA: u32 = 6;
+
+/// GHWCFG2, device endpoints besides EP0
+const GHWCFG2_NUMDEVEPS_OFFSET: u32 = 10;
+/// GHWCFG3, FIFO RAM in words
+const GHWCFG3_DFIFODEPTH_OFFSET: u32 = 16;
+
+// DCFG
+const DCFG_DSPD_HIGH: u32 = 0b00;
+const DCFG_DSPD_FULL: u32 = 0b01;
+const DCFG_DSPD_WIDTH: u32 = 2;
+const DCFG_DAD_OFFSET: u32 = 4;
+const DCFG_DAD_WIDTH: u32 = 7;
+
+/// DCTL, soft disconnect
+const DCTL_SDIS: u32 = 1 << 1;
+
+// DIEPCTL, DOEPCTL
+const DEPCTL_USBAEP: u32 = 1 << 15;
+const DEPCTL_EPTYP_OFFSET: u32 = 18;
+const DEPCTL_STALL: u32 = 1 << 21;
+const DEPCTL_TXFNUM_OFFSET: u32 = 22;
+const DEPCTL_CNAK: u32 = 1 << 26;
+const DEPCTL_SNAK: u32 = 1 << 27;
+const DEPCTL_SD0PID: u32 = 1 << 28;
+const DEPCTL_EPDIS: u32 = 1 << 30;
+const DEPCTL_EPENA: u32 = 1 << 31;
+
+/// DIEPINT, DOEPINT, transfer completed
+const DEPINT_XFRC: u32 = 1 << 0;
+/// DOEPINT, SETUP phase done
+const DOEPINT_STUP: u32 = 1 << 3;
+
+// DIEPTSIZ, DOEPTSIZ
+const DEPTSIZ_PKTCNT_OFFSET: u32 = 19;
+const DOEPTSIZ_STUPCNT_OFFSET: u32 = 29;
+
+/// PCGCCTL, stop PHY clock
+const PCGCCTL_STPPCLK: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Speed {
+    /// 480Mbit/s, bulk endpoints must use 512 byte packets
+    High,
+    /// 12Mbit/s over the high speed PHY, what most `usb-device` classes expect
+    #[default]
+    Full,
+}
+
+#[derive(Default)]
+pub struct Config {
+    /// Fastest speed offered during the reset handshake
+    pub speed: Speed,
+}
+
+#[derive(Clone, Copy)]
+struct EndpointConfig {
+    ep_type: EndpointType,
+    max_packet_size: u16,
+}
+
+/// OUT packets popped from the RX FIFO, waiting for `read`
+struct OutState {
+    buf: [[u8; MAX_PACKET_SIZE]; MAX_ENDPOINTS],
+    len: [Option<usize>; MAX_ENDPOINTS],
+    setup: bool,
+}
+
+pub struct UsbBus<'d> {
+    config: Config,
+    endpoints: usize,
+    in_eps: [Option<EndpointConfig>; MAX_ENDPOINTS],
+    out_eps: [Option<EndpointConfig>; MAX_ENDPOINTS],
+    out: Mutex<RefCell<OutState>>,
+    phantom: PhantomData<&'d mut peripherals::USB>,
+}
+
+impl<'d> UsbBus<'d> {
+    pub fn new(
+        _peri: impl Peripheral<P = peripherals::USB> + 'd,
+        vbus: impl Peripheral<P = impl VbusDetPin> + 'd,
+        config: Config,
+    ) -> Result<Self, pinmux::Conflict> {
+        into_ref!(_peri, vbus);
+        // USB_VBUS_DET
+        vbus.try_claim(vbus.alt_function(), "USB")?;
+
+        Ok(Self::new_inner(config, false))
+    }
+
+    /// Attach without VBUS sensing, the session is always valid
+    pub fn new_without_vbus(_peri: impl Peripheral<P = peripherals::USB> + 'd, config: Config) -> Self {
+        into_ref!(_peri);
+        Self::new_inner(config, true)
+    }
+
+    fn new_inner(config: Config, force_vbus: bool) -> Self {
+        rcc::enable_and_reset(&GATES, Reset::Usb);
+
+        let regs = regs();
+        if force_vbus {
+            regs.gotgctl().set_bits(GOTGCTL_BVALOEN | GOTGCTL_BVALOVAL);
+        }
+        let endpoints = (regs.ghwcfg2().read_field(GHWCFG2_NUMDEVEPS_OFFSET, 4) as usize + 1).min(MAX_ENDPOINTS);
+
+        Self {
+            config,
+            endpoints,
+            in_eps: [None; MAX_ENDPOINTS],
+            out_eps: [None; MAX_ENDPOINTS],
+            out: Mutex::new(RefCell::new(OutState {
+                buf: [[0; MAX_PACKET_SIZE]; MAX_ENDPOINTS],
+                len: [None; MAX_ENDPOINTS],
+                setup: false,
+            })),
+            phantom: PhantomData,
+        }
+    }
+
+    /// VBUS is present, or forced by [`Self::new_without_vbus`]
+    pub fn is_vbus_present(&self) -> bool {
+        regs().gotgctl().is_set(GOTGCTL_BSESVLD)
+    }
+
+    /// FIFO words the endpoints need, RX first then one TX FIFO per IN endpoint
+    fn fifo_words(in_eps: &[Option<EndpointConfig>]) -> u32 {
+        RX_FIFO_WORDS + in_eps.iter().flatten().map(tx_fifo_words).sum::<u32>()
+    }
+
+    /// Lay out the FIFOs, EP0 takes the non-periodic TX FIFO
+    fn configure_fifos(&self) -> Result<(), Timeout> {
+        let regs = regs();
+        regs.grxfsiz().write(RX_FIFO_WORDS);
+
+        let mut start = RX_FIFO_WORDS;
+        for (n, ep) in self.in_eps.iter().enumerate().take(self.endpoints) {
+            let depth = ep.as_ref().map_or(MIN_TX_FIFO_WORDS, tx_fifo_words);
+            let reg = if n == 0 { regs.gnptxfsiz() } else { regs.dieptxf(n - 1) };
+            reg.write((depth << 16) | start);
+            start += depth;
+        }
+        flush_fifos()
+    }
+
+    /// Accept the next packet, or SETUP, on OUT endpoint `n`
+    fn arm_out(&self, n: usize) {
+        let regs = regs();
+        let Some(ep) = self.out_eps[n] else {
+            return;
+        };
+        let mut tsiz = (1 << DEPTSIZ_PKTCNT_OFFSET) | ep.max_packet_size as u32;
+        if n == 0 {
+            tsiz |= 3 << DOEPTSIZ_STUPCNT_OFFSET;
+        }
+        regs.doeptsiz(n).write(tsiz);
+        regs.doepctl(n).set_bits(DEPCTL_CNAK | DEPCTL_EPENA);
+    }
+
+    /// Pop one entry of the RX FIFO
+    fn pop_rx(&self, out: &mut OutState) {
+        let status = regs().grxstsp().read();
+        let n = (status & GRXSTS_CHEPNUM_MASK) as usize;
+        let len = ((status >> GRXSTS_BCNT_OFFSET) & GRXSTS_BCNT_MASK) as usize;
+        match (status >> GRXSTS_PKTSTS_OFFSET) & GRXSTS_PKTSTS_MASK {
+            PKTSTS_OUT_DATA if n < MAX_ENDPOINTS => {
+                read_fifo(&mut out.buf[n], len);
+                out.len[n] = Some(len.min(MAX_PACKET_SIZE));
+            }
+            PKTSTS_SETUP_DATA => {
+                read_fifo(&mut out.buf[0], len);
+                out.len[0] = Some(len.min(8));
+                out.setup = true;
+            }
+            // transfer and SETUP completion, global OUT NAK
+            _ => {}
+        }
+    }
+}
+
+impl Drop for UsbBus<'_> {
+    fn drop(&mut self) {
+        pinmux::release_owner("USB");
+        regs().dctl().set_bits(DCTL_SDIS);
+        rcc::disable(&GATES, Reset::Usb);
+    }
+}
+
+#[inline]
+fn tx_fifo_words(ep: &EndpointConfig) -> u32 {
+    (ep.max_packet_size as u32).div_ceil(4).max(MIN_TX_FIFO_WORDS)
+}
+
+/// DxEPCTL.MPSIZ, EP0 has a 2-bit code: 0 is 64 bytes, 1 is 32, 2 is 16, 3 is 8
+#[inline]
+fn mpsiz(n: usize, max_packet_size: u16) -> u32 {
+    if n == 0 {
+        EP0_PACKET_SIZES
+            .iter()
+            .position(|&size| size == max_packet_size)
+            .unwrap_or(0) as u32
+    } else {
+        max_packet_size as u32
+    }
+}
+
+impl usb_device::bus::UsbBus for UsbBus<'_> {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        _interval: u8,
+    ) -> usb_device::Result<EndpointAddress> {
+        if max_packet_size as usize > MAX_PACKET_SIZE {
+            return Err(UsbError::EndpointMemoryOverflow);
+        }
+        let control = ep_type == EndpointType::Control;
+        if control && !EP0_PACKET_SIZES.contains(&max_packet_size) {
+            return Err(UsbError::Unsupported);
+        }
+
+        let eps = match ep_dir {
+            UsbDirection::In => &mut self.in_eps,
+            UsbDirection::Out => &mut self.out_eps,
+        };
+        let n = match ep_addr {
+            Some(addr) => addr.index(),
+            // EP0 is the only control endpoint
+            None if control => 0,
+            None => (1..self.endpoints)
+                .find(|&n| eps[n].is_none())
+                .ok_or(UsbError::EndpointOverflow)?,
+        };
+        if n >= self.endpoints || (n == 0) != control {
+            return Err(UsbError::InvalidEndpoint);
+        }
+        if eps[n].is_some() {
+            return Err(UsbError::InvalidEndpoint);
+        }
+
+        eps[n] = Some(EndpointConfig {
+            ep_type,
+            max_packet_size,
+        });
+        if ep_dir == UsbDirection::In {
+            let depth = regs().ghwcfg3().read() >> GHWCFG3_DFIFODEPTH_OFFSET;
+            if Self::fifo_words(&self.in_eps) > depth {
+                self.in_eps[n] = None;
+                return Err(UsbError::EndpointMemoryOverflow);
+            }
+        }
+        Ok(EndpointAddress::from_parts(n, ep_dir))
+    }
+
+    fn enable(&mut self) {
+        let regs = regs();
+        // `UsbBus::enable` can't fail, a stuck core stays soft disconnected
+        // and the host never sees the device
+        if core_reset().is_err() {
+            return;
+        }
+        regs.gusbcfg().modify(|r| (r & !GUSBCFG_FHMOD) | GUSBCFG_FDMOD);
+        set_turnaround();
+        // forced mode takes effect after 25ms
+        Delay::new().delay_ms(25);
+
+        let speed = match self.config.speed {
+            Speed::High => DCFG_DSPD_HIGH,
+            Speed::Full => DCFG_DSPD_FULL,
+        };
+        regs.dcfg().write_field(0, DCFG_DSPD_WIDTH, speed);
+        regs.pcgcctl().write(0);
+
+        regs.gintsts().write(u32::MAX);
+        regs.gintmsk()
+            .write(GINT_RXFLVL | GINT_USBSUSP | GINT_USBRST | GINT_ENUMDNE | GINT_IEPINT | GINT_OEPINT | GINT_WKUPINT);
+        regs.gahbcfg().write(GAHBCFG_GINTMSK);
+        regs.dctl().clear_bits(DCTL_SDIS);
+    }
+
+    fn reset(&self) {
+        let regs = regs();
+        regs.dcfg().write_field(DCFG_DAD_OFFSET, DCFG_DAD_WIDTH, 0);
+        // no endpoint is armed with stale FIFOs, enumeration fails instead
+        if self.configure_fifos().is_err() {
+            return;
+        }
+
+        critical_section::with(|cs| {
+            let mut out = self.out.borrow_ref_mut(cs);
+            out.len = [None; MAX_ENDPOINTS];
+            out.setup = false;
+        });
+
+        let mut mask = 0;
+        for n in 0..self.endpoints {
+            if let Some(ep) = self.in_eps[n] {
+                let ep_type = (ep.ep_type.to_bm_attributes() & 0b11) as u32;
+                let mut ctl = DEPCTL_USBAEP
+                    | DEPCTL_SNAK
+                    | (ep_type << DEPCTL_EPTYP_OFFSET)
+                    | ((n as u32) << DEPCTL_TXFNUM_OFFSET)
+                    | mpsiz(n, ep.max_packet_size);
+                if n != 0 {
+                    ctl |= DEPCTL_SD0PID;
+                }
+                regs.diepctl(n).write(ctl);
+                regs.diepint(n).write(u32::MAX);
+                mask |= 1 << n;
+            }
+            if let Some(ep) = self.out_eps[n] {
+                let ep_type = (ep.ep_type.to_bm_attributes() & 0b11) as u32;
+                let mut ctl = DEPCTL_USBAEP | (ep_type << DEPCTL_EPTYP_OFFSET) | mpsiz(n, ep.max_packet_size);
+                if n != 0 {
+                    ctl |= DEPCTL_SD0PID;
+                }
+                regs.doepctl(n).write(ctl);
+                regs.doepint(n).write(u32::MAX);
+                self.arm_out(n);
+                mask |= 1 << (n + 16);
+            }
+        }
+        regs.diepmsk().write(DEPINT_XFRC);
+        regs.doepmsk().write(DEPINT_XFRC | DOEPINT_STUP);
+        regs.daintmsk().write(mask);
+    }
+
+    fn set_device_address(&self, addr: u8) {
+        regs().dcfg().write_field(DCFG_DAD_OFFSET, DCFG_DAD_WIDTH, addr as u32);
+    }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> usb_device::Result<usize> {
+        let n = ep_addr.index();
+        let ep = self.in_eps.get(n).copied().flatten().ok_or(UsbError::InvalidEndpoint)?;
+        if buf.len() > ep.max_packet_size as usize {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let regs = regs();
+        if regs.diepctl(n).is_set(DEPCTL_EPENA) {
+            return Err(UsbError::WouldBlock);
+        }
+        let words = buf.len().div_ceil(4) as u32;
+        if regs.dtxfsts(n).read() & 0xffff < words {
+            return Err(UsbError::WouldBlock);
+        }
+
+        regs.dieptsiz(n).write((1 << DEPTSIZ_PKTCNT_OFFSET) | buf.len() as u32);
+        regs.diepctl(n).set_bits(DEPCTL_CNAK | DEPCTL_EPENA);
+        write_fifo(n, buf);
+        Ok(buf.len())
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> usb_device::Result<usize> {
+        let n = ep_addr.index();
+        if self.out_eps.get(n).copied().flatten().is_none() {
+            return Err(UsbError::InvalidEndpoint);
+        }
+
+        let len = critical_section::with(|cs| {
+            let mut out = self.out.borrow_ref_mut(cs);
+            let len = out.len[n].ok_or(UsbError::WouldBlock)?;
+            if len > buf.len() {
+                return Err(UsbError::BufferOverflow);
+            }
+            buf[..len].copy_from_slice(&out.buf[n][..len]);
+            out.len[n] = None;
+            if n == 0 {
+                out.setup = false;
+            }
+            Ok(len)
+        })?;
+        self.arm_out(n);
+        Ok(len)
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        let n = ep_addr.index();
+        if n >= self.endpoints {
+            return;
+        }
+        let regs = regs();
+        let ctl = match ep_addr.direction() {
+            UsbDirection::In => regs.diepctl(n),
+            UsbDirection::Out => regs.doepctl(n),
+        };
+        if stalled {
+            ctl.set_bits(DEPCTL_STALL);
+        } else {
+            // data toggle restarts at DATA0 after a halt is cleared
+            let pid = if n == 0 { 0 } else { DEPCTL_SD0PID };
+            ctl.modify(|r| (r & !(DEPCTL_STALL | DEPCTL_EPENA | DEPCTL_EPDIS)) | pid);
+        }
+    }
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        let n = ep_addr.index();
+        if n >= self.endpoints {
+            return false;
+        }
+        let regs = regs();
+        match ep_addr.direction() {
+            UsbDirection::In => regs.diepctl(n).is_set(DEPCTL_STALL),
+            UsbDirection::Out => regs.doepctl(n).is_set(DEPCTL_STALL),
+        }
+    }
+
+    fn suspend(&self) {
+        regs().pcgcctl().set_bits(PCGCCTL_STPPCLK);
+    }
+
+    fn resume(&self) {
+        regs().pcgcctl().clear_bits(PCGCCTL_STPPCLK);
+    }
+
+    fn poll(&self) -> PollResult {
+        let regs = regs();
+        let status = regs.gintsts().read();
+
+        if status & GINT_WKUPINT != 0 {
+            regs.gintsts().write(GINT_WKUPINT);
+            return PollResult::Resume;
+        }
+        if status & GINT_USBRST != 0 {
+            regs.gintsts().write(GINT_USBRST);
+            return PollResult::Reset;
+        }
+        if status & GINT_ENUMDNE != 0 {
+            regs.gintsts().write(GINT_ENUMDNE);
+        }
+        if status & GINT_USBSUSP != 0 {
+            regs.gintsts().write(GINT_USBSUSP);
+            return PollResult::Suspend;
+        }
+
+        let (mut ep_out, mut ep_in_complete, mut ep_setup) = (0u16, 0u16, 0u16);
+        critical_section::with(|cs| {
+            let mut out = self.out.borrow_ref_mut(cs);
+            while regs.gintsts().is_set(GINT_RXFLVL) {
+                self.pop_rx(&mut out);
+            }
+            for n in 0..self.endpoints {
+                if out.len[n].is_some() {
+                    ep_out |= 1 << n;
+                }
+            }
+            if out.setup {
+                ep_setup |= 1;
+            }
+        });
+
+        for n in 0..self.endpoints {
+            let int = regs.diepint(n);
+            if int.is_set(DEPINT_XFRC) {
+                int.write(DEPINT_XFRC);
+                ep_in_complete |= 1 << n;
+            }
+            regs.doepint(n).write(DEPINT_XFRC | DOEPINT_STUP);
+        }
+
+        if ep_out | ep_in_complete | ep_setup == 0 {
+            PollResult::None
+        } else {
+            PollResult::Data {
+                ep_out,
+                ep_in_complete,
+                ep_setup,
+            }
+        }
+    }
+
+    const QUIRK_SET_ADDRESS_BEFORE_STATUS: bool = true;
+}
@@ -0,0 +1,854 @@
+//! USB host mode on the DWC2 controller
+//!
+//! One device on the root port, hubs are not supported. [`Host::enumerate`]
+//! resets the port, gives the device address 1 and selects its first
+//! configuration, then class drivers open [`Pipe`]s to the endpoints they need:
+//! [`msc::MassStorage`] for flash sticks, [`hid::Keyboard`] for boot keyboards.
+//!
+//! Transfers are blocking and go through host channel 0 one packet at a time,
+//! in slave mode like the device side. NAKed control and bulk packets are
+//! retried until a timeout, a NAKed interrupt packet means there's no data.
+//!
+//! The controller drives its VBUS enable output when the port is powered, on
+//! the Duo VBUS for the device has to be supplied externally.
+//!
+//! ```ignore
+//! let mut host = Host::new(p.USB)?;
+//! while !host.is_connected() {}
+//! let device = host.enumerate()?;
+//! let stick = MassStorage::new(&mut host, &device)?;
+//! let fs = hal::sdmmc::fat::mount(stick)?;
+//! ```
+
+use core::marker::PhantomData;
+
+use embedded_hal::delay::DelayNs;
+
+use super::{
+    core_reset, flush_fifos, read_fifo, regs, set_turnaround, write_fifo, Timeout, GAHBCFG_GINTMSK, GATES, GINT_RXFLVL,
+    GRXSTS_BCNT_MASK, GRXSTS_BCNT_OFFSET, GRXSTS_PKTSTS_MASK, GRXSTS_PKTSTS_OFFSET, GUSBCFG_FDMOD, GUSBCFG_FHMOD,
+};
+use crate::delay::{wait, Delay};
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, Peripheral};
+
+pub mod hid;
+pub mod msc;
+
+/// Address given to the device on the root port
+pub const DEVICE_ADDRESS: u8 = 1;
+/// Configuration descriptor bytes kept by [`Device`], longer ones are truncated
+pub const MAX_CONFIG_SIZE: usize = 512;
+
+// FIFO layout in words: RX, non-periodic TX, periodic TX
+const RX_FIFO_WORDS: u32 = 272;
+const NPTX_FIFO_WORDS: u32 = 256;
+const PTX_FIFO_WORDS: u32 = 128;
+
+/// Channel every transfer goes through
+const CH: usize = 0;
+
+// GOTGCTL, session valid overrides for a host without VBUS sensing
+const GOTGCTL_VBVALOEN: u32 = 1 << 2;
+const GOTGCTL_VBVALOVAL: u32 = 1 << 3;
+const GOTGCTL_AVALOEN: u32 = 1 << 4;
+const GOTGCTL_AVALOVAL: u32 = 1 << 5;
+
+/// GRXSTSP, IN data packet received
+const PKTSTS_IN_DATA: u32 = 2;
+
+/// HCFG, FS/LS PHY clock select, 30/60MHz UTMI+
+const HCFG_FSLSPCS_WIDTH: u32 = 2;
+
+/// HFNUM, odd frame
+const HFNUM_ODD: u32 = 1 << 0;
+
+// HPRT
+const HPRT_PCSTS: u32 = 1 << 0;
+const HPRT_PCDET: u32 = 1 << 1;
+const HPRT_PENA: u32 = 1 << 2;
+const HPRT_PENCHNG: u32 = 1 << 3;
+const HPRT_POCCHNG: u32 = 1 << 5;
+const HPRT_PRST: u32 = 1 << 8;
+const HPRT_PPWR: u32 = 1 << 12;
+const HPRT_PSPD_OFFSET: u32 = 17;
+/// Write 1 to clear bits, writing back what was read would disable the port
+const HPRT_W1C: u32 = HPRT_PCDET | HPRT_PENA | HPRT_PENCHNG | HPRT_POCCHNG;
+
+// HCCHAR
+const HCCHAR_EPNUM_OFFSET: u32 = 11;
+const HCCHAR_EPDIR_IN: u32 = 1 << 15;
+const HCCHAR_LSDEV: u32 = 1 << 17;
+const HCCHAR_EPTYP_OFFSET: u32 = 18;
+const HCCHAR_MC_1: u32 = 1 << 20;
+const HCCHAR_DAD_OFFSET: u32 = 22;
+const HCCHAR_ODDFRM: u32 = 1 << 29;
+const HCCHAR_CHDIS: u32 = 1 << 30;
+const HCCHAR_CHENA: u32 = 1 << 31;
+
+// HCINT
+const HCINT_XFRC: u32 = 1 << 0;
+const HCINT_AHBERR: u32 = 1 << 2;
+const HCINT_STALL: u32 = 1 << 3;
+const HCINT_NAK: u32 = 1 << 4;
+const HCINT_NYET: u32 = 1 << 6;
+const HCINT_TXERR: u32 = 1 << 7;
+const HCINT_BBERR: u32 = 1 << 8;
+const HCINT_FRMOR: u32 = 1 << 9;
+const HCINT_DTERR: u32 = 1 << 10;
+
+// HCTSIZ
+const HCTSIZ_PKTCNT_OFFSET: u32 = 19;
+const HCTSIZ_DPID_OFFSET: u32 = 29;
+
+const CONNECT_DEBOUNCE_MS: u32 = 100;
+const PORT_RESET_MS: u32 = 50;
+const PORT_ENABLE_TIMEOUT_US: u32 = 500_000;
+const PACKET_TIMEOUT_US: u32 = 100_000;
+const CONTROL_TIMEOUT_US: u32 = 1_000_000;
+const BULK_TIMEOUT_US: u32 = 5_000_000;
+/// Pause between retries of a NAKed packet
+const NAK_RETRY_US: u32 = 20;
+
+// Standard requests
+pub const REQUEST_GET_STATUS: u8 = 0x00;
+pub const REQUEST_CLEAR_FEATURE: u8 = 0x01;
+pub const REQUEST_SET_FEATURE: u8 = 0x03;
+pub const REQUEST_SET_ADDRESS: u8 = 0x05;
+pub const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+pub const REQUEST_SET_CONFIGURATION: u8 = 0x09;
+
+// bmRequestType
+pub const REQUEST_TYPE_IN: u8 = 0x80;
+pub const REQUEST_TYPE_CLASS: u8 = 0x20;
+pub const REQUEST_TYPE_INTERFACE: u8 = 0x01;
+pub const REQUEST_TYPE_ENDPOINT: u8 = 0x02;
+
+/// CLEAR_FEATURE selector
+pub const FEATURE_ENDPOINT_HALT: u16 = 0;
+
+// Descriptor types
+pub const DESCRIPTOR_DEVICE: u8 = 1;
+pub const DESCRIPTOR_CONFIGURATION: u8 = 2;
+pub const DESCRIPTOR_INTERFACE: u8 = 4;
+pub const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+const CLASS_HUB: u8 = 0x09;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    /// Nothing is plugged into the port
+    NotConnected,
+    /// Port didn't come out of reset enabled
+    PortDisabled,
+    /// Endpoint halted, or the request is not supported
+    Stall,
+    /// Device kept NAKing, or the controller didn't finish
+    Timeout,
+    /// CRC, bit stuffing or response timeout
+    Transaction,
+    /// Device sent more than a packet
+    Babble,
+    DataToggle,
+    /// Controller bus error
+    Bus,
+    /// Descriptor too short or inconsistent
+    InvalidDescriptor,
+    /// Hubs, or a class driver didn't find its interface
+    UnsupportedDevice,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Speed {
+    Low,
+    Full,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TransferType {
+    Control = 0,
+    Isochronous = 1,
+    Bulk = 2,
+    Interrupt = 3,
+}
+
+/// Packet identifier, HCTSIZ.DPID
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Pid {
+    Data0 = 0,
+    Data1 = 2,
+    Setup = 3,
+}
+
+impl Pid {
+    #[inline]
+    fn toggle(toggle: bool) -> Self {
+        if toggle {
+            Pid::Data1
+        } else {
+            Pid::Data0
+        }
+    }
+}
+
+/// Control request, wLength is the data buffer's length
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Request {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+}
+
+impl Request {
+    fn setup_packet(&self, length: u16) -> [u8; 8] {
+        let [value_lo, value_hi] = self.value.to_le_bytes();
+        let [index_lo, index_hi] = self.index.to_le_bytes();
+        let [length_lo, length_hi] = length.to_le_bytes();
+        [
+            self.request_type,
+            self.request,
+            value_lo,
+            value_hi,
+            index_lo,
+            index_hi,
+            length_lo,
+            length_hi,
+        ]
+    }
+
+    pub const fn get_descriptor(descriptor_type: u8, index: u8) -> Self {
+        Self {
+            request_type: REQUEST_TYPE_IN,
+            request: REQUEST_GET_DESCRIPTOR,
+            value: ((descriptor_type as u16) << 8) | index as u16,
+            index: 0,
+        }
+    }
+
+    /// Clear ENDPOINT_HALT on `endpoint`, an address with the direction bit
+    pub const fn clear_halt(endpoint: u8) -> Self {
+        Self {
+            request_type: REQUEST_TYPE_ENDPOINT,
+            request: REQUEST_CLEAR_FEATURE,
+            value: FEATURE_ENDPOINT_HALT,
+            index: endpoint as u16,
+        }
+    }
+}
+
+/// Device descriptor fields
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DeviceDescriptor {
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub device_version: u16,
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    fn parse(d: &[u8; 18]) -> Self {
+        Self {
+            usb_version: u16::from_le_bytes([d[2], d[3]]),
+            class: d[4],
+            subclass: d[5],
+            protocol: d[6],
+            max_packet_size0: d[7],
+            vendor_id: u16::from_le_bytes([d[8], d[9]]),
+            product_id: u16::from_le_bytes([d[10], d[11]]),
+            device_version: u16::from_le_bytes([d[12], d[13]]),
+            num_configurations: d[17],
+        }
+    }
+}
+
+/// Endpoint descriptor fields
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EndpointInfo {
+    /// Endpoint address, bit 7 set for IN
+    pub address: u8,
+    pub transfer_type: TransferType,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl EndpointInfo {
+    #[inline]
+    pub fn number(&self) -> u8 {
+        self.address & 0x0f
+    }
+
+    #[inline]
+    pub fn direction(&self) -> Direction {
+        if self.address & 0x80 != 0 {
+            Direction::In
+        } else {
+            Direction::Out
+        }
+    }
+}
+
+/// An interface of the active configuration, with the descriptors that follow it
+#[derive(Debug, Clone, Copy)]
+pub struct Interface<'a> {
+    pub number: u8,
+    pub alternate_setting: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    descriptors: &'a [u8],
+}
+
+impl<'a> Interface<'a> {
+    pub fn endpoints(&self) -> impl Iterator<Item = EndpointInfo> + 'a {
+        descriptors(self.descriptors)
+            .take_while(|d| d[1] != DESCRIPTOR_INTERFACE)
+            .filter(|d| d[1] == DESCRIPTOR_ENDPOINT && d.len() >= 7)
+            .map(|d| EndpointInfo {
+                address: d[2],
+                transfer_type: match d[3] & 0b11 {
+                    0 => TransferType::Control,
+                    1 => TransferType::Isochronous,
+                    2 => TransferType::Bulk,
+                    _ => TransferType::Interrupt,
+                },
+                max_packet_size: u16::from_le_bytes([d[4], d[5]]) & 0x7ff,
+                interval: d[6],
+            })
+    }
+
+    /// First endpoint of a kind
+    pub fn endpoint(&self, direction: Direction, transfer_type: TransferType) -> Option<EndpointInfo> {
+        self.endpoints()
+            .find(|ep| ep.direction() == direction && ep.transfer_type == transfer_type)
+    }
+
+    /// Class specific descriptors between the interface and its first endpoint, like the HID descriptor
+    pub fn class_descriptors(&self) -> impl Iterator<Item = &'a [u8]> {
+        descriptors(self.descriptors).take_while(|d| d[1] != DESCRIPTOR_INTERFACE && d[1] != DESCRIPTOR_ENDPOINT)
+    }
+}
+
+/// Walk a descriptor list by bLength, stopping at a malformed one
+fn descriptors(mut bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    core::iter::from_fn(move || {
+        let len = *bytes.first()? as usize;
+        if len < 2 || len > bytes.len() {
+            return None;
+        }
+        let (d, rest) = bytes.split_at(len);
+        bytes = rest;
+        Some(d)
+    })
+}
+
+/// An enumerated and configured device
+pub struct Device {
+    address: u8,
+    speed: Speed,
+    max_packet_size0: u16,
+    descriptor: DeviceDescriptor,
+    config: [u8; MAX_CONFIG_SIZE],
+    config_len: usize,
+}
+
+impl Device {
+    #[inline]
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    #[inline]
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    #[inline]
+    pub fn descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    /// The active configuration descriptor with everything that follows it
+    #[inline]
+    pub fn configuration(&self) -> &[u8] {
+        &self.config[..self.config_len]
+    }
+
+    pub fn interfaces(&self) -> impl Iterator<Item = Interface<'_>> {
+        let config = self.configuration();
+        let mut offset = 0;
+        core::iter::from_fn(move || {
+            while offset < config.len() {
+                let d = descriptors(&config[offset..]).next()?;
+                offset += d.len();
+                if d[1] == DESCRIPTOR_INTERFACE && d.len() >= 9 {
+                    return Some(Interface {
+                        number: d[2],
+                        alternate_setting: d[3],
+                        class: d[5],
+                        subclass: d[6],
+                        protocol: d[7],
+                        descriptors: &config[offset..],
+                    });
+                }
+            }
+            None
+        })
+    }
+
+    /// First interface with the given class, subclass and protocol
+    pub fn find_interface(&self, class: u8, subclass: u8, protocol: u8) -> Option<Interface<'_>> {
+        self.interfaces()
+            .find(|i| i.class == class && i.subclass == subclass && i.protocol == protocol)
+    }
+
+    fn control(&self) -> Channel {
+        Channel {
+            address: self.address,
+            endpoint: 0,
+            transfer_type: TransferType::Control,
+            max_packet_size: self.max_packet_size0,
+            speed: self.speed,
+        }
+    }
+}
+
+/// What channel 0 is programmed with for a transaction
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    address: u8,
+    endpoint: u8,
+    transfer_type: TransferType,
+    max_packet_size: u16,
+    speed: Speed,
+}
+
+impl Channel {
+    fn hcchar(&self, direction: Direction) -> u32 {
+        let mut hcchar = self.max_packet_size as u32
+            | ((self.endpoint as u32) << HCCHAR_EPNUM_OFFSET)
+            | ((self.transfer_type as u32) << HCCHAR_EPTYP_OFFSET)
+            | ((self.address as u32) << HCCHAR_DAD_OFFSET)
+            | HCCHAR_MC_1;
+        if direction == Direction::In {
+            hcchar |= HCCHAR_EPDIR_IN;
+        }
+        if self.speed == Speed::Low {
+            hcchar |= HCCHAR_LSDEV;
+        }
+        if self.transfer_type == TransferType::Interrupt && regs().hfnum().read() & HFNUM_ODD == 0 {
+            // schedule for the next frame
+            hcchar |= HCCHAR_ODDFRM;
+        }
+        hcchar
+    }
+}
+
+/// A bulk or interrupt endpoint of a device, keeps the data toggle
+pub struct Pipe {
+    channel: Channel,
+    direction: Direction,
+    toggle: bool,
+}
+
+impl Pipe {
+    pub fn new(device: &Device, endpoint: &EndpointInfo) -> Self {
+        Self {
+            channel: Channel {
+                address: device.address,
+                endpoint: endpoint.number(),
+                transfer_type: endpoint.transfer_type,
+                max_packet_size: endpoint.max_packet_size,
+                speed: device.speed,
+            },
+            direction: endpoint.direction(),
+            toggle: false,
+        }
+    }
+
+    /// Endpoint address, bit 7 set for IN
+    pub fn endpoint_address(&self) -> u8 {
+        match self.direction {
+            Direction::In => self.channel.endpoint | 0x80,
+            Direction::Out => self.channel.endpoint,
+        }
+    }
+
+    /// Restart at DATA0, after the endpoint's halt was cleared
+    pub fn reset_toggle(&mut self) {
+        self.toggle = false;
+    }
+}
+
+pub struct Host<'d> {
+    phantom: PhantomData<&'d mut peripherals::USB>,
+}
+
+impl<'d> Host<'d> {
+    pub fn new(_peri: impl Peripheral<P = peripherals::USB> + 'd) -> Result<Self, Error> {
+        into_ref!(_peri);
+        rcc::enable_and_reset(&GATES, Reset::Usb);
+        // built first so a stuck core is gated again on the way out
+        let host = Self { phantom: PhantomData };
+
+        let regs = regs();
+        core_reset()?;
+        regs.gusbcfg().modify(|r| (r & !GUSBCFG_FDMOD) | GUSBCFG_FHMOD);
+        set_turnaround();
+        // forced mode takes effect after 25ms
+        Delay::new().delay_ms(25);
+
+        regs.gotgctl()
+            .set_bits(GOTGCTL_VBVALOEN | GOTGCTL_VBVALOVAL | GOTGCTL_AVALOEN | GOTGCTL_AVALOVAL);
+        regs.hcfg().write_field(0, HCFG_FSLSPCS_WIDTH, 0);
+
+        regs.grxfsiz().write(RX_FIFO_WORDS);
+        regs.gnptxfsiz().write((NPTX_FIFO_WORDS << 16) | RX_FIFO_WORDS);
+        regs.hptxfsiz()
+            .write((PTX_FIFO_WORDS << 16) | (RX_FIFO_WORDS + NPTX_FIFO_WORDS));
+        flush_fifos()?;
+
+        regs.gintmsk().write(0);
+        regs.gintsts().write(u32::MAX);
+        regs.gahbcfg().write(GAHBCFG_GINTMSK);
+        Self::modify_port(HPRT_PPWR, 0);
+
+        Ok(host)
+    }
+
+    /// Change HPRT without touching its write-1-to-clear bits
+    fn modify_port(set: u32, clear: u32) {
+        regs().hprt().modify(|r| (r & !HPRT_W1C & !clear) | set);
+    }
+
+    #[inline]
+    pub fn is_connected(&self) -> bool {
+        regs().hprt().is_set(HPRT_PCSTS)
+    }
+
+    /// Reset the port after the connect debounce, returning the device's speed
+    pub fn reset_port(&mut self) -> Result<Speed, Error> {
+        let mut delay = Delay::new();
+        if !self.is_connected() {
+            return Err(Error::NotConnected);
+        }
+        delay.delay_ms(CONNECT_DEBOUNCE_MS);
+        regs().hprt().modify(|r| (r & !HPRT_W1C) | HPRT_PCDET | HPRT_PENCHNG);
+
+        Self::modify_port(HPRT_PRST, 0);
+        delay.delay_ms(PORT_RESET_MS);
+        Self::modify_port(0, HPRT_PRST);
+
+        wait(
+            || regs().hprt().is_set(HPRT_PENA),
+            PORT_ENABLE_TIMEOUT_US,
+            Error::PortDisabled,
+        )?;
+        // recovery time
+        delay.delay_ms(10);
+
+        Ok(match regs().hprt().read_field(HPRT_PSPD_OFFSET, 2) {
+            0 => Speed::High,
+            1 => Speed::Full,
+            _ => Speed::Low,
+        })
+    }
+
+    /// Reset the port, address the device and select its first configuration
+    pub fn enumerate(&mut self) -> Result<Device, Error> {
+        let speed = self.reset_port()?;
+        let mut device = Device {
+            address: 0,
+            speed,
+            // enough to read bMaxPacketSize0 from any device
+            max_packet_size0: 8,
+            descriptor: DeviceDescriptor::parse(&[0; 18]),
+            config: [0; MAX_CONFIG_SIZE],
+            config_len: 0,
+        };
+
+        let mut desc = [0; 18];
+        self.control_in(&device, Request::get_descriptor(DESCRIPTOR_DEVICE, 0), &mut desc[..8])?;
+        device.max_packet_size0 = match desc[7] {
+            8 | 16 | 32 | 64 => desc[7] as u16,
+            _ => return Err(Error::InvalidDescriptor),
+        };
+
+        let set_address = Request {
+            request_type: 0,
+            request: REQUEST_SET_ADDRESS,
+            value: DEVICE_ADDRESS as u16,
+            index: 0,
+        };
+        self.control_out(&device, set_address, &[])?;
+        Delay::new().delay_ms(2);
+        device.address = DEVICE_ADDRESS;
+
+        if self.control_in(&device, Request::get_descriptor(DESCRIPTOR_DEVICE, 0), &mut desc)? < desc.len() {
+            return Err(Error::InvalidDescriptor);
+        }
+        device.descriptor = DeviceDescriptor::parse(&desc);
+        if device.descriptor.class == CLASS_HUB {
+            return Err(Error::UnsupportedDevice);
+        }
+
+        let mut header = [0; 9];
+        let request = Request::get_descriptor(DESCRIPTOR_CONFIGURATION, 0);
+        if self.control_in(&device, request, &mut header)? < header.len() {
+            return Err(Error::InvalidDescriptor);
+        }
+        let total = (u16::from_le_bytes([header[2], header[3]]) as usize).min(MAX_CONFIG_SIZE);
+        let mut config = [0; MAX_CONFIG_SIZE];
+        device.config_len = self.control_in(&device, request, &mut config[..total])?;
+        device.config = config;
+
+        let set_configuration = Request {
+            request_type: 0,
+            request: REQUEST_SET_CONFIGURATION,
+            value: header[5] as u16,
+            index: 0,
+        };
+        self.control_out(&device, set_configuration, &[])?;
+        Ok(device)
+    }
+
+    /// Control transfer reading up to `buf.len()` bytes, returns the bytes read
+    pub fn control_in(&mut self, device: &Device, request: Request, buf: &mut [u8]) -> Result<usize, Error> {
+        let channel = device.control();
+        self.setup(channel, request, buf.len() as u16)?;
+        let len = if buf.is_empty() {
+            0
+        } else {
+            self.transfer_in(channel, &mut true, buf, CONTROL_TIMEOUT_US)?
+        };
+        self.transfer_out(channel, &mut true, &[], CONTROL_TIMEOUT_US)?;
+        Ok(len)
+    }
+
+    /// Control transfer writing `data`, which may be empty
+    pub fn control_out(&mut self, device: &Device, request: Request, data: &[u8]) -> Result<(), Error> {
+        let channel = device.control();
+        self.setup(channel, request, data.len() as u16)?;
+        if !data.is_empty() {
+            self.transfer_out(channel, &mut true, data, CONTROL_TIMEOUT_US)?;
+        }
+        self.transfer_in(channel, &mut true, &mut [], CONTROL_TIMEOUT_US)?;
+        Ok(())
+    }
+
+    /// Read until `buf` is full or the device sends a short packet
+    pub fn bulk_in(&mut self, pipe: &mut Pipe, buf: &mut [u8]) -> Result<usize, Error> {
+        self.transfer_in(pipe.channel, &mut pipe.toggle, buf, BULK_TIMEOUT_US)
+    }
+
+    /// Write `data` in max size packets, no zero length packet is added
+    pub fn bulk_out(&mut self, pipe: &mut Pipe, data: &[u8]) -> Result<(), Error> {
+        self.transfer_out(pipe.channel, &mut pipe.toggle, data, BULK_TIMEOUT_US)
+    }
+
+    /// Read one packet from an interrupt endpoint, `None` when the device has nothing to report.
+    ///
+    /// Polling faster than the endpoint's interval only gets more NAKs.
+    pub fn interrupt_in(&mut self, pipe: &mut Pipe, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        let len = buf.len().min(pipe.channel.max_packet_size as usize);
+        let received = self.packet_in(pipe.channel, Pid::toggle(pipe.toggle), &mut buf[..len])?;
+        if received.is_some() {
+            pipe.toggle = !pipe.toggle;
+        }
+        Ok(received)
+    }
+
+    fn setup(&mut self, channel: Channel, request: Request, length: u16) -> Result<(), Error> {
+        let packet = request.setup_packet(length);
+        retry(CONTROL_TIMEOUT_US, || self.packet_out(channel, Pid::Setup, &packet))
+    }
+
+    fn transfer_in(
+        &mut self,
+        channel: Channel,
+        toggle: &mut bool,
+        buf: &mut [u8],
+        timeout_us: u32,
+    ) -> Result<usize, Error> {
+        let mps = channel.max_packet_size as usize;
+        let mut offset = 0;
+        loop {
+            let end = (offset + mps).min(buf.len());
+            let n = retry(timeout_us, || {
+                self.packet_in(channel, Pid::toggle(*toggle), &mut buf[offset..end])
+            })?;
+            *toggle = !*toggle;
+            offset += n;
+            if n < mps || offset == buf.len() {
+                return Ok(offset);
+            }
+        }
+    }
+
+    fn transfer_out(&mut self, channel: Channel, toggle: &mut bool, data: &[u8], timeout_us: u32) -> Result<(), Error> {
+        let mps = channel.max_packet_size as usize;
+        // an empty transfer is one zero length packet
+        let mut chunks = data.chunks(mps);
+        let mut next = Some(chunks.next().unwrap_or(&[]));
+        while let Some(chunk) = next {
+            retry(timeout_us, || self.packet_out(channel, Pid::toggle(*toggle), chunk))?;
+            *toggle = !*toggle;
+            next = chunks.next();
+        }
+        Ok(())
+    }
+
+    /// One IN transaction into `buf`, `None` when NAKed
+    fn packet_in(&mut self, channel: Channel, pid: Pid, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        let regs = regs();
+        regs.hcint(CH).write(u32::MAX);
+        // IN sizes are whole packets, anything past `buf` is dropped and reported as babble
+        regs.hctsiz(CH)
+            .write(((pid as u32) << HCTSIZ_DPID_OFFSET) | (1 << HCTSIZ_PKTCNT_OFFSET) | channel.max_packet_size as u32);
+        regs.hcchar(CH).write(channel.hcchar(Direction::In) | HCCHAR_CHENA);
+
+        let mut received = 0;
+        let mut overflow = false;
+        let result = self.wait_channel(|| {
+            while regs.gintsts().is_set(GINT_RXFLVL) {
+                let status = regs.grxstsp().read();
+                if (status >> GRXSTS_PKTSTS_OFFSET) & GRXSTS_PKTSTS_MASK == PKTSTS_IN_DATA {
+                    let len = ((status >> GRXSTS_BCNT_OFFSET) & GRXSTS_BCNT_MASK) as usize;
+                    read_fifo(buf, len);
+                    overflow = len > buf.len();
+                    received = len.min(buf.len());
+                }
+            }
+        });
+        match result {
+            Ok(true) if overflow => Err(Error::Babble),
+            Ok(true) => Ok(Some(received)),
+            Ok(false) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// One OUT or SETUP transaction, `None` when NAKed
+    fn packet_out(&mut self, channel: Channel, pid: Pid, data: &[u8]) -> Result<Option<()>, Error> {
+        let regs = regs();
+        regs.hcint(CH).write(u32::MAX);
+        regs.hctsiz(CH)
+            .write(((pid as u32) << HCTSIZ_DPID_OFFSET) | (1 << HCTSIZ_PKTCNT_OFFSET) | data.len() as u32);
+        regs.hcchar(CH).write(channel.hcchar(Direction::Out) | HCCHAR_CHENA);
+
+        let words = data.len().div_ceil(4) as u32;
+        let space = match channel.transfer_type {
+            TransferType::Interrupt | TransferType::Isochronous => regs.hptxsts(),
+            _ => regs.gnptxsts(),
+        };
+        wait(|| space.read() & 0xffff >= words, PACKET_TIMEOUT_US, Error::Timeout)?;
+        write_fifo(CH, data);
+
+        self.wait_channel(|| {}).map(|done| done.then_some(()))
+    }
+
+    /// Wait for the channel's outcome, calling `drain` meanwhile, then halt it.
+    ///
+    /// Returns `true` when the transaction completed, `false` on NAK.
+    fn wait_channel(&mut self, mut drain: impl FnMut()) -> Result<bool, Error> {
+        let hcint = regs().hcint(CH);
+        let mut status = 0;
+        let result = wait(
+            || {
+                drain();
+                status = hcint.read();
+                status
+                    & (HCINT_XFRC
+                        | HCINT_NYET
+                        | HCINT_NAK
+                        | HCINT_STALL
+                        | HCINT_TXERR
+                        | HCINT_BBERR
+                        | HCINT_FRMOR
+                        | HCINT_DTERR
+                        | HCINT_AHBERR)
+                    != 0
+            },
+            PACKET_TIMEOUT_US,
+            Error::Timeout,
+        );
+        // data may still be queued behind the status, drain it before halting
+        drain();
+        self.halt(&mut drain);
+        result?;
+
+        match status {
+            // NYET on a high speed OUT means the data was taken
+            s if s & (HCINT_XFRC | HCINT_NYET) != 0 => Ok(true),
+            s if s & HCINT_STALL != 0 => Err(Error::Stall),
+            s if s & HCINT_NAK != 0 => Ok(false),
+            s if s & HCINT_BBERR != 0 => Err(Error::Babble),
+            s if s & HCINT_DTERR != 0 => Err(Error::DataToggle),
+            s if s & HCINT_AHBERR != 0 => Err(Error::Bus),
+            _ => Err(Error::Transaction),
+        }
+    }
+
+    /// Disable the channel if it's still running, so it can be reprogrammed
+    fn halt(&mut self, drain: &mut impl FnMut()) {
+        let regs = regs();
+        let hcchar = regs.hcchar(CH);
+        if hcchar.is_set(HCCHAR_CHENA) {
+            hcchar.set_bits(HCCHAR_CHDIS | HCCHAR_CHENA);
+            let _ = wait(
+                || {
+                    drain();
+                    // the halted status goes through the RX FIFO too
+                    while regs.gintsts().is_set(GINT_RXFLVL) {
+                        regs.grxstsp().read();
+                    }
+                    !hcchar.is_set(HCCHAR_CHENA)
+                },
+                PACKET_TIMEOUT_US,
+                (),
+            );
+        }
+        regs.hcint(CH).write(u32::MAX);
+    }
+}
+
+impl From<Timeout> for Error {
+    fn from(_: Timeout) -> Self {
+        Error::Timeout
+    }
+}
+
+impl Drop for Host<'_> {
+    fn drop(&mut self) {
+        Self::modify_port(0, HPRT_PPWR);
+        rcc::disable(&GATES, Reset::Usb);
+    }
+}
+
+/// Repeat a NAKed transaction until it goes through or `timeout_us` passes
+fn retry<T>(timeout_us: u32, mut f: impl FnMut() -> Result<Option<T>, Error>) -> Result<T, Error> {
+    let mut delay = Delay::new();
+    let mut waited = 0;
+    loop {
+        if let Some(value) = f()? {
+            return Ok(value);
+        }
+        if waited >= timeout_us {
+            return Err(Error::Timeout);
+        }
+        delay.delay_us(NAK_RETRY_US);
+        waited += NAK_RETRY_US;
+    }
+}
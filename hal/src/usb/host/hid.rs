@@ -0,0 +1,170 @@
+//! USB HID boot protocol keyboards
+//!
+//! [`Keyboard`] switches the keyboard to the boot protocol, whose 8 byte
+//! reports need no report descriptor parsing, then reads them from the
+//! interrupt endpoint. Poll at least every [`Keyboard::interval_ms`].
+
+use super::{Device, Direction, Error, Host, Pipe, Request, TransferType, REQUEST_TYPE_CLASS, REQUEST_TYPE_INTERFACE};
+
+pub const CLASS_HID: u8 = 0x03;
+pub const SUBCLASS_BOOT: u8 = 0x01;
+pub const PROTOCOL_KEYBOARD: u8 = 0x01;
+
+const REQUEST_SET_REPORT: u8 = 0x09;
+const REQUEST_SET_IDLE: u8 = 0x0a;
+const REQUEST_SET_PROTOCOL: u8 = 0x0b;
+const PROTOCOL_BOOT: u16 = 0;
+const REPORT_TYPE_OUTPUT: u16 = 2;
+
+const REPORT_LEN: usize = 8;
+/// Usage reported in every key slot when too many keys are down
+const USAGE_ERROR_ROLL_OVER: u8 = 0x01;
+
+// Modifier bits
+pub const MOD_LEFT_CTRL: u8 = 1 << 0;
+pub const MOD_LEFT_SHIFT: u8 = 1 << 1;
+pub const MOD_LEFT_ALT: u8 = 1 << 2;
+pub const MOD_LEFT_GUI: u8 = 1 << 3;
+pub const MOD_RIGHT_CTRL: u8 = 1 << 4;
+pub const MOD_RIGHT_SHIFT: u8 = 1 << 5;
+pub const MOD_RIGHT_ALT: u8 = 1 << 6;
+pub const MOD_RIGHT_GUI: u8 = 1 << 7;
+
+// LED bits for [`Keyboard::set_leds`]
+pub const LED_NUM_LOCK: u8 = 1 << 0;
+pub const LED_CAPS_LOCK: u8 = 1 << 1;
+pub const LED_SCROLL_LOCK: u8 = 1 << 2;
+
+/// Boot protocol input report
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct KeyboardReport {
+    pub modifiers: u8,
+    /// Usage IDs of up to 6 keys held down, 0 for empty slots
+    pub keys: [u8; 6],
+}
+
+impl KeyboardReport {
+    fn parse(report: &[u8; REPORT_LEN]) -> Self {
+        let mut keys = [0; 6];
+        keys.copy_from_slice(&report[2..]);
+        Self {
+            modifiers: report[0],
+            keys,
+        }
+    }
+
+    #[inline]
+    pub fn shift(&self) -> bool {
+        self.modifiers & (MOD_LEFT_SHIFT | MOD_RIGHT_SHIFT) != 0
+    }
+
+    #[inline]
+    pub fn ctrl(&self) -> bool {
+        self.modifiers & (MOD_LEFT_CTRL | MOD_RIGHT_CTRL) != 0
+    }
+
+    /// Keys held down, nothing while the keyboard reports roll over
+    pub fn pressed(&self) -> impl Iterator<Item = u8> + '_ {
+        let roll_over = self.keys.contains(&USAGE_ERROR_ROLL_OVER);
+        self.keys.iter().copied().filter(move |&k| k != 0 && !roll_over)
+    }
+
+    #[inline]
+    pub fn is_pressed(&self, usage: u8) -> bool {
+        self.pressed().any(|k| k == usage)
+    }
+}
+
+pub struct Keyboard {
+    pipe: Pipe,
+    interface: u8,
+    interval: u8,
+}
+
+impl Keyboard {
+    /// Open the device's boot keyboard interface
+    pub fn new(host: &mut Host<'_>, device: &Device) -> Result<Self, Error> {
+        let interface = device
+            .find_interface(CLASS_HID, SUBCLASS_BOOT, PROTOCOL_KEYBOARD)
+            .ok_or(Error::UnsupportedDevice)?;
+        let endpoint = interface
+            .endpoint(Direction::In, TransferType::Interrupt)
+            .ok_or(Error::UnsupportedDevice)?;
+
+        let keyboard = Self {
+            pipe: Pipe::new(device, &endpoint),
+            interface: interface.number,
+            interval: endpoint.interval,
+        };
+        host.control_out(device, keyboard.request(REQUEST_SET_PROTOCOL, PROTOCOL_BOOT), &[])?;
+        // report only on change, optional for keyboards, so a stall is fine
+        match host.control_out(device, keyboard.request(REQUEST_SET_IDLE, 0), &[]) {
+            Ok(()) | Err(Error::Stall) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(keyboard)
+    }
+
+    fn request(&self, request: u8, value: u16) -> Request {
+        Request {
+            request_type: REQUEST_TYPE_CLASS | REQUEST_TYPE_INTERFACE,
+            request,
+            value,
+            index: self.interface as u16,
+        }
+    }
+
+    /// Polling interval the keyboard asks for, frames of 1ms at low and full speed
+    #[inline]
+    pub fn interval_ms(&self) -> u8 {
+        self.interval
+    }
+
+    /// The next report, `None` when nothing changed since the last one
+    pub fn poll(&mut self, host: &mut Host<'_>) -> Result<Option<KeyboardReport>, Error> {
+        let mut report = [0; REPORT_LEN];
+        match host.interrupt_in(&mut self.pipe, &mut report)? {
+            Some(len) if len >= 3 => Ok(Some(KeyboardReport::parse(&report))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Set the lock LEDs, `LED_*` bits
+    pub fn set_leds(&mut self, host: &mut Host<'_>, device: &Device, leds: u8) -> Result<(), Error> {
+        host.control_out(
+            device,
+            self.request(REQUEST_SET_REPORT, REPORT_TYPE_OUTPUT << 8),
+            &[leds],
+        )
+    }
+}
+
+/// ASCII for a keyboard usage ID on a US layout, letters, digits, punctuation, enter, tab, space and backspace
+pub fn usage_to_ascii(usage: u8, shift: bool) -> Option<u8> {
+    const DIGITS: &[u8; 10] = b"1234567890";
+    const SHIFTED_DIGITS: &[u8; 10] = b"!@#$%^&*()";
+    // usages 0x2d ~ 0x38
+    const PUNCTUATION: &[u8; 12] = b"-=[]\\#;'`,./";
+    const SHIFTED_PUNCTUATION: &[u8; 12] = b"_+{}|~:\"~<>?";
+
+    let c = match usage {
+        0x04..=0x1d => {
+            let c = b'a' + (usage - 0x04);
+            if shift {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        }
+        0x1e..=0x27 if shift => SHIFTED_DIGITS[(usage - 0x1e) as usize],
+        0x1e..=0x27 => DIGITS[(usage - 0x1e) as usize],
+        0x28 => b'\n',
+        0x2a => 0x08,
+        0x2b => b'\t',
+        0x2c => b' ',
+        0x2d..=0x38 if shift => SHIFTED_PUNCTUATION[(usage - 0x2d) as usize],
+        0x2d..=0x38 => PUNCTUATION[(usage - 0x2d) as usize],
+        _ => return None,
+    };
+    Some(c)
+}
@@ -0,0 +1,323 @@
+//! USB mass storage, bulk-only transport with SCSI block commands
+//!
+//! [`MassStorage`] implements [`BlockDevice`] like the SD card driver, so
+//! `sdmmc::fat` mounts flash sticks the same way. Only LUN 0 is used and the
+//! medium must have 512 byte blocks.
+
+use core::cell::{Cell, RefCell};
+
+use embedded_hal::delay::DelayNs;
+
+use super::{Device, Direction, Host, Pipe, Request, TransferType, REQUEST_TYPE_CLASS, REQUEST_TYPE_INTERFACE};
+use crate::delay::Delay;
+use crate::sdmmc::{Block, BlockCount, BlockDevice, BlockIdx, BLOCK_SIZE};
+
+pub const CLASS_MASS_STORAGE: u8 = 0x08;
+pub const SUBCLASS_SCSI: u8 = 0x06;
+pub const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+/// Bulk-Only Mass Storage Reset
+const REQUEST_BOMS_RESET: u8 = 0xff;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_LEN: usize = 31;
+const CSW_LEN: usize = 13;
+const CBW_FLAG_IN: u8 = 0x80;
+
+const CSW_STATUS_PASSED: u8 = 0;
+const CSW_STATUS_FAILED: u8 = 1;
+
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_READ_CAPACITY_10: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2a;
+
+const SENSE_LEN: usize = 18;
+const SENSE_KEY_NO_SENSE: u8 = 0x0;
+/// Blocks per READ(10)/WRITE(10)
+const MAX_BLOCKS_PER_COMMAND: usize = 128;
+/// Sticks can take a few seconds to spin up their controller
+const READY_RETRIES: u32 = 50;
+const READY_RETRY_MS: u32 = 100;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    Usb(super::Error),
+    /// No bulk-only SCSI interface on the device
+    NotMassStorage,
+    /// Medium didn't become ready
+    NotReady,
+    /// Block size other than [`BLOCK_SIZE`]
+    UnsupportedBlockSize(u32),
+    /// Device reported the command as failed, with the sense key
+    CommandFailed {
+        sense_key: u8,
+    },
+    /// Transport out of step, the device was reset
+    Phase,
+    /// Block address beyond the end of the medium
+    OutOfRange,
+}
+
+impl From<super::Error> for Error {
+    fn from(e: super::Error) -> Self {
+        Error::Usb(e)
+    }
+}
+
+pub struct MassStorage<'h, 'd> {
+    host: RefCell<&'h mut Host<'d>>,
+    device: &'h Device,
+    interface: u8,
+    bulk_in: RefCell<Pipe>,
+    bulk_out: RefCell<Pipe>,
+    tag: Cell<u32>,
+    blocks: u32,
+}
+
+impl<'h, 'd> MassStorage<'h, 'd> {
+    /// Open the device's mass storage interface and wait for the medium
+    pub fn new(host: &'h mut Host<'d>, device: &'h Device) -> Result<Self, Error> {
+        let interface = device
+            .find_interface(CLASS_MASS_STORAGE, SUBCLASS_SCSI, PROTOCOL_BULK_ONLY)
+            .ok_or(Error::NotMassStorage)?;
+        let ep_in = interface
+            .endpoint(Direction::In, TransferType::Bulk)
+            .ok_or(Error::NotMassStorage)?;
+        let ep_out = interface
+            .endpoint(Direction::Out, TransferType::Bulk)
+            .ok_or(Error::NotMassStorage)?;
+
+        let mut msc = Self {
+            host: RefCell::new(host),
+            device,
+            interface: interface.number,
+            bulk_in: RefCell::new(Pipe::new(device, &ep_in)),
+            bulk_out: RefCell::new(Pipe::new(device, &ep_out)),
+            tag: Cell::new(0),
+            blocks: 0,
+        };
+
+        let mut retries = 0;
+        loop {
+            match msc.command(&[SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0], Data::None) {
+                Ok(()) => break,
+                Err(Error::CommandFailed { .. }) if retries < READY_RETRIES => {
+                    retries += 1;
+                    Delay::new().delay_ms(READY_RETRY_MS);
+                }
+                Err(Error::CommandFailed { .. }) => return Err(Error::NotReady),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut capacity = [0; 8];
+        msc.command(
+            &[SCSI_READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            Data::In(&mut capacity),
+        )?;
+        let last = u32::from_be_bytes([capacity[0], capacity[1], capacity[2], capacity[3]]);
+        let block_len = u32::from_be_bytes([capacity[4], capacity[5], capacity[6], capacity[7]]);
+        if block_len != BLOCK_SIZE as u32 {
+            return Err(Error::UnsupportedBlockSize(block_len));
+        }
+        msc.blocks = last.wrapping_add(1);
+        Ok(msc)
+    }
+
+    /// Capacity in blocks of [`BLOCK_SIZE`]
+    #[inline]
+    pub fn blocks(&self) -> u32 {
+        self.blocks
+    }
+
+    pub fn read_blocks(&self, start: u32, blocks: &mut [Block]) -> Result<(), Error> {
+        self.check_range(start, blocks.len())?;
+        for (i, chunk) in blocks.chunks_mut(MAX_BLOCKS_PER_COMMAND).enumerate() {
+            let lba = start + (i * MAX_BLOCKS_PER_COMMAND) as u32;
+            self.command(&rw10(SCSI_READ_10, lba, chunk.len() as u16), Data::InBlocks(chunk))?;
+        }
+        Ok(())
+    }
+
+    pub fn write_blocks(&self, start: u32, blocks: &[Block]) -> Result<(), Error> {
+        self.check_range(start, blocks.len())?;
+        for (i, chunk) in blocks.chunks(MAX_BLOCKS_PER_COMMAND).enumerate() {
+            let lba = start + (i * MAX_BLOCKS_PER_COMMAND) as u32;
+            self.command(&rw10(SCSI_WRITE_10, lba, chunk.len() as u16), Data::OutBlocks(chunk))?;
+        }
+        Ok(())
+    }
+
+    fn check_range(&self, start: u32, count: usize) -> Result<(), Error> {
+        match (start as usize).checked_add(count) {
+            Some(end) if end <= self.blocks as usize => Ok(()),
+            _ => Err(Error::OutOfRange),
+        }
+    }
+
+    /// Run one SCSI command, fetching the sense key when it fails
+    fn command(&self, cb: &[u8], data: Data<'_>) -> Result<(), Error> {
+        if self.transfer(cb, data)? {
+            Ok(())
+        } else {
+            Err(Error::CommandFailed {
+                sense_key: self.request_sense()?,
+            })
+        }
+    }
+
+    /// Run one SCSI command through CBW, data and CSW stages, `false` if the
+    /// device reports it failed
+    fn transfer(&self, cb: &[u8], data: Data<'_>) -> Result<bool, Error> {
+        let tag = self.tag.get().wrapping_add(1);
+        self.tag.set(tag);
+
+        let mut cbw = [0; CBW_LEN];
+        cbw[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw[4..8].copy_from_slice(&tag.to_le_bytes());
+        cbw[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        cbw[12] = if data.is_in() { CBW_FLAG_IN } else { 0 };
+        // LUN 0
+        cbw[13] = 0;
+        cbw[14] = cb.len() as u8;
+        cbw[15..15 + cb.len()].copy_from_slice(cb);
+
+        let mut host = self.host.borrow_mut();
+        let mut bulk_in = self.bulk_in.borrow_mut();
+        let mut bulk_out = self.bulk_out.borrow_mut();
+        if let Err(e) = host.bulk_out(&mut bulk_out, &cbw) {
+            self.reset_recovery(&mut host, &mut bulk_in, &mut bulk_out);
+            return Err(e.into());
+        }
+
+        let is_in = data.is_in();
+        let result = match data {
+            Data::None => Ok(()),
+            Data::In(buf) => host.bulk_in(&mut bulk_in, buf).map(|_| ()),
+            Data::InBlocks(blocks) => blocks
+                .iter_mut()
+                .try_for_each(|block| host.bulk_in(&mut bulk_in, &mut block.contents).map(|_| ())),
+            Data::OutBlocks(blocks) => blocks
+                .iter()
+                .try_for_each(|block| host.bulk_out(&mut bulk_out, &block.contents)),
+        };
+        match result {
+            Ok(()) => {}
+            // the device ends a data stage it won't complete with a stall, the CSW follows
+            Err(super::Error::Stall) => {
+                let pipe = if is_in { &mut *bulk_in } else { &mut *bulk_out };
+                self.clear_halt(&mut host, pipe)?;
+            }
+            Err(e) => {
+                self.reset_recovery(&mut host, &mut bulk_in, &mut bulk_out);
+                return Err(e.into());
+            }
+        }
+
+        let mut csw = [0; CSW_LEN];
+        let received = match host.bulk_in(&mut bulk_in, &mut csw) {
+            Err(super::Error::Stall) => {
+                self.clear_halt(&mut host, &mut bulk_in)?;
+                host.bulk_in(&mut bulk_in, &mut csw)
+            }
+            r => r,
+        };
+        let valid = matches!(received, Ok(CSW_LEN))
+            && u32::from_le_bytes([csw[0], csw[1], csw[2], csw[3]]) == CSW_SIGNATURE
+            && u32::from_le_bytes([csw[4], csw[5], csw[6], csw[7]]) == tag;
+        if !valid {
+            self.reset_recovery(&mut host, &mut bulk_in, &mut bulk_out);
+            return Err(received.err().map_or(Error::Phase, Error::Usb));
+        }
+
+        match csw[12] {
+            CSW_STATUS_PASSED => Ok(true),
+            CSW_STATUS_FAILED => Ok(false),
+            _ => {
+                self.reset_recovery(&mut host, &mut bulk_in, &mut bulk_out);
+                Err(Error::Phase)
+            }
+        }
+    }
+
+    /// Sense key of the last failed command
+    ///
+    /// A REQUEST SENSE that fails itself isn't asked for its own sense data,
+    /// the key is then NO SENSE.
+    fn request_sense(&self) -> Result<u8, Error> {
+        let mut sense = [0; SENSE_LEN];
+        if self.transfer(&[SCSI_REQUEST_SENSE, 0, 0, 0, SENSE_LEN as u8, 0], Data::In(&mut sense))? {
+            Ok(sense[2] & 0x0f)
+        } else {
+            Ok(SENSE_KEY_NO_SENSE)
+        }
+    }
+
+    fn clear_halt(&self, host: &mut Host<'_>, pipe: &mut Pipe) -> Result<(), Error> {
+        host.control_out(self.device, Request::clear_halt(pipe.endpoint_address()), &[])?;
+        pipe.reset_toggle();
+        Ok(())
+    }
+
+    /// Bulk-only reset then clear both halts, best effort
+    fn reset_recovery(&self, host: &mut Host<'_>, bulk_in: &mut Pipe, bulk_out: &mut Pipe) {
+        let reset = Request {
+            request_type: REQUEST_TYPE_CLASS | REQUEST_TYPE_INTERFACE,
+            request: REQUEST_BOMS_RESET,
+            value: 0,
+            index: self.interface as u16,
+        };
+        let _ = host.control_out(self.device, reset, &[]);
+        let _ = self.clear_halt(host, bulk_in);
+        let _ = self.clear_halt(host, bulk_out);
+    }
+}
+
+impl BlockDevice for MassStorage<'_, '_> {
+    type Error = Error;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        self.read_blocks(start_block_idx.0, blocks)
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        self.write_blocks(start_block_idx.0, blocks)
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        Ok(BlockCount(self.blocks))
+    }
+}
+
+/// Data stage of a command
+enum Data<'a> {
+    None,
+    In(&'a mut [u8]),
+    InBlocks(&'a mut [Block]),
+    OutBlocks(&'a [Block]),
+}
+
+impl Data<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Data::None => 0,
+            Data::In(buf) => buf.len(),
+            Data::InBlocks(blocks) => blocks.len() * BLOCK_SIZE,
+            Data::OutBlocks(blocks) => blocks.len() * BLOCK_SIZE,
+        }
+    }
+
+    fn is_in(&self) -> bool {
+        matches!(self, Data::In(_) | Data::InBlocks(_))
+    }
+}
+
+/// READ(10) or WRITE(10) command block
+fn rw10(opcode: u8, lba: u32, blocks: u16) -> [u8; 10] {
+    let [l0, l1, l2, l3] = lba.to_be_bytes();
+    let [b0, b1] = blocks.to_be_bytes();
+    [opcode, 0, l0, l1, l2, l3, 0, b0, b1, 0]
+}
@@ -0,0 +1,204 @@
+//! WDT, DesignWare APB watchdog
+//!
+//! The timeout is `2^(16 + TOP)` cycles of the watchdog clock, TOP = 0 ~ 15,
+//! the clock rate comes from [`Clocks::timer`](crate::clocks::Clocks::timer).
+//! Once started, the watchdog can't be stopped until the next reset.
+//!
+//! With [`Config::pretimeout_interrupt`], the first expiry only raises
+//! [`IRQ`] and the chip is reset on the second one, so the handler has one
+//! more timeout period to save state or feed the watchdog.
+
+use core::marker::PhantomData;
+
+use fugit::MillisDurationU32 as MillisDuration;
+
+use crate::clocks::{clocks, Gate};
+use crate::mmio::{regs, Reg};
+use crate::rcc::{self, Reset};
+use crate::{into_ref, peripherals, plic, Peripheral};
+
+/// WDT interrupt number
+pub const IRQ: u16 = 74;
+
+const CR_WDT_EN: u32 = 1 << 0;
+/// Response mode, generate an interrupt first then reset
+const CR_RMOD: u32 = 1 << 1;
+/// Reset pulse length, 256 pclk cycles
+const CR_RPL_256: u32 = 0b111 << 2;
+
+const CRR_RESTART: u32 = 0x76;
+
+const MAX_TOP: u32 = 15;
+
+/// TOP_WDT_CTRL, bit 2 routes the WDT reset request to chip reset
+const TOP_WDT_CTRL: Reg = Reg::at(0x0300_0008);
+const TOP_WDT_RST_EN: u32 = 1 << 2;
+
+/// RTC_NOPOR_INFO3, survives warm reset, cleared on power loss. Not part of the
+/// scratch registers handed out by [`crate::rtc::Rtc`].
+const RESET_MARKER: Reg = Reg::at(0x0502_6038);
+const MARKER_ARMED: u32 = 0x5744_5441; // "WDTA"
+const MARKER_EXPIRED: u32 = 0x5744_5445; // "WDTE"
+
+regs! {
+    pub struct Regs {
+        /// [0] enable, [1] response mode, [4:2] reset pulse length
+        cr: 0x00,
+        /// [3:0] timeout period, [7:4] initial timeout period
+        torr: 0x04,
+        ccvr: 0x08,
+        crr: 0x0c,
+        stat: 0x10,
+        eoi: 0x14,
+    }
+}
+
+pub struct Config {
+    /// Minimum time between feeds, rounded up to the next timeout period
+    pub timeout: MillisDuration,
+    /// Raise [`IRQ`] on the first expiry instead of resetting immediately,
+    /// the interrupt is enabled in the PLIC with priority 1
+    pub pretimeout_interrupt: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timeout: MillisDuration::secs(5),
+            pretimeout_interrupt: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Timeout is longer than the largest timeout period
+    InvalidTimeout,
+}
+
+/// Why the chip came out of reset
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ResetCause {
+    /// Power on, or no watchdog was running
+    PowerOn,
+    /// Watchdog expired, and its pre-timeout handler called [`Watchdog::on_pretimeout`]
+    Watchdog,
+    /// Reset while a watchdog was running, most likely a watchdog expiry
+    ///
+    /// Without a pre-timeout handler nothing records the expiry, so a reset
+    /// button press, a software reset or a brown-out that keeps the RTC domain
+    /// powered is reported the same way.
+    WhileArmed,
+}
+
+pub struct Watchdog<'d> {
+    top: u32,
+    /// Watchdog clock when the timeout was set
+    clock: u32,
+    phantom: PhantomData<&'d mut peripherals::WDT>,
+}
+
+impl<'d> Watchdog<'d> {
+    pub fn new(_peri: impl Peripheral<P = peripherals::WDT> + 'd, config: Config) -> Result<Self, Error> {
+        into_ref!(_peri);
+
+        let clock = clocks().timer.raw();
+        let top = top_for(config.timeout, clock).ok_or(Error::InvalidTimeout)?;
+
+        // a running watchdog can only be fed, not reconfigured
+        if !Self::regs().cr().is_set(CR_WDT_EN) {
+            rcc::enable_and_reset(&[Gate::ApbWdt], Reset::Wdt);
+        }
+
+        let regs = Self::regs();
+        regs.torr().write((top << 4) | top);
+        let mut cr = CR_RPL_256;
+        if config.pretimeout_interrupt {
+            cr |= CR_RMOD;
+            plic::set_priority(IRQ, 1);
+            plic::enable(IRQ);
+        }
+        regs.cr().write(cr);
+
+        Ok(Self {
+            top,
+            clock,
+            phantom: PhantomData,
+        })
+    }
+
+    #[inline]
+    fn regs() -> Regs {
+        Regs::at(0x0301_0000)
+    }
+
+    /// Start counting down, can't be undone.
+    pub fn start(&mut self) {
+        TOP_WDT_CTRL.set_bits(TOP_WDT_RST_EN);
+        RESET_MARKER.write(MARKER_ARMED);
+
+        let regs = Self::regs();
+        regs.cr().set_bits(CR_WDT_EN);
+        regs.crr().write(CRR_RESTART);
+    }
+
+    #[inline]
+    pub fn is_running(&self) -> bool {
+        Self::regs().cr().is_set(CR_WDT_EN)
+    }
+
+    /// Restart the countdown.
+    #[inline]
+    pub fn feed(&mut self) {
+        Self::regs().crr().write(CRR_RESTART);
+    }
+
+    /// Actual timeout period
+    pub fn timeout(&self) -> MillisDuration {
+        MillisDuration::from_ticks(((1u64 << (16 + self.top)) * 1000 / self.clock as u64) as u32)
+    }
+
+    /// Remaining watchdog clock cycles before expiry
+    #[inline]
+    pub fn counter(&self) -> u32 {
+        Self::regs().ccvr().read()
+    }
+
+    /// Whether the pre-timeout interrupt is pending
+    #[inline]
+    pub fn is_pretimeout(&self) -> bool {
+        Self::regs().stat().is_set(1)
+    }
+
+    /// Acknowledge the pre-timeout interrupt and record the expiry for [`reset_cause`].
+    ///
+    /// Call this from the [`IRQ`] handler. Feed the watchdog afterwards to
+    /// cancel the pending reset.
+    pub fn on_pretimeout(&mut self) {
+        RESET_MARKER.write(MARKER_EXPIRED);
+        // read to clear
+        let _ = Self::regs().eoi().read();
+    }
+}
+
+/// Smallest TOP whose timeout period is not shorter than `timeout` at `clock` Hz
+fn top_for(timeout: MillisDuration, clock: u32) -> Option<u32> {
+    let cycles = timeout.ticks() as u64 * clock as u64 / 1000;
+    (0..=MAX_TOP).find(|top| (1u64 << (16 + top)) >= cycles)
+}
+
+/// Why the last reset happened, as recorded by [`Watchdog`].
+///
+/// Read this at boot, before starting a new watchdog.
+pub fn reset_cause() -> ResetCause {
+    match RESET_MARKER.read() {
+        MARKER_EXPIRED => ResetCause::Watchdog,
+        MARKER_ARMED => ResetCause::WhileArmed,
+        _ => ResetCause::PowerOn,
+    }
+}
+
+/// Forget the recorded reset cause, e.g. before an intentional reboot.
+pub fn clear_reset_cause() {
+    RESET_MARKER.write(0);
+}
@@ -4479,6 +4479,494 @@ impl core::fmt::Debug for PWR_GPIO {
 }
 #[doc = "PWR_GPIO"]
 pub use self::gpio0 as pwr_gpio;
+#[doc = "EFUSE"]
+pub struct EFUSE {
+    _marker: PhantomData<*const ()>,
+}
+unsafe impl Send for EFUSE {}
+impl EFUSE {
+    #[doc = r"Pointer to the register block"]
+    pub const PTR: *const efuse::RegisterBlock = 0x0305_0000 as *const _;
+    #[doc = r"Return the pointer to the register block"]
+    #[inline(always)]
+    pub const fn ptr() -> *const efuse::RegisterBlock {
+        Self::PTR
+    }
+    #[doc = r" Steal an instance of this peripheral"]
+    #[doc = r""]
+    #[doc = r" # Safety"]
+    #[doc = r""]
+    #[doc = r" Ensure that the new instance of the peripheral cannot be used in a way"]
+    #[doc = r" that may race with any existing instances, for example by only"]
+    #[doc = r" accessing read-only or write-only registers, or by consuming the"]
+    #[doc = r" original peripheral and using critical sections to coordinate"]
+    #[doc = r" access between multiple new instances."]
+    #[doc = r""]
+    #[doc = r" Additionally, other software such as HALs may rely on only one"]
+    #[doc = r" peripheral instance existing to ensure memory safety; ensure"]
+    #[doc = r" no stolen instances are passed to such software."]
+    pub unsafe fn steal() -> Self {
+        Self { _marker: PhantomData }
+    }
+}
+impl Deref for EFUSE {
+    type Target = efuse::RegisterBlock;
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*Self::PTR }
+    }
+}
+impl core::fmt::Debug for EFUSE {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("EFUSE").finish()
+    }
+}
+#[doc = "EFUSE"]
+pub mod efuse {
+    #[doc = r"Register block"]
+    #[repr(C)]
+    #[derive(Debug)]
+    pub struct RegisterBlock {
+        mode: MODE,
+        adr: ADR,
+        dir_cmd: DIR_CMD,
+        rd_data: RD_DATA,
+        status: STATUS,
+        _reserved5: [u8; 0xec],
+        shadow: [SHADOW; 64],
+    }
+    impl RegisterBlock {
+        #[doc = "0x00 - Mode register, writing a command starts it"]
+        #[inline(always)]
+        pub const fn mode(&self) -> &MODE {
+            &self.mode
+        }
+        #[doc = "0x04 - Program address"]
+        #[inline(always)]
+        pub const fn adr(&self) -> &ADR {
+            &self.adr
+        }
+        #[doc = "0x08 - Direct access command"]
+        #[inline(always)]
+        pub const fn dir_cmd(&self) -> &DIR_CMD {
+            &self.dir_cmd
+        }
+        #[doc = "0x0c - Direct access read data"]
+        #[inline(always)]
+        pub const fn rd_data(&self) -> &RD_DATA {
+            &self.rd_data
+        }
+        #[doc = "0x10 - Status register"]
+        #[inline(always)]
+        pub const fn status(&self) -> &STATUS {
+            &self.status
+        }
+        #[doc = "0x100..0x200 - Shadow of the fuse words, loaded at power on and by a refresh"]
+        #[inline(always)]
+        pub const fn shadow(&self, n: usize) -> &SHADOW {
+            &self.shadow[n]
+        }
+        #[doc = "Iterator for array of:"]
+        #[doc = "0x100..0x200 - Shadow of the fuse words, loaded at power on and by a refresh"]
+        #[inline(always)]
+        pub fn shadow_iter(&self) -> impl Iterator<Item = &SHADOW> {
+            self.shadow.iter()
+        }
+    }
+    #[doc = "MODE (rw) register accessor: Mode register, writing a command starts it\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`mode::R`].  You can [`reset`](crate::generic::Reg::reset), [`write`](crate::generic::Reg::write), [`write_with_zero`](crate::generic::Reg::write_with_zero) this register using [`mode::W`]. You can also [`modify`](crate::generic::Reg::modify) this register. See [API](https://docs.rs/svd2rust/#read--modify--write-api).\n\nFor information about available fields see [`mod@mode`]
+module"]
+    pub type MODE = crate::Reg<mode::MODE_SPEC>;
+    #[doc = "Mode register, writing a command starts it"]
+    pub mod mode {
+        #[doc = "Register `MODE` reader"]
+        pub type R = crate::R<MODE_SPEC>;
+        #[doc = "Register `MODE` writer"]
+        pub type W = crate::W<MODE_SPEC>;
+        impl core::fmt::Debug for R {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(f, "{}", self.bits())
+            }
+        }
+        impl core::fmt::Debug for crate::generic::Reg<MODE_SPEC> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::Debug::fmt(&self.read(), f)
+            }
+        }
+        impl W {
+            #[doc = r" Writes raw bits to the register."]
+            #[doc = r""]
+            #[doc = r" # Safety"]
+            #[doc = r""]
+            #[doc = r" Passing incorrect value can cause undefined behaviour. See reference manual"]
+            #[inline(always)]
+            pub unsafe fn bits(&mut self, bits: u32) -> &mut Self {
+                self.bits = bits;
+                self
+            }
+        }
+        #[doc = "Mode register, writing a command starts it\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`mode::R`](R).  You can [`reset`](crate::generic::Reg::reset), [`write`](crate::generic::Reg::write), [`write_with_zero`](crate::generic::Reg::write_with_zero) this register using [`mode::W`](W). You can also [`modify`](crate::generic::Reg::modify) this register. See [API](https://docs.rs/svd2rust/#read--modify--write-api)."]
+        pub struct MODE_SPEC;
+        impl crate::RegisterSpec for MODE_SPEC {
+            type Ux = u32;
+        }
+        #[doc = "`read()` method returns [`mode::R`](R) reader structure"]
+        impl crate::Readable for MODE_SPEC {}
+        #[doc = "`write(|w| ..)` method takes [`mode::W`](W) writer structure"]
+        impl crate::Writable for MODE_SPEC {
+            const ZERO_TO_MODIFY_FIELDS_BITMAP: u32 = 0;
+            const ONE_TO_MODIFY_FIELDS_BITMAP: u32 = 0;
+        }
+        #[doc = "`reset()` method sets MODE to value 0"]
+        impl crate::Resettable for MODE_SPEC {
+            const RESET_VALUE: u32 = 0;
+        }
+    }
+    #[doc = "ADR (rw) register accessor: Program address\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`adr::R`].  You can [`reset`](crate::generic::Reg::reset), [`write`](crate::generic::Reg::write), [`write_with_zero`](crate::generic::Reg::write_with_zero) this register using [`adr::W`]. You can also [`modify`](crate::generic::Reg::modify) this register. See [API](https://docs.rs/svd2rust/#read--modify--write-api).\n\nFor information about available fields see [`mod@adr`]
+module"]
+    pub type ADR = crate::Reg<adr::ADR_SPEC>;
+    #[doc = "Program address"]
+    pub mod adr {
+        #[doc = "Register `ADR` reader"]
+        pub type R = crate::R<ADR_SPEC>;
+        #[doc = "Register `ADR` writer"]
+        pub type W = crate::W<ADR_SPEC>;
+        #[doc = "Field `ROW` reader - Row of the redundant pair"]
+        pub type ROW_R = crate::BitReader;
+        #[doc = "Field `ROW` writer - Row of the redundant pair"]
+        pub type ROW_W<'a, REG> = crate::BitWriter<'a, REG>;
+        #[doc = "Field `WORD` reader - Word index"]
+        pub type WORD_R = crate::FieldReader;
+        #[doc = "Field `WORD` writer - Word index"]
+        pub type WORD_W<'a, REG> = crate::FieldWriter<'a, REG, 6>;
+        #[doc = "Field `BIT` reader - Bit index in the word"]
+        pub type BIT_R = crate::FieldReader;
+        #[doc = "Field `BIT` writer - Bit index in the word"]
+        pub type BIT_W<'a, REG> = crate::FieldWriter<'a, REG, 5>;
+        impl R {
+            #[doc = "Bit 0 - Row of the redundant pair"]
+            #[inline(always)]
+            pub fn row(&self) -> ROW_R {
+                ROW_R::new((self.bits & 1) != 0)
+            }
+            #[doc = "Bits 1:6 - Word index"]
+            #[inline(always)]
+            pub fn word(&self) -> WORD_R {
+                WORD_R::new(((self.bits >> 1) & 0x3f) as u8)
+            }
+            #[doc = "Bits 7:11 - Bit index in the word"]
+            #[inline(always)]
+            pub fn bit(&self) -> BIT_R {
+                BIT_R::new(((self.bits >> 7) & 0x1f) as u8)
+            }
+        }
+        impl core::fmt::Debug for R {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.debug_struct("ADR")
+                    .field("row", &format_args!("{}", self.row().bit()))
+                    .field("word", &format_args!("{}", self.word().bits()))
+                    .field("bit", &format_args!("{}", self.bit().bits()))
+                    .finish()
+            }
+        }
+        impl core::fmt::Debug for crate::generic::Reg<ADR_SPEC> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::Debug::fmt(&self.read(), f)
+            }
+        }
+        impl W {
+            #[doc = "Bit 0 - Row of the redundant pair"]
+            #[inline(always)]
+            #[must_use]
+            pub fn row(&mut self) -> ROW_W<ADR_SPEC> {
+                ROW_W::new(self, 0)
+            }
+            #[doc = "Bits 1:6 - Word index"]
+            #[inline(always)]
+            #[must_use]
+            pub fn word(&mut self) -> WORD_W<ADR_SPEC> {
+                WORD_W::new(self, 1)
+            }
+            #[doc = "Bits 7:11 - Bit index in the word"]
+            #[inline(always)]
+            #[must_use]
+            pub fn bit(&mut self) -> BIT_W<ADR_SPEC> {
+                BIT_W::new(self, 7)
+            }
+            #[doc = r" Writes raw bits to the register."]
+            #[doc = r""]
+            #[doc = r" # Safety"]
+            #[doc = r""]
+            #[doc = r" Passing incorrect value can cause undefined behaviour. See reference manual"]
+            #[inline(always)]
+            pub unsafe fn bits(&mut self, bits: u32) -> &mut Self {
+                self.bits = bits;
+                self
+            }
+        }
+        #[doc = "Program address\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`adr::R`](R).  You can [`reset`](crate::generic::Reg::reset), [`write`](crate::generic::Reg::write), [`write_with_zero`](crate::generic::Reg::write_with_zero) this register using [`adr::W`](W). You can also [`modify`](crate::generic::Reg::modify) this register. See [API](https://docs.rs/svd2rust/#read--modify--write-api)."]
+        pub struct ADR_SPEC;
+        impl crate::RegisterSpec for ADR_SPEC {
+            type Ux = u32;
+        }
+        #[doc = "`read()` method returns [`adr::R`](R) reader structure"]
+        impl crate::Readable for ADR_SPEC {}
+        #[doc = "`write(|w| ..)` method takes [`adr::W`](W) writer structure"]
+        impl crate::Writable for ADR_SPEC {
+            const ZERO_TO_MODIFY_FIELDS_BITMAP: u32 = 0;
+            const ONE_TO_MODIFY_FIELDS_BITMAP: u32 = 0;
+        }
+        #[doc = "`reset()` method sets ADR to value 0"]
+        impl crate::Resettable for ADR_SPEC {
+            const RESET_VALUE: u32 = 0;
+        }
+    }
+    #[doc = "DIR_CMD (rw) register accessor: Direct access command\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`dir_cmd::R`].  You can [`reset`](crate::generic::Reg::reset), [`write`](crate::generic::Reg::write), [`write_with_zero`](crate::generic::Reg::write_with_zero) this register using [`dir_cmd::W`]. You can also [`modify`](crate::generic::Reg::modify) this register. See [API](https://docs.rs/svd2rust/#read--modify--write-api).\n\nFor information about available fields see [`mod@dir_cmd`]
+module"]
+    pub type DIR_CMD = crate::Reg<dir_cmd::DIR_CMD_SPEC>;
+    #[doc = "Direct access command"]
+    pub mod dir_cmd {
+        #[doc = "Register `DIR_CMD` reader"]
+        pub type R = crate::R<DIR_CMD_SPEC>;
+        #[doc = "Register `DIR_CMD` writer"]
+        pub type W = crate::W<DIR_CMD_SPEC>;
+        impl core::fmt::Debug for R {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(f, "{}", self.bits())
+            }
+        }
+        impl core::fmt::Debug for crate::generic::Reg<DIR_CMD_SPEC> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::Debug::fmt(&self.read(), f)
+            }
+        }
+        impl W {
+            #[doc = r" Writes raw bits to the register."]
+            #[doc = r""]
+            #[doc = r" # Safety"]
+            #[doc = r""]
+            #[doc = r" Passing incorrect value can cause undefined behaviour. See reference manual"]
+            #[inline(always)]
+            pub unsafe fn bits(&mut self, bits: u32) -> &mut Self {
+                self.bits = bits;
+                self
+            }
+        }
+        #[doc = "Direct access command\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`dir_cmd::R`](R).  You can [`reset`](crate::generic::Reg::reset), [`write`](crate::generic::Reg::write), [`write_with_zero`](crate::generic::Reg::write_with_zero) this register using [`dir_cmd::W`](W). You can also [`modify`](crate::generic::Reg::modify) this register. See [API](https://docs.rs/svd2rust/#read--modify--write-api)."]
+        pub struct DIR_CMD_SPEC;
+        impl crate::RegisterSpec for DIR_CMD_SPEC {
+            type Ux = u32;
+        }
+        #[doc = "`read()` method returns [`dir_cmd::R`](R) reader structure"]
+        impl crate::Readable for DIR_CMD_SPEC {}
+        #[doc = "`write(|w| ..)` method takes [`dir_cmd::W`](W) writer structure"]
+        impl crate::Writable for DIR_CMD_SPEC {
+            const ZERO_TO_MODIFY_FIELDS_BITMAP: u32 = 0;
+            const ONE_TO_MODIFY_FIELDS_BITMAP: u32 = 0;
+        }
+        #[doc = "`reset()` method sets DIR_CMD to value 0"]
+        impl crate::Resettable for DIR_CMD_SPEC {
+            const RESET_VALUE: u32 = 0;
+        }
+    }
+    #[doc = "RD_DATA (r) register accessor: Direct access read data\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`rd_data::R`].  See [API](https://docs.rs/svd2rust/#read--modify--write-api).\n\nFor information about available fields see [`mod@rd_data`]
+module"]
+    pub type RD_DATA = crate::Reg<rd_data::RD_DATA_SPEC>;
+    #[doc = "Direct access read data"]
+    pub mod rd_data {
+        #[doc = "Register `RD_DATA` reader"]
+        pub type R = crate::R<RD_DATA_SPEC>;
+        impl core::fmt::Debug for R {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(f, "{}", self.bits())
+            }
+        }
+        impl core::fmt::Debug for crate::generic::Reg<RD_DATA_SPEC> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::Debug::fmt(&self.read(), f)
+            }
+        }
+        #[doc = "Direct access read data\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`rd_data::R`](R).  See [API](https://docs.rs/svd2rust/#read--modify--write-api)."]
+        pub struct RD_DATA_SPEC;
+        impl crate::RegisterSpec for RD_DATA_SPEC {
+            type Ux = u32;
+        }
+        #[doc = "`read()` method returns [`rd_data::R`](R) reader structure"]
+        impl crate::Readable for RD_DATA_SPEC {}
+        #[doc = "`reset()` method sets RD_DATA to value 0"]
+        impl crate::Resettable for RD_DATA_SPEC {
+            const RESET_VALUE: u32 = 0;
+        }
+    }
+    #[doc = "STATUS (r) register accessor: Status register\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`status::R`].  See [API](https://docs.rs/svd2rust/#read--modify--write-api).\n\nFor information about available fields see [`mod@status`]
+module"]
+    pub type STATUS = crate::Reg<status::STATUS_SPEC>;
+    #[doc = "Status register"]
+    pub mod status {
+        #[doc = "Register `STATUS` reader"]
+        pub type R = crate::R<STATUS_SPEC>;
+        #[doc = "Field `BUSY` reader - Command in progress"]
+        pub type BUSY_R = crate::BitReader;
+        impl R {
+            #[doc = "Bit 0 - Command in progress"]
+            #[inline(always)]
+            pub fn busy(&self) -> BUSY_R {
+                BUSY_R::new((self.bits & 1) != 0)
+            }
+        }
+        impl core::fmt::Debug for R {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.debug_struct("STATUS")
+                    .field("busy", &format_args!("{}", self.busy().bit()))
+                    .finish()
+            }
+        }
+        impl core::fmt::Debug for crate::generic::Reg<STATUS_SPEC> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::Debug::fmt(&self.read(), f)
+            }
+        }
+        #[doc = "Status register\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`status::R`](R).  See [API](https://docs.rs/svd2rust/#read--modify--write-api)."]
+        pub struct STATUS_SPEC;
+        impl crate::RegisterSpec for STATUS_SPEC {
+            type Ux = u32;
+        }
+        #[doc = "`read()` method returns [`status::R`](R) reader structure"]
+        impl crate::Readable for STATUS_SPEC {}
+        #[doc = "`reset()` method sets STATUS to value 0"]
+        impl crate::Resettable for STATUS_SPEC {
+            const RESET_VALUE: u32 = 0;
+        }
+    }
+    #[doc = "SHADOW (r) register accessor: Shadow of the fuse words, loaded at power on and by a refresh\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`shadow::R`].  See [API](https://docs.rs/svd2rust/#read--modify--write-api).\n\nFor information about available fields see [`mod@shadow`]
+module"]
+    pub type SHADOW = crate::Reg<shadow::SHADOW_SPEC>;
+    #[doc = "Shadow of the fuse words, loaded at power on and by a refresh"]
+    pub mod shadow {
+        #[doc = "Register `SHADOW` reader"]
+        pub type R = crate::R<SHADOW_SPEC>;
+        impl core::fmt::Debug for R {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(f, "{}", self.bits())
+            }
+        }
+        impl core::fmt::Debug for crate::generic::Reg<SHADOW_SPEC> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::Debug::fmt(&self.read(), f)
+            }
+        }
+        #[doc = "Shadow of the fuse words, loaded at power on and by a refresh\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`shadow::R`](R).  See [API](https://docs.rs/svd2rust/#read--modify--write-api)."]
+        pub struct SHADOW_SPEC;
+        impl crate::RegisterSpec for SHADOW_SPEC {
+            type Ux = u32;
+        }
+        #[doc = "`read()` method returns [`shadow::R`](R) reader structure"]
+        impl crate::Readable for SHADOW_SPEC {}
+        #[doc = "`reset()` method sets SHADOW to value 0"]
+        impl crate::Resettable for SHADOW_SPEC {
+            const RESET_VALUE: u32 = 0;
+        }
+    }
+}
+#[doc = "TOP"]
+pub struct TOP {
+    _marker: PhantomData<*const ()>,
+}
+unsafe impl Send for TOP {}
+impl TOP {
+    #[doc = r"Pointer to the register block"]
+    pub const PTR: *const top::RegisterBlock = 0x0300_0000 as *const _;
+    #[doc = r"Return the pointer to the register block"]
+    #[inline(always)]
+    pub const fn ptr() -> *const top::RegisterBlock {
+        Self::PTR
+    }
+    #[doc = r" Steal an instance of this peripheral"]
+    #[doc = r""]
+    #[doc = r" # Safety"]
+    #[doc = r""]
+    #[doc = r" Ensure that the new instance of the peripheral cannot be used in a way"]
+    #[doc = r" that may race with any existing instances, for example by only"]
+    #[doc = r" accessing read-only or write-only registers, or by consuming the"]
+    #[doc = r" original peripheral and using critical sections to coordinate"]
+    #[doc = r" access between multiple new instances."]
+    #[doc = r""]
+    #[doc = r" Additionally, other software such as HALs may rely on only one"]
+    #[doc = r" peripheral instance existing to ensure memory safety; ensure"]
+    #[doc = r" no stolen instances are passed to such software."]
+    pub unsafe fn steal() -> Self {
+        Self { _marker: PhantomData }
+    }
+}
+impl Deref for TOP {
+    type Target = top::RegisterBlock;
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*Self::PTR }
+    }
+}
+impl core::fmt::Debug for TOP {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("TOP").finish()
+    }
+}
+#[doc = "TOP"]
+pub mod top {
+    #[doc = r"Register block"]
+    #[repr(C)]
+    #[derive(Debug)]
+    pub struct RegisterBlock {
+        _reserved0: [u8; 0x04],
+        conf_info: CONF_INFO,
+    }
+    impl RegisterBlock {
+        #[doc = "0x04 - Chip configuration latched at reset"]
+        #[inline(always)]
+        pub const fn conf_info(&self) -> &CONF_INFO {
+            &self.conf_info
+        }
+    }
+    #[doc = "CONF_INFO (r) register accessor: Chip configuration latched at reset\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`conf_info::R`].  See [API](https://docs.rs/svd2rust/#read--modify--write-api).\n\nFor information about available fields see [`mod@conf_info`]
+module"]
+    pub type CONF_INFO = crate::Reg<conf_info::CONF_INFO_SPEC>;
+    #[doc = "Chip configuration latched at reset"]
+    pub mod conf_info {
+        #[doc = "Register `CONF_INFO` reader"]
+        pub type R = crate::R<CONF_INFO_SPEC>;
+        #[doc = "Field `PKG_TYPE` reader - Package and SiP DDR, read from the package strap pads"]
+        pub type PKG_TYPE_R = crate::FieldReader;
+        impl R {
+            #[doc = "Bits 28:30 - Package and SiP DDR, read from the package strap pads"]
+            #[inline(always)]
+            pub fn pkg_type(&self) -> PKG_TYPE_R {
+                PKG_TYPE_R::new(((self.bits >> 28) & 7) as u8)
+            }
+        }
+        impl core::fmt::Debug for R {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.debug_struct("CONF_INFO")
+                    .field("pkg_type", &format_args!("{}", self.pkg_type().bits()))
+                    .finish()
+            }
+        }
+        impl core::fmt::Debug for crate::generic::Reg<CONF_INFO_SPEC> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                core::fmt::Debug::fmt(&self.read(), f)
+            }
+        }
+        #[doc = "Chip configuration latched at reset\n\nYou can [`read`](crate::generic::Reg::read) this register and get [`conf_info::R`](R).  See [API](https://docs.rs/svd2rust/#read--modify--write-api)."]
+        pub struct CONF_INFO_SPEC;
+        impl crate::RegisterSpec for CONF_INFO_SPEC {
+            type Ux = u32;
+        }
+        #[doc = "`read()` method returns [`conf_info::R`](R) reader structure"]
+        impl crate::Readable for CONF_INFO_SPEC {}
+        #[doc = "`reset()` method sets CONF_INFO to value 0"]
+        impl crate::Resettable for CONF_INFO_SPEC {
+            const RESET_VALUE: u32 = 0;
+        }
+    }
+}
+
 #[no_mangle]
 static mut DEVICE_PERIPHERALS: bool = false;
 #[doc = r" All the peripherals."]
@@ -4518,6 +5006,10 @@ pub struct Peripherals {
     pub GPIO3: GPIO3,
     #[doc = "PWR_GPIO"]
     pub PWR_GPIO: PWR_GPIO,
+    #[doc = "EFUSE"]
+    pub EFUSE: EFUSE,
+    #[doc = "TOP"]
+    pub TOP: TOP,
 }
 impl Peripherals {
     #[doc = r" Returns all the peripherals *once*."]
@@ -4557,6 +5049,8 @@ impl Peripherals {
             GPIO2: GPIO2 { _marker: PhantomData },
             GPIO3: GPIO3 { _marker: PhantomData },
             PWR_GPIO: PWR_GPIO { _marker: PhantomData },
+            EFUSE: EFUSE { _marker: PhantomData },
+            TOP: TOP { _marker: PhantomData },
         }
     }
 }
@@ -2,11 +2,21 @@
 #![feature(naked_functions, asm_const)]
 
 use core::arch::asm;
+/// Start of DRAM
+pub const DRAM_BASE: usize = 0x8000_0000;
+
 const LEN_STACK: usize = 1 * 1024 * 1024;
-const STACK_START: usize = 0x80000000 + 240 * 1024 * 1024;
+// below the C906L image, vendor memory map
+#[cfg(feature = "board-duo")]
+const STACK_START: usize = DRAM_BASE + 63 * 1024 * 1024;
+#[cfg(not(feature = "board-duo"))]
+const STACK_START: usize = DRAM_BASE + 240 * 1024 * 1024;
 
 pub use riscv_rt_macros::entry;
 
+#[cfg(feature = "mmu")]
+pub mod mmu;
+
 #[repr(align(16))]
 pub struct Stack<const N: usize>([u8; N]);
 
@@ -33,7 +43,7 @@ unsafe extern "C" fn entry() -> ! {
         // configure mxstatus register
         // PM = 0b11 (Current privilege mode is Machine mode)
         // THEADISAEE = 1 (Enable T-Head ISA)
-        // MAEE = 1 (Enable extended MMU attributes)
+        // MAEE = 1 (Enable extended MMU attributes, see `mmu`)
         // MHRD = 0 (Disable TLB hardware refill)
         // CLINTEE = 1 (CLINT usoft and utimer can be responded)
         // UCME = 1 (Enable extended cache instructions on U-mode)
@@ -48,6 +58,7 @@ unsafe extern "C" fn entry() -> ! {
         //"   li      t2, 0x30013
         //    csrw    0x7c2, t2",
         // enable I-cache, D-cache by mhcr register
+        // (M-mode only, OpenSBI already did this, maintenance lives in `hal::cache`)
         //"   csrsi   0x7c1, 0x3",
         // load stack address
         "   la      sp, {stack}
@@ -0,0 +1,282 @@
+//! Sv39 page tables with T-Head extended page attributes
+//!
+//! Without translation every access uses the default memory attributes, so
+//! device registers may be cached or merged. With MXSTATUS.MAEE set, which
+//! OpenSBI does on the C906, PTE bits [63:59] pick the memory type instead:
+//!
+//! ```text
+//! 63 SO  strong order, device memory
+//! 62 C   cacheable
+//! 61 B   bufferable
+//! 60 SH  shareable
+//! 59 SEC trustable
+//! ```
+//!
+//! [`PageTables`] builds an identity map from a fixed pool of tables, mapping
+//! with the largest pages (1GiB, 2MiB, 4KiB) alignment allows, then
+//! [`PageTables::activate`] turns translation on.
+//!
+//! ```ignore
+//! static mut TABLES: PageTables<4> = PageTables::new();
+//!
+//! let tables = unsafe { &mut *core::ptr::addr_of_mut!(TABLES) };
+//! tables.map_defaults(DRAM_SIZE)?;
+//! tables.map(FRAMEBUFFER, FRAMEBUFFER, FRAMEBUFFER_SIZE, Attributes::new(MemoryType::NonCacheable, Access::ReadWrite))?;
+//! unsafe { tables.activate() };
+//! ```
+
+use core::arch::asm;
+
+pub use crate::DRAM_BASE;
+
+pub const PAGE_SIZE: usize = 4096;
+/// Peripherals, ROM and SRAM below DRAM, 1GiB
+pub const DEVICE_BASE: usize = 0x0000_0000;
+pub const DEVICE_SIZE: usize = 0x4000_0000;
+
+const ENTRIES: usize = 512;
+const LEVELS: usize = 3;
+/// Identity mapped addresses must be canonical with bit 38 clear
+const VA_LIMIT: usize = 1 << 38;
+
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_G: u64 = 1 << 5;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+const PTE_PPN_OFFSET: u32 = 10;
+const PTE_PPN_MASK: u64 = ((1 << 44) - 1) << PTE_PPN_OFFSET;
+
+const PTE_SO: u64 = 1 << 63;
+const PTE_C: u64 = 1 << 62;
+const PTE_B: u64 = 1 << 61;
+const PTE_SH: u64 = 1 << 60;
+
+const SATP_MODE_SV39: usize = 8 << 60;
+
+/// Memory type, T-Head extended attributes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    /// Cacheable, bufferable: DRAM
+    Normal,
+    /// Bufferable but not cached: DMA buffers, frame buffers
+    NonCacheable,
+    /// Strongly ordered: peripheral registers
+    Device,
+}
+
+impl MemoryType {
+    const fn bits(self) -> u64 {
+        match self {
+            MemoryType::Normal => PTE_C | PTE_B | PTE_SH,
+            MemoryType::NonCacheable => PTE_B | PTE_SH,
+            MemoryType::Device => PTE_SO | PTE_SH,
+        }
+    }
+}
+
+/// Supervisor access rights
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+    ReadExecute,
+    ReadWriteExecute,
+}
+
+impl Access {
+    const fn bits(self) -> u64 {
+        match self {
+            Access::ReadOnly => PTE_R,
+            Access::ReadWrite => PTE_R | PTE_W,
+            Access::ReadExecute => PTE_R | PTE_X,
+            Access::ReadWriteExecute => PTE_R | PTE_W | PTE_X,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attributes {
+    pub memory: MemoryType,
+    pub access: Access,
+}
+
+impl Attributes {
+    pub const fn new(memory: MemoryType, access: Access) -> Self {
+        Self { memory, access }
+    }
+
+    /// Leaf PTE flags, A and D preset so no access faults are taken
+    const fn bits(self) -> u64 {
+        PTE_V | PTE_G | PTE_A | PTE_D | self.access.bits() | self.memory.bits()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Addresses or size are not page aligned
+    Unaligned,
+    /// Address above what an identity map can reach
+    OutOfRange,
+    /// Part of the range is mapped already
+    AlreadyMapped,
+    /// The table pool is used up
+    OutOfTables,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, align(4096))]
+struct Table([u64; ENTRIES]);
+
+/// Sv39 root table plus a pool of `N` tables for 2MiB and 4KiB mappings
+///
+/// While building, pointers to pool tables hold the pool index, they become
+/// physical addresses in [`PageTables::activate`] once the tables can't move.
+pub struct PageTables<const N: usize> {
+    root: Table,
+    pool: [Table; N],
+    used: usize,
+}
+
+impl<const N: usize> PageTables<N> {
+    pub const fn new() -> Self {
+        Self {
+            root: Table([0; ENTRIES]),
+            pool: [Table([0; ENTRIES]); N],
+            used: 0,
+        }
+    }
+
+    /// Identity map peripherals as [`MemoryType::Device`] and `dram_size` bytes of DRAM as
+    /// [`MemoryType::Normal`], needs one pool table when DRAM is smaller than 1GiB.
+    pub fn map_defaults(&mut self, dram_size: usize) -> Result<(), Error> {
+        self.map(
+            DEVICE_BASE,
+            DEVICE_BASE,
+            DEVICE_SIZE,
+            Attributes::new(MemoryType::Device, Access::ReadWrite),
+        )?;
+        self.map(
+            DRAM_BASE,
+            DRAM_BASE,
+            dram_size,
+            Attributes::new(MemoryType::Normal, Access::ReadWriteExecute),
+        )
+    }
+
+    /// Map `size` bytes at virtual `va` to physical `pa`
+    pub fn map(&mut self, va: usize, pa: usize, size: usize, attributes: Attributes) -> Result<(), Error> {
+        if !(va | pa | size).is_multiple_of(PAGE_SIZE) {
+            return Err(Error::Unaligned);
+        }
+        if va.checked_add(size).is_none_or(|end| end > VA_LIMIT) {
+            return Err(Error::OutOfRange);
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            let (va, pa) = (va + offset, pa + offset);
+            let level = (0..LEVELS)
+                .rev()
+                .find(|&level| {
+                    let block = page_size(level);
+                    va % block == 0 && pa % block == 0 && size - offset >= block
+                })
+                .unwrap_or(0);
+            self.set_leaf(va, pa, level, attributes)?;
+            offset += page_size(level);
+        }
+        Ok(())
+    }
+
+    fn set_leaf(&mut self, va: usize, pa: usize, level: usize, attributes: Attributes) -> Result<(), Error> {
+        // None is the root table, Some(i) pool table i
+        let mut table = None;
+        for walk in (level + 1..LEVELS).rev() {
+            let index = vpn(va, walk);
+            let entry = self.table(table).0[index];
+            table = Some(if entry & PTE_V == 0 {
+                if self.used == N {
+                    return Err(Error::OutOfTables);
+                }
+                let next = self.used;
+                self.used += 1;
+                self.table(table).0[index] = ((next as u64) << PTE_PPN_OFFSET) | PTE_V;
+                next
+            } else if is_leaf(entry) {
+                return Err(Error::AlreadyMapped);
+            } else {
+                ((entry & PTE_PPN_MASK) >> PTE_PPN_OFFSET) as usize
+            });
+        }
+
+        let entry = &mut self.table(table).0[vpn(va, level)];
+        if *entry & PTE_V != 0 {
+            return Err(Error::AlreadyMapped);
+        }
+        *entry = (((pa / PAGE_SIZE) as u64) << PTE_PPN_OFFSET) | attributes.bits();
+        Ok(())
+    }
+
+    #[inline]
+    fn table(&mut self, table: Option<usize>) -> &mut Table {
+        match table {
+            Some(i) => &mut self.pool[i],
+            None => &mut self.root,
+        }
+    }
+
+    /// Turn on Sv39 translation with these tables.
+    ///
+    /// # Safety
+    ///
+    /// The code, stack and data in use must be mapped, identity mapped for
+    /// anything that holds pointers. Tables must not change afterwards.
+    pub unsafe fn activate(&'static mut self) {
+        let pool = self.pool.as_ptr() as u64;
+        let used = self.used;
+        for table in core::iter::once(&mut self.root).chain(self.pool[..used].iter_mut()) {
+            for entry in table.0.iter_mut() {
+                if *entry & PTE_V != 0 && !is_leaf(*entry) {
+                    let index = (*entry & PTE_PPN_MASK) >> PTE_PPN_OFFSET;
+                    let addr = pool + index * PAGE_SIZE as u64;
+                    *entry = ((addr / PAGE_SIZE as u64) << PTE_PPN_OFFSET) | PTE_V;
+                }
+            }
+        }
+
+        let satp = SATP_MODE_SV39 | (&self.root as *const Table as usize / PAGE_SIZE);
+        asm!(
+            // th.dcache.call, th.sync.s: tables must be in memory for the walker
+            ".long 0x0010000b",
+            ".long 0x0190000b",
+            "csrw satp, {satp}",
+            "sfence.vma",
+            satp = in(reg) satp,
+        );
+    }
+}
+
+impl<const N: usize> Default for PageTables<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bytes mapped by one entry at `level`, 4KiB, 2MiB or 1GiB
+#[inline]
+const fn page_size(level: usize) -> usize {
+    PAGE_SIZE << (9 * level)
+}
+
+#[inline]
+const fn vpn(va: usize, level: usize) -> usize {
+    (va >> (12 + 9 * level)) % ENTRIES
+}
+
+#[inline]
+const fn is_leaf(entry: u64) -> bool {
+    entry & (PTE_R | PTE_W | PTE_X) != 0
+}